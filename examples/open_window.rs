@@ -1,21 +1,23 @@
+use std::any::Any;
 use std::time::Duration;
 
-use rtrb::{Consumer, RingBuffer};
-
-use baseview::{Event, EventStatus, Window, WindowHandler, WindowScalePolicy};
+use baseview::{
+    Event, EventStatus, FrameRate, Window, WindowEvent, WindowHandler, WindowPosition,
+    WindowScalePolicy,
+};
 
 #[derive(Debug, Clone)]
 enum Message {
     Hello,
 }
 
-struct OpenWindowExample {
-    rx: Consumer<Message>,
-}
+struct OpenWindowExample;
 
 impl WindowHandler for OpenWindowExample {
-    fn on_frame(&mut self, _window: &mut Window) {
-        while let Ok(message) = self.rx.pop() {
+    fn on_frame(&mut self, _window: &mut Window) {}
+
+    fn on_message(&mut self, _window: &mut Window, message: Box<dyn Any + Send>) {
+        if let Some(message) = message.downcast_ref::<Message>() {
             println!("Message: {:?}", message);
         }
     }
@@ -24,7 +26,16 @@ impl WindowHandler for OpenWindowExample {
         match event {
             Event::Mouse(e) => println!("Mouse event: {:?}", e),
             Event::Keyboard(e) => println!("Keyboard event: {:?}", e),
-            Event::Window(e) => println!("Window event: {:?}", e),
+            Event::Window(e) => {
+                println!("Window event: {:?}", e);
+
+                // Let the window close when the user clicks the close button
+                if let WindowEvent::CloseRequested = e {
+                    return EventStatus::Ignored;
+                }
+            }
+            Event::DragDrop(e) => println!("Drag and drop event: {:?}", e),
+            Event::Ime(e) => println!("IME event: {:?}", e),
         }
 
         EventStatus::Captured
@@ -35,22 +46,30 @@ fn main() {
     let window_open_options = baseview::WindowOpenOptions {
         title: "baseview".into(),
         size: baseview::Size::new(512.0, 512.0),
+        position: WindowPosition::CenteredOnScreen,
+        min_size: Some(baseview::Size::new(256.0, 256.0)),
+        max_size: None,
+        resizable: true,
+        aspect_ratio: None,
         scale: WindowScalePolicy::SystemScaleFactor,
+        frame_rate: FrameRate::MonitorRefreshRate,
 
         // TODO: Add an example that uses the OpenGL context
         #[cfg(feature = "opengl")]
         gl_config: None,
     };
 
-    let (mut tx, rx) = RingBuffer::new(128);
+    let result = Window::open_blocking(window_open_options, |window| {
+        let proxy = window.proxy();
+        ::std::thread::spawn(move || loop {
+            ::std::thread::sleep(Duration::from_secs(5));
 
-    ::std::thread::spawn(move || loop {
-        ::std::thread::sleep(Duration::from_secs(5));
+            proxy.post(Message::Hello);
+        });
 
-        if let Err(_) = tx.push(Message::Hello) {
-            println!("Failed sending message");
-        }
+        OpenWindowExample
     });
-
-    Window::open_blocking(window_open_options, |_| OpenWindowExample { rx });
+    if let Err(err) = result {
+        eprintln!("Could not open window: {}", err);
+    }
 }
@@ -0,0 +1,14 @@
+/// The clipboard to read from or write to
+///
+/// # TODO
+///
+/// The clipboard can currently only be accessed on Linux.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClipboardKind {
+    /// The regular clipboard used for copy and paste
+    #[default]
+    Clipboard,
+    /// The primary selection, which contains the most recently selected text and is pasted with
+    /// the middle mouse button. This only exists on Linux.
+    Primary,
+}
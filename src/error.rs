@@ -0,0 +1,40 @@
+use std::fmt;
+
+/// The errors that can occur while opening a window
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// Could not connect to the windowing system, e.g. because `DISPLAY` is not set or the X
+    /// server could not be reached.
+    ConnectionFailed(String),
+
+    /// The windowing system refused to create the window, or the parent window handle is not
+    /// supported on this platform.
+    WindowCreationFailed(String),
+
+    /// No framebuffer configuration or OpenGL context matching the requested
+    /// [`GlConfig`](crate::gl::GlConfig) could be created.
+    #[cfg(feature = "opengl")]
+    GlConfigFailed(String),
+
+    /// The `build` closure passed to one of the `Window::open_*` functions panicked, or the
+    /// window handler panicked before the window was fully opened.
+    BuildPanicked,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ConnectionFailed(reason) => {
+                write!(f, "could not connect to the windowing system: {}", reason)
+            }
+            Error::WindowCreationFailed(reason) => write!(f, "could not create window: {}", reason),
+            #[cfg(feature = "opengl")]
+            Error::GlConfigFailed(reason) => {
+                write!(f, "could not create OpenGL context: {}", reason)
+            }
+            Error::BuildPanicked => write!(f, "the window handler's build closure panicked"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -1,6 +1,8 @@
+use std::path::PathBuf;
+
 use keyboard_types::{KeyboardEvent, Modifiers};
 
-use crate::{Point, WindowInfo};
+use crate::{PhyRect, Point, WindowInfo};
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum MouseButton {
@@ -32,6 +34,19 @@ pub enum ScrollDelta {
     },
 }
 
+/// Where a scroll movement is in a scroll gesture, e.g. on a touchpad. Kinetic scrolling can be
+/// started when a gesture ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScrollPhase {
+    /// The user started a scroll gesture
+    Started,
+    /// The user is scrolling. This is also used for devices without gestures, such as most mouse
+    /// wheels, and for the system's own kinetic scrolling on macOS.
+    Moved,
+    /// The user stopped scrolling
+    Ended,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MouseEvent {
     /// The mouse cursor was moved
@@ -62,10 +77,31 @@ pub enum MouseEvent {
     WheelScrolled {
         /// How much was scrolled, in factional lines.
         delta: ScrollDelta,
+        /// Where this movement is in a scroll gesture.
+        ///
+        /// X11 doesn't report scroll gestures, so on Linux a touchpad's gesture is considered to
+        /// have ended when no more scrolling movements are received for a short while. Mouse
+        /// wheels always use [`ScrollPhase::Moved`] there.
+        phase: ScrollPhase,
         /// The modifiers that were held down just before the event.
         modifiers: Modifiers,
     },
 
+    /// The pointer moved while it was locked through
+    /// [`Window::lock_pointer`](crate::Window::lock_pointer). The position of the cursor doesn't
+    /// change while the pointer is locked, so [`CursorMoved`](Self::CursorMoved) is not sent
+    /// during that time.
+    ///
+    /// The movement is the distance in physical pixels the cursor would have moved, including
+    /// pointer acceleration. On Linux, devices with absolute positions such as graphics tablets
+    /// report it in their own units instead.
+    RawMotion {
+        /// The horizontal movement
+        dx: f64,
+        /// The vertical movement
+        dy: f64,
+    },
+
     /// The mouse cursor entered the window.
     ///
     /// May not be available on all platforms.
@@ -80,16 +116,144 @@ pub enum MouseEvent {
 #[derive(Debug, Clone)]
 pub enum WindowEvent {
     Resized(WindowInfo),
+    /// The window was moved. This contains the new logical position of the window's top left
+    /// corner, relative to the parent window for parented windows and relative to the screen
+    /// otherwise.
+    Moved(Point),
     Focused,
     Unfocused,
+    /// The user or the window manager asked to close the window, e.g. through the window's close
+    /// button. Returning [`EventStatus::Captured`] keeps the window open, otherwise the window
+    /// will be closed and [`WindowEvent::WillClose`] follows.
+    ///
+    /// This is not sent when the window is closed through [`Window::close`](crate::Window::close)
+    /// or by dropping its parent's [`WindowHandle`](crate::WindowHandle).
+    ///
+    /// # TODO
+    ///
+    /// This is currently never sent on macOS, where windows are always closed.
+    CloseRequested,
+    /// Parts of the window's contents were lost, e.g. because the window was uncovered, and need
+    /// to be redrawn. All damage that happened since the last time the window's events were
+    /// processed is combined into a single event.
+    ///
+    /// # TODO
+    ///
+    /// This is currently only sent on Linux.
+    Damaged(Vec<PhyRect>),
+    /// The window is about to close. This is always the last event the window receives, and it
+    /// cannot be cancelled.
     WillClose,
 }
 
+/// The data being dragged in a drag and drop operation
+#[derive(Debug, Clone, PartialEq)]
+pub enum DragData {
+    /// One or more files
+    Files(Vec<PathBuf>),
+    /// A piece of text
+    Text(String),
+}
+
+/// The operation a drag and drop operation performs with the dragged data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropAction {
+    /// The data is copied to the target
+    Copy,
+    /// The data is moved to the target, and the source should remove its copy
+    Move,
+    /// The target creates a link or reference to the data
+    Link,
+}
+
+/// A drag and drop operation.
+///
+/// All variants except for [`DragEnded`](Self::DragEnded) describe something being dragged onto
+/// the window from another window or application. Returning [`EventStatus::Captured`] from
+/// [`Entered`](Self::Entered) or [`Moved`](Self::Moved) tells the source that the data can be
+/// dropped at that position with the requested action, returning [`EventStatus::Ignored`] rejects
+/// the drop. Sources that ask for an action other than the ones in [`DropAction`] are told that
+/// the data will be copied.
+///
+/// # TODO
+///
+/// This is currently only supported on Linux.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DragDropEvent {
+    /// The data was dragged onto the window.
+    Entered {
+        /// The logical coordinates of the mouse position
+        position: Point,
+        /// The data being dragged
+        data: DragData,
+        /// The action the source asked for. This is the action that's performed if the data is
+        /// accepted.
+        action: DropAction,
+    },
+
+    /// The data was moved while over the window.
+    Moved {
+        /// The logical coordinates of the mouse position
+        position: Point,
+        /// The data being dragged
+        data: DragData,
+        /// The action the source asked for. This can change while the data is being dragged,
+        /// e.g. when the user holds down a modifier key.
+        action: DropAction,
+    },
+
+    /// The data was dragged away from the window, the drag was cancelled, or the data was dropped
+    /// after it was rejected.
+    Left,
+
+    /// The data was dropped onto the window after it was accepted.
+    Dropped {
+        /// The logical coordinates of the mouse position
+        position: Point,
+        /// The data that was dropped
+        data: DragData,
+        /// The action to perform with the data
+        action: DropAction,
+    },
+
+    /// A drag started with [`Window::start_drag`](crate::Window::start_drag) has ended.
+    DragEnded {
+        /// The action the drop target performed, or `None` if the drag was cancelled or the
+        /// data was rejected.
+        action: Option<DropAction>,
+    },
+}
+
+/// Text input through an input method editor (IME). These are only sent after IME input has been
+/// enabled with [`Window::set_ime_allowed`](crate::Window::set_ime_allowed). Key presses consumed
+/// by the input method are not sent as [`Event::Keyboard`] events.
+///
+/// # TODO
+///
+/// This is currently only supported on Linux.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Ime {
+    /// The text that's currently being composed changed. This should be displayed at the text
+    /// cursor, but it isn't part of the text yet. An empty string means that composition has
+    /// ended.
+    Preedit {
+        /// The text being composed
+        text: String,
+        /// The byte offset of the cursor within `text`, or `None` if the cursor should be hidden
+        cursor: Option<usize>,
+    },
+
+    /// Composition has finished and `String` should be inserted at the text cursor.
+    Commit(String),
+}
+
 #[derive(Debug, Clone)]
 pub enum Event {
     Mouse(MouseEvent),
     Keyboard(KeyboardEvent),
     Window(WindowEvent),
+    DragDrop(DragDropEvent),
+    Ime(Ime),
 }
 
 /// Return value for [WindowHandler::on_event](`crate::WindowHandler::on_event()`),
@@ -98,8 +262,8 @@ pub enum Event {
 ///
 /// For most event types, this value won't have any effect. This is the case
 /// when there is no clear meaning of passing back the event to the platform,
-/// or it isn't obviously useful. Currently, only [`Event::Keyboard`] variants
-/// are supported.
+/// or it isn't obviously useful. Currently, only [`Event::Keyboard`] variants,
+/// [`WindowEvent::CloseRequested`] and [`Event::DragDrop`] are supported.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum EventStatus {
     /// Event was handled by your window and will not be sent back to the
@@ -91,10 +91,16 @@ impl GlContext {
         GlContext { context, phantom: PhantomData }
     }
 
+    /// # Safety
+    ///
+    /// The context must not be current on any other thread.
     pub unsafe fn make_current(&self) {
         self.context.make_current();
     }
 
+    /// # Safety
+    ///
+    /// The context must be current on this thread.
     pub unsafe fn make_not_current(&self) {
         self.context.make_not_current();
     }
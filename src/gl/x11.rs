@@ -101,7 +101,7 @@ impl GlContext {
                 if addr.is_null() {
                     return Err(GlError::CreationFailed(CreationFailedError::GetProcAddressFailed));
                 } else {
-                    std::mem::transmute(addr)
+                    std::mem::transmute::<*const c_void, GlXCreateContextAttribsARB>(addr)
                 }
             };
 
@@ -111,7 +111,7 @@ impl GlContext {
                 if addr.is_null() {
                     return Err(GlError::CreationFailed(CreationFailedError::GetProcAddressFailed));
                 } else {
-                    std::mem::transmute(addr)
+                    std::mem::transmute::<*const c_void, GlXSwapIntervalEXT>(addr)
                 }
             };
 
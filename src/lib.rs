@@ -5,6 +5,8 @@ mod win;
 #[cfg(target_os = "linux")]
 mod x11;
 
+mod clipboard;
+mod error;
 mod event;
 mod keyboard;
 mod mouse_cursor;
@@ -15,8 +17,10 @@ mod window_open_options;
 #[cfg(feature = "opengl")]
 pub mod gl;
 
+pub use clipboard::ClipboardKind;
+pub use error::Error;
 pub use event::*;
-pub use mouse_cursor::MouseCursor;
+pub use mouse_cursor::{CursorImage, CustomCursor, MouseCursor};
 pub use window::*;
 pub use window_info::*;
 pub use window_open_options::*;
@@ -1,6 +1,6 @@
 use std::ffi::c_void;
 
-use cocoa::appkit::{NSColor, NSEvent, NSView, NSWindow};
+use cocoa::appkit::{NSColor, NSEvent, NSEventPhase, NSView, NSWindow};
 use cocoa::base::{id, nil, BOOL, NO, YES};
 use cocoa::foundation::{NSArray, NSPoint, NSRect, NSSize};
 
@@ -15,8 +15,8 @@ use uuid::Uuid;
 
 use crate::MouseEvent::{ButtonPressed, ButtonReleased};
 use crate::{
-    Event, EventStatus, MouseButton, MouseEvent, Point, ScrollDelta, Size, WindowEvent, WindowInfo,
-    WindowOpenOptions,
+    Event, EventStatus, MouseButton, MouseEvent, Point, ScrollDelta, ScrollPhase, Size,
+    WindowEvent, WindowInfo, WindowOpenOptions,
 };
 
 use super::keyboard::make_modifiers;
@@ -153,6 +153,11 @@ unsafe fn create_view_class() -> &'static Class {
 
     class.add_method(sel!(scrollWheel:), scroll_wheel as extern "C" fn(&Object, Sel, id));
 
+    class.add_method(sel!(windowDidMove:), frame_did_change as extern "C" fn(&Object, Sel, id));
+    class
+        .add_method(sel!(viewFrameDidChange:), frame_did_change as extern "C" fn(&Object, Sel, id));
+    class.add_method(sel!(sendMovedEvent), send_moved_event as extern "C" fn(&Object, Sel));
+
     class.add_method(
         sel!(viewDidChangeBackingProperties:),
         view_did_change_backing_properties as extern "C" fn(&Object, Sel, id),
@@ -253,6 +258,35 @@ extern "C" fn view_did_change_backing_properties(this: &Object, _: Sel, _: id) {
     }
 }
 
+/// Called through `NSNotificationCenter` when a standalone window or a parented view was moved.
+/// This also happens while the handler moves the window itself, so the event is sent from the run
+/// loop afterwards.
+extern "C" fn frame_did_change(this: &Object, _: Sel, _: id) {
+    unsafe {
+        let () = msg_send![
+            class!(NSObject),
+            cancelPreviousPerformRequestsWithTarget: this
+            selector: sel!(sendMovedEvent)
+            object: nil
+        ];
+        let () = msg_send![
+            this,
+            performSelector: sel!(sendMovedEvent)
+            withObject: nil
+            afterDelay: 0.0
+        ];
+    }
+}
+
+extern "C" fn send_moved_event(this: &Object, _: Sel) {
+    unsafe {
+        let state_ptr: *mut c_void = *this.get_ivar(BASEVIEW_STATE_IVAR);
+        if !state_ptr.is_null() {
+            WindowState::from_field(this).handle_moved();
+        }
+    }
+}
+
 /// Init/reinit tracking area
 ///
 /// Info:
@@ -376,10 +410,25 @@ extern "C" fn scroll_wheel(this: &Object, _: Sel, event: id) {
         }
     };
 
+    // Momentum scrolling has its own phase, and the regular phase is `None` during it
+    let phase = unsafe {
+        let phase = NSEvent::phase(event);
+        if phase.contains(NSEventPhase::NSEventPhaseBegan) {
+            ScrollPhase::Started
+        } else if phase
+            .intersects(NSEventPhase::NSEventPhaseEnded | NSEventPhase::NSEventPhaseCancelled)
+        {
+            ScrollPhase::Ended
+        } else {
+            ScrollPhase::Moved
+        }
+    };
+
     let modifiers = unsafe { NSEvent::modifierFlags(event) };
 
     state.trigger_event(Event::Mouse(MouseEvent::WheelScrolled {
         delta,
+        phase,
         modifiers: make_modifiers(modifiers),
     }));
 }
@@ -1,26 +1,34 @@
+use std::any::Any;
 use std::ffi::c_void;
 use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
 use std::ptr;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use cocoa::appkit::{
     NSApp, NSApplication, NSApplicationActivationPolicyRegular, NSBackingStoreBuffered, NSWindow,
     NSWindowStyleMask,
 };
-use cocoa::base::{id, nil, YES, NO};
-use cocoa::foundation::{NSAutoreleasePool, NSPoint, NSRect, NSSize, NSString};
+use cocoa::base::{id, nil, BOOL, YES, NO};
+use cocoa::foundation::{NSAutoreleasePool, NSInteger, NSPoint, NSRect, NSSize, NSString};
+use core_foundation::base::{kCFAllocatorDefault, TCFType};
 use core_foundation::runloop::{
-    CFRunLoop, CFRunLoopTimer, CFRunLoopTimerContext, __CFRunLoopTimer, kCFRunLoopDefaultMode,
+    __CFRunLoopTimer, kCFRunLoopDefaultMode, CFRunLoop, CFRunLoopSource, CFRunLoopSourceContext,
+    CFRunLoopSourceCreate, CFRunLoopSourceInvalidate, CFRunLoopSourceSignal, CFRunLoopTimer,
+    CFRunLoopTimerContext, CFRunLoopWakeUp,
 };
 use keyboard_types::KeyboardEvent;
 
-use objc::{msg_send, runtime::Object, sel, sel_impl};
+use objc::{class, msg_send, runtime::Object, sel, sel_impl};
 
 use raw_window_handle::{AppKitHandle, HasRawWindowHandle, RawWindowHandle};
 
 use crate::{
-    Event, EventStatus, MouseCursor, WindowEvent, WindowHandler, WindowInfo, WindowOpenOptions, Size,
+    Error, Event, EventStatus, FrameRate, MouseCursor, Point, Size, WindowEvent, WindowHandler,
+    WindowInfo, WindowOpenOptions, WindowPosition,
 };
 
 use super::cursor::Cursor;
@@ -29,7 +37,7 @@ use super::view::{create_view, BASEVIEW_STATE_IVAR};
 
 #[cfg(feature = "opengl")]
 use crate::{
-    gl::{GlConfig, GlContext},
+    gl::{GlConfig, GlContext, GlError},
     window::RawWindowHandleWrapper,
 };
 
@@ -37,6 +45,7 @@ pub struct WindowHandle {
     raw_window_handle: Option<RawWindowHandle>,
     close_requested: Arc<AtomicBool>,
     is_open: Arc<AtomicBool>,
+    proxy: WindowProxy,
 
     // Ensure handle is !Send
     _phantom: PhantomData<*mut ()>,
@@ -99,6 +108,119 @@ impl WindowHandle {
     pub fn is_open(&self) -> bool {
         self.is_open.load(Ordering::Relaxed)
     }
+
+    pub fn proxy(&self) -> WindowProxy {
+        self.proxy.clone()
+    }
+}
+
+/// A user message posted through `WindowProxy::post()`.
+type Message = Box<dyn Any + Send>;
+
+#[derive(Clone)]
+pub struct WindowProxy {
+    /// Set by `wake()` so multiple calls before the handler runs result in a single `on_wake()`
+    /// call.
+    woken: Arc<AtomicBool>,
+    message_tx: mpsc::Sender<Message>,
+    /// Wakes up the window's run loop. This is `None` until the window has been set up and after
+    /// it has been closed, in which case wakeups and messages are handled on the next frame or
+    /// not at all.
+    run_loop_waker: Arc<Mutex<Option<RunLoopWaker>>>,
+}
+
+impl WindowProxy {
+    fn new() -> (Self, mpsc::Receiver<Message>) {
+        let (message_tx, message_rx) = mpsc::channel();
+
+        let proxy = Self {
+            woken: Arc::new(AtomicBool::new(false)),
+            message_tx,
+            run_loop_waker: Arc::new(Mutex::new(None)),
+        };
+
+        (proxy, message_rx)
+    }
+
+    pub fn post(&self, message: Message) {
+        // This only fails once the window has been closed, in which case there's nobody left to
+        // receive the message
+        if self.message_tx.send(message).is_ok() {
+            self.wake_run_loop();
+        }
+    }
+
+    pub fn wake(&self) {
+        if !self.woken.swap(true, Ordering::AcqRel) {
+            self.wake_run_loop();
+        }
+    }
+
+    fn wake_run_loop(&self) {
+        if let Some(run_loop_waker) = &*self.run_loop_waker.lock().unwrap() {
+            run_loop_waker.wake();
+        }
+    }
+}
+
+/// A run loop source on the window's thread that calls `WindowState::handle_wake()` when it's
+/// signaled from another thread.
+struct RunLoopWaker {
+    source: CFRunLoopSource,
+    run_loop: CFRunLoop,
+}
+
+// Signaling a source and waking up a run loop are thread safe, and Core Foundation objects may be
+// released from any thread
+unsafe impl Send for RunLoopWaker {}
+
+impl RunLoopWaker {
+    /// Add a source for the window state to the current thread's run loop. Don't call until the
+    /// WindowState pointer is stored in the view.
+    unsafe fn new(window_state_ptr: *mut WindowState) -> Self {
+        extern "C" fn perform(window_state_ptr: *const c_void) {
+            unsafe {
+                let window_state = &mut *(window_state_ptr as *mut WindowState);
+
+                window_state.handle_wake();
+            }
+        }
+
+        let mut context = CFRunLoopSourceContext {
+            version: 0,
+            info: window_state_ptr as *mut c_void,
+            retain: None,
+            release: None,
+            copyDescription: None,
+            equal: None,
+            hash: None,
+            schedule: None,
+            cancel: None,
+            perform,
+        };
+
+        let source = CFRunLoopSource::wrap_under_create_rule(CFRunLoopSourceCreate(
+            kCFAllocatorDefault,
+            0,
+            &mut context,
+        ));
+        let run_loop = CFRunLoop::get_current();
+        run_loop.add_source(&source, kCFRunLoopDefaultMode);
+
+        Self { source, run_loop }
+    }
+
+    fn wake(&self) {
+        unsafe {
+            CFRunLoopSourceSignal(self.source.as_concrete_TypeRef());
+            CFRunLoopWakeUp(self.run_loop.as_concrete_TypeRef());
+        }
+    }
+
+    /// Remove the source from the run loop. The window state won't be accessed afterwards.
+    fn invalidate(&self) {
+        unsafe { CFRunLoopSourceInvalidate(self.source.as_concrete_TypeRef()) };
+    }
 }
 
 unsafe impl HasRawWindowHandle for WindowHandle {
@@ -119,7 +241,7 @@ struct ParentHandle {
 }
 
 impl ParentHandle {
-    pub fn new(raw_window_handle: RawWindowHandle) -> (Self, WindowHandle) {
+    pub fn new(raw_window_handle: RawWindowHandle, proxy: WindowProxy) -> (Self, WindowHandle) {
         let close_requested = Arc::new(AtomicBool::new(false));
         let is_open = Arc::new(AtomicBool::new(true));
 
@@ -127,6 +249,7 @@ impl ParentHandle {
             raw_window_handle: Some(raw_window_handle),
             close_requested: Arc::clone(&close_requested),
             is_open: Arc::clone(&is_open),
+            proxy,
             _phantom: PhantomData::default(),
         };
 
@@ -156,54 +279,57 @@ pub struct Window {
     /// Our subclassed NSView
     ns_view: id,
     close_requested: bool,
+    redraw_requested: bool,
+    /// The frame timer's interval
+    frame_interval: Duration,
+    /// Calls `WindowState::trigger_frame()`. This is set up once the window state exists.
+    frame_timer: Option<CFRunLoopTimer>,
+    proxy: WindowProxy,
+    message_rx: mpsc::Receiver<Message>,
 
     #[cfg(feature = "opengl")]
     gl_context: Option<GlContext>,
 }
 
 impl Window {
-    pub fn open_parented<P, H, B>(parent: &P, options: WindowOpenOptions, build: B) -> WindowHandle
+    pub fn open_parented<P, H, B>(
+        parent: &P, options: WindowOpenOptions, build: B,
+    ) -> Result<WindowHandle, Error>
     where
         P: HasRawWindowHandle,
         H: WindowHandler + 'static,
         B: FnOnce(&mut crate::Window) -> H,
         B: Send + 'static,
     {
-        let pool = unsafe { NSAutoreleasePool::new(nil) };
-
         let handle = if let RawWindowHandle::AppKit(handle) = parent.raw_window_handle() {
             handle
         } else {
-            panic!("Not a macOS window");
+            return Err(Error::WindowCreationFailed("Not a macOS window".into()));
         };
 
-        let ns_view = unsafe { create_view(&options) };
-
-        let window = Window {
-            ns_app: None,
-            ns_window: None,
-            ns_view,
-            close_requested: false,
+        let pool = unsafe { NSAutoreleasePool::new(nil) };
 
-            #[cfg(feature = "opengl")]
-            gl_context: options
-                .gl_config
-                .map(|gl_config| Self::create_gl_context(None, ns_view, gl_config)),
-        };
+        let ns_view = unsafe { create_view(&options) };
 
-        let window_handle = Self::init(true, window, build);
+        let result = Self::new_window(None, None, ns_view, &options)
+            .and_then(|window| Self::init(true, window, build));
 
         unsafe {
-            let _: id = msg_send![handle.ns_view as *mut Object, addSubview: ns_view];
+            if result.is_ok() {
+                let _: id = msg_send![handle.ns_view as *mut Object, addSubview: ns_view];
+                WindowState::place(ns_view, options.position);
+            }
             let () = msg_send![ns_view as id, release];
 
             let () = msg_send![pool, drain];
         }
 
-        window_handle
+        result
     }
 
-    pub fn open_as_if_parented<H, B>(options: WindowOpenOptions, build: B) -> WindowHandle
+    pub fn open_as_if_parented<H, B>(
+        options: WindowOpenOptions, build: B,
+    ) -> Result<WindowHandle, Error>
     where
         H: WindowHandler + 'static,
         B: FnOnce(&mut crate::Window) -> H,
@@ -213,28 +339,23 @@ impl Window {
 
         let ns_view = unsafe { create_view(&options) };
 
-        let window = Window {
-            ns_app: None,
-            ns_window: None,
-            ns_view,
-            close_requested: false,
-
-            #[cfg(feature = "opengl")]
-            gl_context: options
-                .gl_config
-                .map(|gl_config| Self::create_gl_context(None, ns_view, gl_config)),
-        };
-
-        let window_handle = Self::init(true, window, build);
+        let result = Self::new_window(None, None, ns_view, &options)
+            .and_then(|window| Self::init(true, window, build));
 
         unsafe {
+            if result.is_ok() {
+                WindowState::place(ns_view, options.position);
+            } else {
+                let () = msg_send![ns_view as id, release];
+            }
+
             let () = msg_send![pool, drain];
         }
 
-        window_handle
+        result
     }
 
-    pub fn open_blocking<H, B>(options: WindowOpenOptions, build: B)
+    pub fn open_blocking<H, B>(options: WindowOpenOptions, build: B) -> Result<(), Error>
     where
         H: WindowHandler + 'static,
         B: FnOnce(&mut crate::Window) -> H,
@@ -265,13 +386,17 @@ impl Window {
             ),
         );
 
+        let mut style_mask = NSWindowStyleMask::NSTitledWindowMask
+            | NSWindowStyleMask::NSClosableWindowMask
+            | NSWindowStyleMask::NSMiniaturizableWindowMask;
+        if options.resizable {
+            style_mask |= NSWindowStyleMask::NSResizableWindowMask;
+        }
+
         let ns_window = unsafe {
             let ns_window = NSWindow::alloc(nil).initWithContentRect_styleMask_backing_defer_(
                 rect,
-                NSWindowStyleMask::NSTitledWindowMask
-                    | NSWindowStyleMask::NSClosableWindowMask
-                    | NSWindowStyleMask::NSMiniaturizableWindowMask
-                    | NSWindowStyleMask::NSResizableWindowMask,
+                style_mask,
                 NSBackingStoreBuffered,
                 NO,
             );
@@ -287,39 +412,74 @@ impl Window {
 
         let ns_view = unsafe { create_view(&options) };
 
-        let window = Window {
-            ns_app: Some(app),
-            ns_window: Some(ns_window),
-            ns_view,
-            close_requested: false,
-
-            #[cfg(feature = "opengl")]
-            gl_context: options
-                .gl_config
-                .map(|gl_config| Self::create_gl_context(Some(ns_window), ns_view, gl_config)),
-        };
+        let result = Self::new_window(Some(app), Some(ns_window), ns_view, &options)
+            .and_then(|mut window| {
+                window.set_min_size(options.min_size);
+                window.set_max_size(options.max_size);
+                window.set_aspect_ratio(options.aspect_ratio);
 
-        let _ = Self::init(false, window, build);
+                Self::init(false, window, build)
+            });
 
         unsafe {
-            ns_window.setContentView_(ns_view);
+            if result.is_ok() {
+                ns_window.setContentView_(ns_view);
+                WindowState::place(ns_view, options.position);
+            } else {
+                ns_window.close();
+            }
 
             let () = msg_send![ns_view as id, release];
             let () = msg_send![pool, drain];
 
-            app.run();
+            if result.is_ok() {
+                app.run();
+            }
         }
+
+        result.map(|_| ())
     }
 
-    fn init<H, B>(parented: bool, mut window: Window, build: B) -> WindowHandle
+    #[cfg_attr(not(feature = "opengl"), allow(unused_variables))]
+    fn new_window(
+        ns_app: Option<id>, ns_window: Option<id>, ns_view: id, options: &WindowOpenOptions,
+    ) -> Result<Window, Error> {
+        let (proxy, message_rx) = WindowProxy::new();
+
+        Ok(Window {
+            ns_app,
+            ns_window,
+            ns_view,
+            close_requested: false,
+            redraw_requested: false,
+            frame_interval: unsafe { frame_interval(ns_view, options.frame_rate) },
+            frame_timer: None,
+            proxy,
+            message_rx,
+
+            #[cfg(feature = "opengl")]
+            gl_context: options
+                .gl_config
+                .clone()
+                .map(|gl_config| Self::create_gl_context(ns_window, ns_view, gl_config))
+                .transpose()
+                .map_err(|err| Error::GlConfigFailed(format!("{:?}", err)))?,
+        })
+    }
+
+    fn init<H, B>(parented: bool, mut window: Window, build: B) -> Result<WindowHandle, Error>
     where
         H: WindowHandler + 'static,
         B: FnOnce(&mut crate::Window) -> H,
         B: Send + 'static,
     {
-        let window_handler = Box::new(build(&mut crate::Window::new(&mut window)));
+        let window_handler =
+            panic::catch_unwind(AssertUnwindSafe(|| build(&mut crate::Window::new(&mut window))))
+                .map(Box::new)
+                .map_err(|_| Error::BuildPanicked)?;
 
-        let (parent_handle, window_handle) = ParentHandle::new(window.raw_window_handle());
+        let (parent_handle, window_handle) =
+            ParentHandle::new(window.raw_window_handle(), window.proxy.clone());
         let parent_handle = if parented { Some(parent_handle) } else { None };
 
         let retain_count_after_build: usize = unsafe { msg_send![window.ns_view, retainCount] };
@@ -328,7 +488,7 @@ impl Window {
             window,
             window_handler,
             keyboard_state: KeyboardState::new(),
-            frame_timer: None,
+            position: Point::new(0.0, 0.0),
             retain_count_after_build,
             _parent_handle: parent_handle,
         }));
@@ -338,9 +498,12 @@ impl Window {
                 .set_ivar(BASEVIEW_STATE_IVAR, window_state_ptr as *mut c_void);
 
             WindowState::setup_timer(window_state_ptr);
+
+            let run_loop_waker = RunLoopWaker::new(window_state_ptr);
+            *(*window_state_ptr).window.proxy.run_loop_waker.lock().unwrap() = Some(run_loop_waker);
         }
 
-        window_handle
+        Ok(window_handle)
     }
 
     pub fn resize(&self, size: Size) {
@@ -348,6 +511,176 @@ impl Window {
  
     }
 
+    pub fn position(&self) -> Point {
+        unsafe {
+            let frame = self.frame();
+            let y = match self.unflipped_parent_height() {
+                Some(parent_height) => parent_height - frame.origin.y - frame.size.height,
+                None => frame.origin.y,
+            };
+
+            Point::new(frame.origin.x, y)
+        }
+    }
+
+    pub fn set_position(&mut self, position: Point) {
+        unsafe {
+            let frame = self.frame();
+            let y = match self.unflipped_parent_height() {
+                Some(parent_height) => parent_height - position.y - frame.size.height,
+                None => position.y,
+            };
+            let origin = NSPoint::new(position.x, y);
+
+            match self.ns_window {
+                Some(ns_window) => {
+                    let content_rect = NSRect::new(origin, frame.size);
+                    let frame: NSRect = msg_send![ns_window, frameRectForContentRect: content_rect];
+                    let () = msg_send![ns_window, setFrameOrigin: frame.origin];
+                }
+                None => {
+                    let () = msg_send![self.ns_view, setFrameOrigin: origin];
+                }
+            }
+        }
+    }
+
+    /// Move the window to its initial position. Parented views need to have been added to their
+    /// superview.
+    unsafe fn set_initial_position(&mut self, position: WindowPosition) {
+        let size = self.frame().size;
+        let superview: id = msg_send![self.ns_view, superview];
+
+        match position {
+            WindowPosition::Default => {}
+            WindowPosition::Absolute(position) => self.set_position(position),
+            WindowPosition::CenteredOnParent if self.ns_window.is_none() && superview != nil => {
+                let bounds: NSRect = msg_send![superview, bounds];
+                self.set_position(Point::new(
+                    (bounds.size.width - size.width) / 2.0,
+                    (bounds.size.height - size.height) / 2.0,
+                ));
+            }
+            WindowPosition::CenteredOnScreen | WindowPosition::CenteredOnParent => {
+                if let Some(ns_window) = self.ns_window {
+                    ns_window.center();
+                    return;
+                }
+
+                let ns_window: id = msg_send![self.ns_view, window];
+                let screen: id = if ns_window != nil { msg_send![ns_window, screen] } else { nil };
+                if superview == nil || screen == nil {
+                    return;
+                }
+
+                // The rectangle is converted to the superview's coordinates through the window
+                let screen_frame: NSRect = msg_send![screen, frame];
+                let rect = NSRect::new(
+                    NSPoint::new(
+                        screen_frame.origin.x + (screen_frame.size.width - size.width) / 2.0,
+                        screen_frame.origin.y + (screen_frame.size.height - size.height) / 2.0,
+                    ),
+                    size,
+                );
+                let rect: NSRect = msg_send![ns_window, convertRectFromScreen: rect];
+                let rect: NSRect = msg_send![superview, convertRect: rect fromView: nil];
+                let () = msg_send![self.ns_view, setFrameOrigin: rect.origin];
+            }
+        }
+    }
+
+    /// The frame of the window's content in screen coordinates for standalone windows, or the
+    /// view's frame in its superview's coordinates otherwise.
+    unsafe fn frame(&self) -> NSRect {
+        match self.ns_window {
+            Some(ns_window) => {
+                let frame: NSRect = msg_send![ns_window, frame];
+                msg_send![ns_window, contentRectForFrameRect: frame]
+            }
+            None => msg_send![self.ns_view, frame],
+        }
+    }
+
+    /// The height of the coordinate system `frame()` is in if its y axis points up, so positions
+    /// can be converted to the top-left origin used everywhere else. Screen coordinates start at
+    /// the bottom left corner of the primary screen.
+    unsafe fn unflipped_parent_height(&self) -> Option<f64> {
+        if self.ns_window.is_some() {
+            let screens: id = msg_send![class!(NSScreen), screens];
+            let primary_screen: id = msg_send![screens, firstObject];
+            if primary_screen == nil {
+                return None;
+            }
+
+            let screen_frame: NSRect = msg_send![primary_screen, frame];
+            return Some(screen_frame.size.height);
+        }
+
+        let superview: id = msg_send![self.ns_view, superview];
+        if superview == nil {
+            return None;
+        }
+
+        let is_flipped: BOOL = msg_send![superview, isFlipped];
+        if is_flipped == YES {
+            None
+        } else {
+            let bounds: NSRect = msg_send![superview, bounds];
+            Some(bounds.size.height)
+        }
+    }
+
+    // The size constraints only apply to windows we created ourselves, a parented view's size is
+    // determined by its parent
+
+    pub fn set_min_size(&mut self, min_size: Option<Size>) {
+        if let Some(ns_window) = self.ns_window {
+            let min_size = min_size.unwrap_or(Size::new(0.0, 0.0));
+            let min_size = NSSize::new(min_size.width, min_size.height);
+            unsafe {
+                let () = msg_send![ns_window, setContentMinSize: min_size];
+            }
+        }
+    }
+
+    pub fn set_max_size(&mut self, max_size: Option<Size>) {
+        if let Some(ns_window) = self.ns_window {
+            let max_size = max_size.unwrap_or(Size::new(f32::MAX as f64, f32::MAX as f64));
+            let max_size = NSSize::new(max_size.width, max_size.height);
+            unsafe {
+                let () = msg_send![ns_window, setContentMaxSize: max_size];
+            }
+        }
+    }
+
+    pub fn set_resizable(&mut self, resizable: bool) {
+        if let Some(ns_window) = self.ns_window {
+            unsafe {
+                let mut style_mask = ns_window.styleMask();
+                style_mask.set(NSWindowStyleMask::NSResizableWindowMask, resizable);
+                ns_window.setStyleMask_(style_mask);
+            }
+        }
+    }
+
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: Option<f64>) {
+        if let Some(ns_window) = self.ns_window {
+            unsafe {
+                match aspect_ratio.filter(|ratio| *ratio > 0.0) {
+                    Some(aspect_ratio) => {
+                        let aspect_ratio = NSSize::new(aspect_ratio, 1.0);
+                        let () = msg_send![ns_window, setContentAspectRatio: aspect_ratio];
+                    }
+                    // Setting the resize increments is the only way to remove the aspect ratio
+                    None => {
+                        let increments = NSSize::new(1.0, 1.0);
+                        let () = msg_send![ns_window, setContentResizeIncrements: increments];
+                    }
+                }
+            }
+        }
+    }
+
     pub fn set_mouse_cursor(&mut self, cursor: MouseCursor) {
         let native_cursor = Cursor::from(cursor);
         unsafe {
@@ -360,23 +693,63 @@ impl Window {
         }
     }
 
+    pub fn has_focus(&self) -> bool {
+        unsafe {
+            let ns_window: id = msg_send![self.ns_view, window];
+            if ns_window == nil {
+                return false;
+            }
+
+            let is_key_window: BOOL = msg_send![ns_window, isKeyWindow];
+            let first_responder: id = msg_send![ns_window, firstResponder];
+
+            is_key_window == YES && first_responder == self.ns_view
+        }
+    }
+
     pub fn close(&mut self) {
         self.close_requested = true;
     }
 
+    pub fn proxy(&self) -> WindowProxy {
+        self.proxy.clone()
+    }
+
+    pub fn request_redraw(&mut self) {
+        self.redraw_requested = true;
+    }
+
+    pub fn set_frame_rate(&mut self, frame_rate: FrameRate) {
+        self.frame_interval = unsafe { frame_interval(self.ns_view, frame_rate) };
+
+        // A timer's interval can't be changed, so the timer is replaced instead. Before the window
+        // state has been set up, `WindowState::setup_timer()` uses the new interval.
+        if let Some(frame_timer) = self.frame_timer.take() {
+            unsafe {
+                CFRunLoop::get_current().remove_timer(&frame_timer, kCFRunLoopDefaultMode);
+
+                let window_state_ptr: *mut c_void = *(*self.ns_view).get_ivar(BASEVIEW_STATE_IVAR);
+                let window_state_ptr = window_state_ptr as *mut WindowState;
+                self.frame_timer = Some(create_frame_timer(window_state_ptr, self.frame_interval));
+            }
+        }
+    }
+
     #[cfg(feature = "opengl")]
     pub fn gl_context(&self) -> Option<&GlContext> {
         self.gl_context.as_ref()
     }
 
     #[cfg(feature = "opengl")]
-    fn create_gl_context(ns_window: Option<id>, ns_view: id, config: GlConfig) -> GlContext {
+    fn create_gl_context(
+        ns_window: Option<id>, ns_view: id, config: GlConfig,
+    ) -> Result<GlContext, GlError> {
         let mut handle = AppKitHandle::empty();
         handle.ns_window = ns_window.unwrap_or(ptr::null_mut()) as *mut c_void;
         handle.ns_view = ns_view as *mut c_void;
         let handle = RawWindowHandleWrapper { handle: RawWindowHandle::AppKit(handle) };
 
-        unsafe { GlContext::create(&handle, config).expect("Could not create OpenGL context") }
+        unsafe { GlContext::create(&handle, config) }
     }
 }
 
@@ -384,7 +757,8 @@ pub(super) struct WindowState {
     window: Window,
     window_handler: Box<dyn WindowHandler>,
     keyboard_state: KeyboardState,
-    frame_timer: Option<CFRunLoopTimer>,
+    /// The last position sent in `WindowEvent::Moved`
+    position: Point,
     _parent_handle: Option<ParentHandle>,
     pub retain_count_after_build: usize,
 }
@@ -405,7 +779,22 @@ impl WindowState {
         self.window_handler.on_event(&mut crate::Window::new(&mut self.window), event)
     }
 
+    /// Handle wakeups and messages from the window's `WindowProxy`.
+    fn handle_wake(&mut self) {
+        if self.window.proxy.woken.swap(false, Ordering::AcqRel) {
+            self.window_handler.on_wake(&mut crate::Window::new(&mut self.window));
+        }
+        while let Ok(message) = self.window.message_rx.try_recv() {
+            self.window_handler.on_message(&mut crate::Window::new(&mut self.window), message);
+        }
+    }
+
     pub(super) fn trigger_frame(&mut self) {
+        self.handle_wake();
+        if std::mem::take(&mut self.window.redraw_requested) {
+            self.window_handler.on_redraw(&mut crate::Window::new(&mut self.window));
+        }
+
         self.window_handler.on_frame(&mut crate::Window::new(&mut self.window));
 
         let mut do_close = false;
@@ -439,35 +828,49 @@ impl WindowState {
         }
     }
 
+    /// Move the window to its initial position and start listening for moves. The view needs to
+    /// have been added to its window or superview, and the initial position isn't reported as a
+    /// move.
+    unsafe fn place(ns_view: id, position: WindowPosition) {
+        let state = WindowState::from_field(&*ns_view);
+        state.window.set_initial_position(position);
+        state.position = state.window.position();
+
+        // Standalone windows are moved as a whole, parented views are moved within their
+        // superview. The view handles both notifications in `view.rs`.
+        let (name, object, selector) = match state.window.ns_window {
+            Some(ns_window) => ("NSWindowDidMoveNotification", ns_window, sel!(windowDidMove:)),
+            None => ("NSViewFrameDidChangeNotification", ns_view, sel!(viewFrameDidChange:)),
+        };
+        let name = NSString::alloc(nil).init_str(name).autorelease();
+        let center: id = msg_send![class!(NSNotificationCenter), defaultCenter];
+        let () = msg_send![
+            center,
+            addObserver: ns_view
+            selector: selector
+            name: name
+            object: object
+        ];
+    }
+
+    /// Send `WindowEvent::Moved` if the window's position changed since the last time.
+    pub(super) fn handle_moved(&mut self) {
+        let position = self.window.position();
+        if position != self.position {
+            self.position = position;
+            self.trigger_event(Event::Window(WindowEvent::Moved(position)));
+        }
+    }
+
     pub(super) fn process_native_key_event(&mut self, event: *mut Object) -> Option<KeyboardEvent> {
         self.keyboard_state.process_native_event(event)
     }
 
     /// Don't call until WindowState pointer is stored in view
     unsafe fn setup_timer(window_state_ptr: *mut WindowState) {
-        extern "C" fn timer_callback(_: *mut __CFRunLoopTimer, window_state_ptr: *mut c_void) {
-            unsafe {
-                let window_state = &mut *(window_state_ptr as *mut WindowState);
-
-                window_state.trigger_frame();
-            }
-        }
+        let window = &mut (*window_state_ptr).window;
 
-        let mut timer_context = CFRunLoopTimerContext {
-            version: 0,
-            info: window_state_ptr as *mut c_void,
-            retain: None,
-            release: None,
-            copyDescription: None,
-        };
-
-        let timer = CFRunLoopTimer::new(0.0, 0.015, 0, 0, timer_callback, &mut timer_context);
-
-        CFRunLoop::get_current().add_timer(&timer, kCFRunLoopDefaultMode);
-
-        let window_state = &mut *(window_state_ptr);
-
-        window_state.frame_timer = Some(timer);
+        window.frame_timer = Some(create_frame_timer(window_state_ptr, window.frame_interval));
     }
 
     /// Call when freeing view
@@ -478,9 +881,20 @@ impl WindowState {
         // when it goes out of scope
         let mut window_state = Box::from_raw(state_ptr as *mut WindowState);
 
-        if let Some(frame_timer) = window_state.frame_timer.take() {
+        if let Some(frame_timer) = window_state.window.frame_timer.take() {
             CFRunLoop::get_current().remove_timer(&frame_timer, kCFRunLoopDefaultMode);
         }
+        let run_loop_waker = window_state.window.proxy.run_loop_waker.lock().unwrap().take();
+        if let Some(run_loop_waker) = run_loop_waker {
+            run_loop_waker.invalidate();
+        }
+
+        let center: id = msg_send![class!(NSNotificationCenter), defaultCenter];
+        let () = msg_send![center, removeObserver: ns_view_obj as *mut Object];
+        let () = msg_send![
+            class!(NSObject),
+            cancelPreviousPerformRequestsWithTarget: ns_view_obj as *mut Object
+        ];
 
         // Clear ivar before triggering WindowEvent::WillClose. Otherwise, if the
         // handler of the event causes another call to release, this function could be
@@ -496,6 +910,67 @@ impl WindowState {
     }
 }
 
+/// Add a timer that calls `WindowState::trigger_frame()` to the current thread's run loop.
+unsafe fn create_frame_timer(
+    window_state_ptr: *mut WindowState, interval: Duration,
+) -> CFRunLoopTimer {
+    extern "C" fn timer_callback(_: *mut __CFRunLoopTimer, window_state_ptr: *mut c_void) {
+        unsafe {
+            let window_state = &mut *(window_state_ptr as *mut WindowState);
+
+            window_state.trigger_frame();
+        }
+    }
+
+    let mut timer_context = CFRunLoopTimerContext {
+        version: 0,
+        info: window_state_ptr as *mut c_void,
+        retain: None,
+        release: None,
+        copyDescription: None,
+    };
+
+    let timer =
+        CFRunLoopTimer::new(0.0, interval.as_secs_f64(), 0, 0, timer_callback, &mut timer_context);
+
+    CFRunLoop::get_current().add_timer(&timer, kCFRunLoopDefaultMode);
+
+    timer
+}
+
+/// The frame timer's interval for a frame rate.
+unsafe fn frame_interval(ns_view: id, frame_rate: FrameRate) -> Duration {
+    frame_rate.fixed_interval().unwrap_or_else(|| {
+        let refresh_rate = monitor_refresh_rate(ns_view).unwrap_or(FrameRate::FALLBACK_HZ);
+
+        Duration::from_secs_f64(1.0 / refresh_rate)
+    })
+}
+
+/// The refresh rate of the screen the view is on, or of the main screen if the view isn't part of
+/// a window yet. This is only available on macOS 12 and later.
+unsafe fn monitor_refresh_rate(ns_view: id) -> Option<f64> {
+    let ns_window: id = msg_send![ns_view, window];
+    let mut screen: id = if ns_window != nil { msg_send![ns_window, screen] } else { nil };
+    if screen == nil {
+        screen = msg_send![class!(NSScreen), mainScreen];
+    }
+    if screen == nil {
+        return None;
+    }
+    let supported: BOOL = msg_send![screen, respondsToSelector: sel!(maximumFramesPerSecond)];
+    if supported == NO {
+        return None;
+    }
+
+    let refresh_rate: NSInteger = msg_send![screen, maximumFramesPerSecond];
+    if refresh_rate > 0 {
+        Some(refresh_rate as f64)
+    } else {
+        None
+    }
+}
+
 unsafe impl HasRawWindowHandle for Window {
     fn raw_window_handle(&self) -> RawWindowHandle {
         let ns_window = self.ns_window.unwrap_or(ptr::null_mut()) as *mut c_void;
@@ -1,5 +1,8 @@
-#[derive(Debug, Eq, PartialEq, Clone, Copy, PartialOrd, Ord, Hash)]
+use std::sync::Arc;
+
+#[derive(Debug, Default, Eq, PartialEq, Clone, PartialOrd, Ord, Hash)]
 pub enum MouseCursor {
+    #[default]
     Default,
     Hand,
     HandGrabbing,
@@ -41,10 +44,111 @@ pub enum MouseCursor {
     NeswResize,
     ColResize,
     RowResize,
+
+    /// A cursor made from the application's own images
+    ///
+    /// # TODO
+    ///
+    /// This is currently only supported on Linux. The default cursor is shown on Windows and
+    /// macOS.
+    Custom(CustomCursor),
+}
+
+/// A cursor made from RGBA images, for use with [`MouseCursor::Custom`]. Cloning a cursor is
+/// cheap, since the images are shared.
+///
+/// A cursor can contain the same image in multiple resolutions. The smallest image is used at a
+/// scale factor of 1. At larger scale factors the smallest image that's at least as wide as the
+/// smallest image times the scale factor is used, or the largest image if there is no such image.
+#[derive(Debug, Eq, PartialEq, Clone, PartialOrd, Ord, Hash)]
+pub struct CustomCursor {
+    /// Sorted by width
+    images: Arc<[CursorImage]>,
 }
 
-impl Default for MouseCursor {
-    fn default() -> Self {
-        Self::Default
+impl CustomCursor {
+    /// Create a cursor from a single image, which is used at every scale factor.
+    pub fn new(image: CursorImage) -> Self {
+        Self { images: Arc::new([image]) }
+    }
+
+    /// Create a cursor from the same image in multiple resolutions, e.g. a 16x16 image for a
+    /// scale factor of 1 and a 32x32 image for a scale factor of 2.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `images` is empty.
+    pub fn from_images(images: impl IntoIterator<Item = CursorImage>) -> Self {
+        let mut images: Vec<CursorImage> = images.into_iter().collect();
+        assert!(!images.is_empty(), "a custom cursor needs at least one image");
+        images.sort_by_key(|image| image.width);
+
+        Self { images: images.into() }
+    }
+
+    /// The cursor's images, from the smallest to the largest.
+    pub fn images(&self) -> &[CursorImage] {
+        &self.images
+    }
+
+    /// A weak reference to the images, which is shared by all clones of this cursor. The
+    /// allocation's address can't be reused for another cursor while the reference exists, so it
+    /// identifies the cursor without comparing the images themselves.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn downgrade(&self) -> std::sync::Weak<[CursorImage]> {
+        Arc::downgrade(&self.images)
+    }
+}
+
+/// One resolution of a [`CustomCursor`].
+#[derive(Debug, Eq, PartialEq, Clone, PartialOrd, Ord, Hash)]
+pub struct CursorImage {
+    width: u32,
+    height: u32,
+    hotspot_x: u32,
+    hotspot_y: u32,
+    rgba: Vec<u8>,
+}
+
+impl CursorImage {
+    /// Create a cursor image from its pixels. `rgba` contains the rows of pixels from top to
+    /// bottom, with four bytes per pixel for the red, green, blue and alpha channels. The color
+    /// channels must not be premultiplied by the alpha channel. The hotspot is the pixel that's
+    /// placed at the pointer's position.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `rgba` doesn't contain exactly `width * height` pixels, or if the hotspot is
+    /// outside of the image.
+    pub fn new(width: u32, height: u32, hotspot_x: u32, hotspot_y: u32, rgba: Vec<u8>) -> Self {
+        assert_eq!(
+            rgba.len(),
+            width as usize * height as usize * 4,
+            "the cursor image's pixels don't match its size"
+        );
+        assert!(
+            hotspot_x < width && hotspot_y < height,
+            "the cursor image's hotspot is outside of the image"
+        );
+
+        Self { width, height, hotspot_x, hotspot_y, rgba }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The position of the hotspot in pixels, relative to the image's top left corner
+    pub fn hotspot(&self) -> (u32, u32) {
+        (self.hotspot_x, self.hotspot_y)
+    }
+
+    /// The image's pixels in RGBA order
+    pub fn rgba(&self) -> &[u8] {
+        &self.rgba
     }
 }
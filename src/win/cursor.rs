@@ -8,7 +8,7 @@ use winapi::{
 use crate::MouseCursor;
 
 impl MouseCursor {
-    pub(crate) fn to_windows_cursor(self) -> PCWSTR {
+    pub(crate) fn to_windows_cursor(&self) -> PCWSTR {
         match self {
             MouseCursor::Default => IDC_ARROW,
             MouseCursor::Hand | MouseCursor::Pointer => IDC_HAND,
@@ -1,28 +1,40 @@
 use winapi::shared::guiddef::GUID;
 use winapi::shared::minwindef::{ATOM, FALSE, TRUE, LPARAM, LRESULT, UINT, WPARAM};
-use winapi::shared::windef::{HWND, RECT};
+use winapi::shared::windef::{HWND, POINT, RECT};
 use winapi::um::combaseapi::CoCreateGuid;
-use winapi::um::winuser::{AdjustWindowRectEx, CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, GetDpiForWindow, GetMessageW, GetWindowLongPtrW, LoadCursorW, PostMessageW, RegisterClassW, ReleaseCapture, SetCapture, SetProcessDpiAwarenessContext, SetTimer, SetWindowLongPtrW, SetWindowPos, TranslateMessage, UnregisterClassW, CS_OWNDC, GET_XBUTTON_WPARAM, GWLP_USERDATA, MSG, SWP_NOMOVE, SWP_NOZORDER, WHEEL_DELTA, WM_CHAR, WM_CLOSE, WM_CREATE, WM_DPICHANGED, WM_INPUTLANGCHANGE, WM_KEYDOWN, WM_KEYUP, WM_LBUTTONDOWN, WM_LBUTTONUP, WM_MBUTTONDOWN, WM_MBUTTONUP, WM_MOUSEMOVE, WM_MOUSEWHEEL, WM_NCDESTROY, WM_RBUTTONDOWN, WM_RBUTTONUP, WM_SHOWWINDOW, WM_SIZE, WM_SYSCHAR, WM_SYSKEYDOWN, WM_SYSKEYUP, WM_TIMER, WM_USER, WM_XBUTTONDOWN, WM_XBUTTONUP, WNDCLASSW, WS_CAPTION, WS_CHILD, WS_CLIPSIBLINGS, WS_MAXIMIZEBOX, WS_MINIMIZEBOX, WS_POPUPWINDOW, WS_SIZEBOX, WS_VISIBLE, XBUTTON1, XBUTTON2, SetCursor, SetFocus, MoveWindow, GetWindowRect, SWP_NOOWNERZORDER, GCLP_HCURSOR, SetClassLongPtrW, GCLP_HBRBACKGROUND};
+use winapi::um::errhandlingapi::GetLastError;
+use winapi::um::winuser::{AdjustWindowRectEx, CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, GetDpiForWindow, GetMessageW, GetWindowLongPtrW, LoadCursorW, PostMessageW, RegisterClassW, ReleaseCapture, SetCapture, SetProcessDpiAwarenessContext, SetTimer, SetWindowLongPtrW, SetWindowPos, TranslateMessage, UnregisterClassW, CS_OWNDC, GET_XBUTTON_WPARAM, GWLP_USERDATA, MSG, SWP_NOMOVE, SWP_NOZORDER, WHEEL_DELTA, WM_CHAR, WM_CLOSE, WM_CREATE, WM_DPICHANGED, WM_INPUTLANGCHANGE, WM_KEYDOWN, WM_KEYUP, WM_LBUTTONDOWN, WM_LBUTTONUP, WM_MBUTTONDOWN, WM_MBUTTONUP, WM_MOUSEMOVE, WM_MOUSEWHEEL, WM_NCDESTROY, WM_RBUTTONDOWN, WM_RBUTTONUP, WM_SHOWWINDOW, WM_SIZE, WM_SYSCHAR, WM_SYSKEYDOWN, WM_SYSKEYUP, WM_TIMER, WM_USER, WM_XBUTTONDOWN, WM_XBUTTONUP, WNDCLASSW, WS_CAPTION, WS_CHILD, WS_CLIPSIBLINGS, WS_MAXIMIZEBOX, WS_MINIMIZEBOX, WS_POPUPWINDOW, WS_SIZEBOX, WS_VISIBLE, XBUTTON1, XBUTTON2, SetCursor, SetFocus, MoveWindow, GetWindowRect, SWP_NOOWNERZORDER, GCLP_HCURSOR, SetClassLongPtrW, GCLP_HBRBACKGROUND, GWL_STYLE, MINMAXINFO, SWP_FRAMECHANGED, SWP_NOACTIVATE, WMSZ_BOTTOM, WMSZ_TOP, WMSZ_TOPLEFT, WMSZ_TOPRIGHT, WM_GETMINMAXINFO, WM_SIZING, ClientToScreen, GetParent, ScreenToClient, SWP_NOSIZE, ClipCursor, GetClientRect, GetCursorPos, SetCursorPos, GetCapture, WM_CAPTURECHANGED, GetFocus, WM_MOVE, IsIconic, MonitorFromWindow, GetMonitorInfoW, MONITORINFO, MONITOR_DEFAULTTONEAREST, WM_DESTROY};
 
 
 
-use std::cell::RefCell;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
 use std::ffi::{c_void, OsStr};
 use std::marker::PhantomData;
 use std::ops::DerefMut;
 use std::os::windows::ffi::OsStrExt;
+use std::panic::{self, AssertUnwindSafe};
 use std::ptr::null_mut;
+use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use raw_window_handle::{HasRawWindowHandle, RawWindowHandle, Win32Handle};
 use winapi::um::wingdi::{CreateSolidBrush, RGB};
 
 const BV_WINDOW_MUST_CLOSE: UINT = WM_USER + 1;
+const BV_WINDOW_MESSAGE: UINT = WM_USER + 2;
+const BV_WINDOW_WAKE: UINT = WM_USER + 3;
+const BV_WINDOW_SIZE_CONSTRAINTS_CHANGED: UINT = WM_USER + 4;
+const BV_WINDOW_REDRAW: UINT = WM_USER + 5;
+const BV_WINDOW_MOVED: UINT = WM_USER + 6;
 
 use crate::{
-    Event, MouseButton, MouseEvent, PhyPoint, PhySize, ScrollDelta, WindowEvent, WindowHandler,
-    WindowInfo, WindowOpenOptions, MouseCursor, Size
+    Error, Event, EventStatus, MouseButton, MouseEvent, PhyPoint, PhySize, ScrollDelta, WindowEvent,
+    WindowHandler, WindowInfo, WindowOpenOptions, WindowScalePolicy, MouseCursor, Point, Size, FrameRate,
+    ScrollPhase, WindowPosition
 };
 
 use super::keyboard::KeyboardState;
@@ -54,6 +66,7 @@ const WIN_FRAME_TIMER: usize = 4242;
 pub struct WindowHandle {
     hwnd: Option<HWND>,
     is_open: Arc<AtomicBool>,
+    proxy: WindowProxy,
 
     // Ensure handle is !Send
     _phantom: PhantomData<*mut ()>,
@@ -87,6 +100,67 @@ impl WindowHandle {
     pub fn is_open(&self) -> bool {
         self.is_open.load(Ordering::Relaxed)
     }
+
+    pub fn proxy(&self) -> WindowProxy {
+        self.proxy.clone()
+    }
+}
+
+/// A user message posted through `WindowProxy::post()`.
+type Message = Box<dyn Any + Send>;
+
+#[derive(Clone)]
+pub struct WindowProxy {
+    hwnd: HWND,
+    /// Set by `wake()` so multiple calls before the handler runs result in a single `on_wake()`
+    /// call.
+    woken: Arc<AtomicBool>,
+    message_tx: mpsc::Sender<Message>,
+    /// Cleared in `WM_DESTROY`. After that the `HWND` may be reused for another window, so nothing
+    /// may be posted to it anymore. The lock is held while posting so the window can't be
+    /// destroyed in between.
+    alive: Arc<Mutex<bool>>,
+}
+
+// Posting messages to a window is allowed from any thread
+unsafe impl Send for WindowProxy {}
+
+impl WindowProxy {
+    fn new(hwnd: HWND) -> (Self, mpsc::Receiver<Message>) {
+        let (message_tx, message_rx) = mpsc::channel();
+
+        let proxy = Self {
+            hwnd,
+            woken: Arc::new(AtomicBool::new(false)),
+            message_tx,
+            alive: Arc::new(Mutex::new(true)),
+        };
+
+        (proxy, message_rx)
+    }
+
+    pub fn post(&self, message: Message) {
+        // This only fails once the window has been closed, in which case there's nobody left to
+        // receive the message
+        if self.message_tx.send(message).is_ok() {
+            self.post_window_message(BV_WINDOW_MESSAGE);
+        }
+    }
+
+    pub fn wake(&self) {
+        if !self.woken.swap(true, Ordering::AcqRel) {
+            self.post_window_message(BV_WINDOW_WAKE);
+        }
+    }
+
+    fn post_window_message(&self, msg: UINT) {
+        let alive = self.alive.lock().unwrap();
+        if *alive {
+            unsafe {
+                PostMessageW(self.hwnd, msg, 0, 0);
+            }
+        }
+    }
 }
 
 unsafe impl HasRawWindowHandle for WindowHandle {
@@ -107,12 +181,13 @@ struct ParentHandle {
 }
 
 impl ParentHandle {
-    pub fn new(hwnd: HWND) -> (Self, WindowHandle) {
+    pub fn new(hwnd: HWND, proxy: WindowProxy) -> (Self, WindowHandle) {
         let is_open = Arc::new(AtomicBool::new(true));
 
         let handle = WindowHandle {
             hwnd: Some(hwnd),
             is_open: Arc::clone(&is_open),
+            proxy,
             _phantom: PhantomData::default(),
         };
 
@@ -126,8 +201,111 @@ impl Drop for ParentHandle {
     }
 }
 
+/// Move the cursor to a physical position relative to the window's client area.
+unsafe fn move_cursor(hwnd: HWND, position: PhyPoint) {
+    let mut position = POINT { x: position.x, y: position.y };
+    ClientToScreen(hwnd, &mut position);
+    SetCursorPos(position.x, position.y);
+}
+
+/// The parent of a child window, or `None` for top level windows.
+unsafe fn window_parent(hwnd: HWND) -> Option<HWND> {
+    let style = GetWindowLongPtrW(hwnd, GWL_STYLE) as u32;
+    let parent = GetParent(hwnd);
+    if style & WS_CHILD != 0 && !parent.is_null() {
+        Some(parent)
+    } else {
+        None
+    }
+}
+
+/// The physical position of the client area's top left corner, relative to the parent's client
+/// area for parented windows and relative to the screen otherwise.
+unsafe fn client_origin(hwnd: HWND) -> PhyPoint {
+    let mut position = POINT { x: 0, y: 0 };
+    ClientToScreen(hwnd, &mut position);
+    if let Some(parent) = window_parent(hwnd) {
+        ScreenToClient(parent, &mut position);
+    }
+
+    PhyPoint::new(position.x, position.y)
+}
+
+/// Move the client area's top left corner to a physical position, relative to the parent's
+/// client area for parented windows and relative to the screen otherwise.
+unsafe fn set_client_origin(hwnd: HWND, position: PhyPoint) {
+    // `SetWindowPos()` positions the window's frame instead of its client area
+    let client_origin = client_origin(hwnd);
+    let mut rect: RECT = std::mem::zeroed();
+    GetWindowRect(hwnd, &mut rect);
+    let mut frame_origin = POINT { x: rect.left, y: rect.top };
+    if let Some(parent) = window_parent(hwnd) {
+        ScreenToClient(parent, &mut frame_origin);
+    }
+
+    SetWindowPos(
+        hwnd,
+        null_mut(),
+        position.x + frame_origin.x - client_origin.x,
+        position.y + frame_origin.y - client_origin.y,
+        0,
+        0,
+        SWP_NOSIZE | SWP_NOZORDER | SWP_NOACTIVATE,
+    );
+}
+
+/// Where the client area should be placed for `WindowPosition`, or `None` if the window should
+/// stay where Windows put it.
+unsafe fn initial_client_origin(
+    hwnd: HWND, position: WindowPosition, window_info: &WindowInfo,
+) -> Option<PhyPoint> {
+    let size = window_info.physical_size();
+    let centered_in = |rect: RECT| {
+        PhyPoint::new(
+            rect.left + (rect.right - rect.left - size.width as i32) / 2,
+            rect.top + (rect.bottom - rect.top - size.height as i32) / 2,
+        )
+    };
+    let parent = window_parent(hwnd);
+    let centered_on_screen = || {
+        let mut monitor_info: MONITORINFO = std::mem::zeroed();
+        monitor_info.cbSize = std::mem::size_of::<MONITORINFO>() as u32;
+        let monitor = MonitorFromWindow(hwnd, MONITOR_DEFAULTTONEAREST);
+        if GetMonitorInfoW(monitor, &mut monitor_info) == 0 {
+            return None;
+        }
+
+        // The window's position is relative to its parent
+        let position = centered_in(monitor_info.rcWork);
+        let mut position = POINT { x: position.x, y: position.y };
+        if let Some(parent) = parent {
+            ScreenToClient(parent, &mut position);
+        }
+
+        Some(PhyPoint::new(position.x, position.y))
+    };
+
+    match position {
+        WindowPosition::Default => None,
+        WindowPosition::Absolute(position) => Some(position.to_physical(window_info)),
+        WindowPosition::CenteredOnScreen => centered_on_screen(),
+        WindowPosition::CenteredOnParent => match parent {
+            Some(parent) => {
+                let mut rect: RECT = std::mem::zeroed();
+                GetClientRect(parent, &mut rect);
+                Some(centered_in(rect))
+            }
+            None => centered_on_screen(),
+        },
+    }
+}
+
 unsafe fn on_dpi_changed(window_state_ptr: *mut RefCell<WindowState>, dpi: u32, hwnd: HWND) {
     let mut window_state = (*window_state_ptr).borrow_mut();
+    if window_state.scale_policy != WindowScalePolicy::SystemScaleFactor {
+        return;
+    }
+
     let mut window = window_state.create_window(hwnd);
     let mut window = crate::Window::new(&mut window);
 
@@ -144,6 +322,31 @@ unsafe fn on_dpi_changed(window_state_ptr: *mut RefCell<WindowState>, dpi: u32,
         .on_event(&mut window, Event::Window(WindowEvent::Resized(window_info)));
 }
 
+/// Send a `Moved` event for the client area position from a `WM_MOVE` message.
+unsafe fn on_moved(window_state_ptr: *mut RefCell<WindowState>, lparam: LPARAM, hwnd: HWND) {
+    // Minimized windows are moved far outside of the screen
+    if IsIconic(hwnd) != 0 {
+        return;
+    }
+
+    let mut window_state = match (*window_state_ptr).try_borrow_mut() {
+        Ok(window_state) => window_state,
+        // The handler moved the window itself, so the event is sent once it has returned
+        Err(_) => {
+            PostMessageW(hwnd, BV_WINDOW_MOVED, 0, lparam);
+            return;
+        }
+    };
+    let mut window = window_state.create_window(hwnd);
+    let mut window = crate::Window::new(&mut window);
+
+    let x = (lparam & 0xFFFF) as i16 as i32;
+    let y = ((lparam >> 16) & 0xFFFF) as i16 as i32;
+    let position = PhyPoint::new(x, y).to_logical(&window_state.window_info);
+
+    window_state.handler.on_event(&mut window, Event::Window(WindowEvent::Moved(position)));
+}
+
 unsafe extern "system" fn wnd_proc(
     hwnd: HWND, msg: UINT, wparam: WPARAM, lparam: LPARAM,
 ) -> LRESULT {
@@ -164,6 +367,22 @@ unsafe extern "system" fn wnd_proc(
                 let x = (lparam & 0xFFFF) as i16 as i32;
                 let y = ((lparam >> 16) & 0xFFFF) as i16 as i32;
 
+                // While the pointer is locked it's moved back after every movement. This also
+                // causes another move message at the locked position, which is ignored.
+                if let Some(locked_position) = window_state.locked_cursor_position.get() {
+                    let dx = x - locked_position.x;
+                    let dy = y - locked_position.y;
+                    if dx != 0 || dy != 0 {
+                        move_cursor(hwnd, locked_position);
+
+                        let event =
+                            Event::Mouse(MouseEvent::RawMotion { dx: dx as f64, dy: dy as f64 });
+                        window_state.handler.on_event(&mut window, event);
+                    }
+
+                    return 0;
+                }
+
                 let physical_pos = PhyPoint { x, y };
                 let logical_pos = physical_pos.to_logical(&window_state.window_info);
                 let event = Event::Mouse(MouseEvent::CursorMoved {
@@ -186,6 +405,7 @@ unsafe extern "system" fn wnd_proc(
 
                 let event = Event::Mouse(MouseEvent::WheelScrolled {
                     delta: ScrollDelta::Lines { x: 0.0, y: value },
+                    phase: ScrollPhase::Moved,
                     modifiers: window_state.keyboard_state.get_modifiers_from_mouse_wparam(wparam),
                 });
 
@@ -199,7 +419,7 @@ unsafe extern "system" fn wnd_proc(
                 let mut window = window_state.create_window(hwnd);
                 let mut window = crate::Window::new(&mut window);
 
-                let mut mouse_button_counter = window_state.mouse_button_counter;
+                let mut mouse_button_counter = window_state.mouse_button_counter.get();
 
                 let button = match msg {
                     WM_LBUTTONDOWN | WM_LBUTTONUP => Some(MouseButton::Left),
@@ -227,9 +447,10 @@ unsafe extern "system" fn wnd_proc(
                             }
                         }
                         WM_LBUTTONUP | WM_MBUTTONUP | WM_RBUTTONUP | WM_XBUTTONUP => {
-                            // Release the mouse cursor capture when all buttons are released
+                            // Release the mouse cursor capture when all buttons are released,
+                            // unless it was captured through `Window::capture_mouse()`
                             mouse_button_counter = mouse_button_counter.saturating_sub(1);
-                            if mouse_button_counter == 0 {
+                            if mouse_button_counter == 0 && !window_state.mouse_captured.get() {
                                 ReleaseCapture();
                             }
 
@@ -245,11 +466,24 @@ unsafe extern "system" fn wnd_proc(
                         }
                     };
 
-                    window_state.mouse_button_counter = mouse_button_counter;
+                    window_state.mouse_button_counter.set(mouse_button_counter);
 
                     window_state.handler.on_event(&mut window, Event::Mouse(event));
                 }
             }
+            WM_CAPTURECHANGED => {
+                // Another window took the capture, so the releases of the held buttons may never
+                // arrive. This is also sent while the window state is borrowed when we release the
+                // capture ourselves.
+                if lparam as HWND != hwnd {
+                    if let Ok(window_state) = (*window_state_ptr).try_borrow() {
+                        window_state.mouse_button_counter.set(0);
+                        window_state.mouse_captured.set(false);
+                    }
+                }
+
+                return 0;
+            }
             WM_TIMER => {
                 let dpi_changed = {
                     let mut window_state = (*window_state_ptr).borrow_mut();
@@ -285,6 +519,15 @@ unsafe extern "system" fn wnd_proc(
                     let mut window = window_state.create_window(hwnd);
                     let mut window = crate::Window::new(&mut window);
 
+                    let status = window_state
+                        .handler
+                        .on_event(&mut window, Event::Window(WindowEvent::CloseRequested));
+
+                    // The handler can veto the close request by capturing the event
+                    if status == EventStatus::Captured {
+                        return 0;
+                    }
+
                     window_state
                         .handler
                         .on_event(&mut window, Event::Window(WindowEvent::WillClose));
@@ -330,6 +573,9 @@ unsafe extern "system" fn wnd_proc(
                     .handler
                     .on_event(&mut window, Event::Window(WindowEvent::Resized(window_info)));
             }
+            WM_MOVE => {
+                on_moved(window_state_ptr, lparam, hwnd);
+            }
             WM_DPICHANGED => {
                 let dpi = {
                     let mut window_state = (*window_state_ptr).borrow_mut();
@@ -339,8 +585,63 @@ unsafe extern "system" fn wnd_proc(
                 };
                 on_dpi_changed(window_state_ptr, dpi, hwnd);
             }
+            WM_GETMINMAXINFO => {
+                // This can also be sent while the handler is resizing the window, in which case
+                // Windows' defaults are used
+                if let Ok(window_state) = (*window_state_ptr).try_borrow() {
+                    let constraints = window_state.size_constraints.get();
+                    let min_max_info = &mut *(lparam as *mut MINMAXINFO);
+
+                    if let Some(min_size) = constraints.min_size {
+                        min_max_info.ptMinTrackSize = window_state.outer_size(min_size);
+                    }
+                    if let Some(max_size) = constraints.max_size {
+                        min_max_info.ptMaxTrackSize = window_state.outer_size(max_size);
+                        min_max_info.ptMaxSize = min_max_info.ptMaxTrackSize;
+                    }
+
+                    return 0;
+                }
+            }
+            WM_SIZING => {
+                if let Ok(window_state) = (*window_state_ptr).try_borrow() {
+                    let constraints = window_state.size_constraints.get();
+                    let aspect_ratio = constraints.aspect_ratio.filter(|ratio| *ratio > 0.0);
+
+                    if let Some(aspect_ratio) = aspect_ratio {
+                        // The rectangle includes the window's frame, but the aspect ratio applies
+                        // to the client area
+                        let rect = &mut *(lparam as *mut RECT);
+                        let frame = window_state.outer_size(Size::new(0.0, 0.0));
+                        let width = (rect.right - rect.left - frame.x) as f64;
+                        let height = (rect.bottom - rect.top - frame.y) as f64;
+
+                        let edge = wparam as u32;
+                        if edge == WMSZ_TOP as u32 || edge == WMSZ_BOTTOM as u32 {
+                            let width = (height * aspect_ratio).round() as i32 + frame.x;
+                            rect.right = rect.left + width;
+                        } else {
+                            let height = (width / aspect_ratio).round() as i32 + frame.y;
+                            if edge == WMSZ_TOPLEFT as u32 || edge == WMSZ_TOPRIGHT as u32 {
+                                rect.top = rect.bottom - height;
+                            } else {
+                                rect.bottom = rect.top + height;
+                            }
+                        }
+
+                        return TRUE as LRESULT;
+                    }
+                }
+            }
+            WM_DESTROY => {
+                *(*window_state_ptr).borrow().proxy.alive.lock().unwrap() = false;
+            }
             WM_NCDESTROY => {
                 let window_state = Box::from_raw(window_state_ptr);
+                // The cursor would otherwise stay confined after the window is gone
+                if window_state.borrow().locked_cursor_position.get().is_some() {
+                    ClipCursor(std::ptr::null());
+                }
                 unregister_wnd_class(window_state.borrow().window_class);
                 SetWindowLongPtrW(hwnd, GWLP_USERDATA, 0);
             }
@@ -349,6 +650,83 @@ unsafe extern "system" fn wnd_proc(
                     DestroyWindow(hwnd);
                     return 0;
                 }
+
+                if msg == BV_WINDOW_MESSAGE {
+                    let mut window_state = (*window_state_ptr).borrow_mut();
+                    let mut window = window_state.create_window(hwnd);
+                    let mut window = crate::Window::new(&mut window);
+
+                    while let Ok(message) = window_state.message_rx.try_recv() {
+                        window_state.handler.on_message(&mut window, message);
+                    }
+
+                    return 0;
+                }
+
+                if msg == BV_WINDOW_WAKE {
+                    let mut window_state = (*window_state_ptr).borrow_mut();
+                    let mut window = window_state.create_window(hwnd);
+                    let mut window = crate::Window::new(&mut window);
+
+                    if window_state.proxy.woken.swap(false, Ordering::AcqRel) {
+                        window_state.handler.on_wake(&mut window);
+                    }
+
+                    return 0;
+                }
+
+                if msg == BV_WINDOW_MOVED {
+                    on_moved(window_state_ptr, lparam, hwnd);
+                    return 0;
+                }
+
+                if msg == BV_WINDOW_REDRAW {
+                    let mut window_state = (*window_state_ptr).borrow_mut();
+                    let mut window = window_state.create_window(hwnd);
+                    let mut window = crate::Window::new(&mut window);
+
+                    if window_state.redraw_requested.replace(false) {
+                        window_state.handler.on_redraw(&mut window);
+                    }
+
+                    return 0;
+                }
+
+                if msg == BV_WINDOW_SIZE_CONSTRAINTS_CHANGED {
+                    // Changing the style and size sends messages to the window right away, so
+                    // this can't be done while the handler holds on to the window state
+                    let (style, size) = {
+                        let mut window_state = (*window_state_ptr).borrow_mut();
+                        let constraints = window_state.size_constraints.get();
+
+                        // Parented windows are resized by their parent instead of by the user
+                        if window_state.dw_style & WS_CHILD == 0 {
+                            if constraints.resizable {
+                                window_state.dw_style |= WS_SIZEBOX | WS_MAXIMIZEBOX;
+                            } else {
+                                window_state.dw_style &= !(WS_SIZEBOX | WS_MAXIMIZEBOX);
+                            }
+                        }
+
+                        let window_info = window_state.window_info;
+                        let size = constraints.constrain(window_info.logical_size());
+
+                        (window_state.dw_style, window_state.outer_size(size))
+                    };
+
+                    SetWindowLongPtrW(hwnd, GWL_STYLE, style as _);
+                    SetWindowPos(
+                        hwnd,
+                        null_mut(),
+                        0,
+                        0,
+                        size.x,
+                        size.y,
+                        SWP_NOMOVE | SWP_NOZORDER | SWP_NOACTIVATE | SWP_FRAMECHANGED,
+                    );
+
+                    return 0;
+                }
             }
         }
     }
@@ -387,10 +765,24 @@ struct WindowState {
     window_info: WindowInfo,
     _parent_handle: Option<ParentHandle>,
     keyboard_state: KeyboardState,
-    mouse_button_counter: usize,
+    /// The number of mouse buttons currently held down. The mouse is captured while any button is
+    /// held so drags keep receiving events outside of the window.
+    mouse_button_counter: Rc<Cell<usize>>,
+    /// Whether the mouse was captured through `Window::capture_mouse()`
+    mouse_captured: Rc<Cell<bool>>,
     handler: Box<dyn WindowHandler>,
     dw_style: u32,
     dpi: u32,
+    scale_policy: WindowScalePolicy,
+    proxy: WindowProxy,
+    message_rx: mpsc::Receiver<Message>,
+    size_constraints: Rc<Cell<SizeConstraints>>,
+    /// Set by `Window::request_redraw()` so multiple calls before the handler runs result in a
+    /// single `on_redraw()` call.
+    redraw_requested: Rc<Cell<bool>>,
+    /// The cursor's physical position relative to the client area when the pointer was locked
+    /// through `Window::lock_pointer()`
+    locked_cursor_position: Rc<Cell<Option<PhyPoint>>>,
 
     #[cfg(feature = "opengl")]
     gl_context: Arc<Option<GlContext>>,
@@ -399,24 +791,95 @@ struct WindowState {
 impl WindowState {
     #[cfg(not(feature = "opengl"))]
     fn create_window(&self, hwnd: HWND) -> Window {
-        Window { hwnd }
+        Window {
+            hwnd,
+            window_info: self.window_info,
+            proxy: self.proxy.clone(),
+            size_constraints: self.size_constraints.clone(),
+            redraw_requested: self.redraw_requested.clone(),
+            locked_cursor_position: self.locked_cursor_position.clone(),
+            mouse_button_counter: self.mouse_button_counter.clone(),
+            mouse_captured: self.mouse_captured.clone(),
+        }
     }
 
     #[cfg(feature = "opengl")]
     fn create_window(&self, hwnd: HWND) -> Window {
-        Window { hwnd, gl_context: self.gl_context.clone() }
+        Window {
+            hwnd,
+            window_info: self.window_info,
+            proxy: self.proxy.clone(),
+            size_constraints: self.size_constraints.clone(),
+            redraw_requested: self.redraw_requested.clone(),
+            locked_cursor_position: self.locked_cursor_position.clone(),
+            mouse_button_counter: self.mouse_button_counter.clone(),
+            mouse_captured: self.mouse_captured.clone(),
+            gl_context: self.gl_context.clone(),
+        }
+    }
+
+    /// The physical size of the window including its frame for a logical client area size.
+    fn outer_size(&self, size: Size) -> POINT {
+        let size = size.to_physical(&self.window_info);
+        let mut rect =
+            RECT { left: 0, top: 0, right: size.width as i32, bottom: size.height as i32 };
+        unsafe {
+            AdjustWindowRectEx(&mut rect, self.dw_style, FALSE, 0);
+        }
+
+        POINT { x: rect.right - rect.left, y: rect.bottom - rect.top }
+    }
+}
+
+/// The size constraints from `WindowOpenOptions`, in logical sizes. These are shared with
+/// `Window` because the window state is borrowed while the handler runs.
+#[derive(Debug, Clone, Copy)]
+struct SizeConstraints {
+    min_size: Option<Size>,
+    max_size: Option<Size>,
+    resizable: bool,
+    aspect_ratio: Option<f64>,
+}
+
+impl SizeConstraints {
+    /// Constrain a size to the aspect ratio, and the minimum and maximum sizes. When these
+    /// conflict, the minimum and maximum sizes take precedence.
+    fn constrain(&self, size: Size) -> Size {
+        let mut size = size;
+        if let Some(aspect_ratio) = self.aspect_ratio.filter(|ratio| *ratio > 0.0) {
+            size.height = size.width / aspect_ratio;
+        }
+        if let Some(min_size) = self.min_size {
+            size.width = size.width.max(min_size.width);
+            size.height = size.height.max(min_size.height);
+        }
+        if let Some(max_size) = self.max_size {
+            size.width = size.width.min(max_size.width);
+            size.height = size.height.min(max_size.height);
+        }
+
+        size
     }
 }
 
 pub struct Window {
     hwnd: HWND,
+    window_info: WindowInfo,
+    proxy: WindowProxy,
+    size_constraints: Rc<Cell<SizeConstraints>>,
+    redraw_requested: Rc<Cell<bool>>,
+    locked_cursor_position: Rc<Cell<Option<PhyPoint>>>,
+    mouse_button_counter: Rc<Cell<usize>>,
+    mouse_captured: Rc<Cell<bool>>,
 
     #[cfg(feature = "opengl")]
     gl_context: Arc<Option<GlContext>>,
 }
 
 impl Window {
-    pub fn open_parented<P, H, B>(parent: &P, options: WindowOpenOptions, build: B) -> WindowHandle
+    pub fn open_parented<P, H, B>(
+        parent: &P, options: WindowOpenOptions, build: B,
+    ) -> Result<WindowHandle, Error>
     where
         P: HasRawWindowHandle,
         H: WindowHandler + 'static,
@@ -425,32 +888,39 @@ impl Window {
     {
         let parent = match parent.raw_window_handle() {
             RawWindowHandle::Win32(h) => h.hwnd as HWND,
-            h => panic!("unsupported parent handle {:?}", h),
+            h => {
+                return Err(Error::WindowCreationFailed(format!(
+                    "unsupported parent handle {:?}",
+                    h
+                )))
+            }
         };
 
-        let (window_handle, _) = Self::open(true, parent, options, build);
+        let (window_handle, _) = Self::open(true, parent, options, build)?;
 
-        window_handle
+        Ok(window_handle)
     }
 
-    pub fn open_as_if_parented<H, B>(options: WindowOpenOptions, build: B) -> WindowHandle
+    pub fn open_as_if_parented<H, B>(
+        options: WindowOpenOptions, build: B,
+    ) -> Result<WindowHandle, Error>
     where
         H: WindowHandler + 'static,
         B: FnOnce(&mut crate::Window) -> H,
         B: Send + 'static,
     {
-        let (window_handle, _) = Self::open(true, null_mut(), options, build);
+        let (window_handle, _) = Self::open(true, null_mut(), options, build)?;
 
-        window_handle
+        Ok(window_handle)
     }
 
-    pub fn open_blocking<H, B>(options: WindowOpenOptions, build: B)
+    pub fn open_blocking<H, B>(options: WindowOpenOptions, build: B) -> Result<(), Error>
     where
         H: WindowHandler + 'static,
         B: FnOnce(&mut crate::Window) -> H,
         B: Send + 'static,
     {
-        let (_, hwnd) = Self::open(false, null_mut(), options, build);
+        let (_, hwnd) = Self::open(false, null_mut(), options, build)?;
 
         unsafe {
             let mut msg: MSG = std::mem::zeroed();
@@ -466,11 +936,13 @@ impl Window {
                 DispatchMessageW(&msg);
             }
         }
+
+        Ok(())
     }
 
     fn open<H, B>(
         parented: bool, parent: HWND, options: WindowOpenOptions, build: B,
-    ) -> (WindowHandle, HWND)
+    ) -> Result<(WindowHandle, HWND), Error>
     where
         H: WindowHandler + 'static,
         B: FnOnce(&mut crate::Window) -> H,
@@ -481,7 +953,12 @@ impl Window {
             title.push(0);
 
             let window_class = register_wnd_class();
-            // todo: manage error ^
+            if window_class == 0 {
+                return Err(Error::WindowCreationFailed(format!(
+                    "RegisterClassW failed with error code {}",
+                    GetLastError()
+                )));
+            }
 
             let initial_scaling = 1.0; // NOTE: How to know the system scaling before creating the window?
             let window_info = WindowInfo::from_logical_size(options.size, initial_scaling);
@@ -496,7 +973,7 @@ impl Window {
 
             let flags = if parented {
                 WS_CHILD | WS_VISIBLE
-            } else {
+            } else if options.resizable {
                 WS_POPUPWINDOW
                     | WS_CAPTION
                     | WS_VISIBLE
@@ -504,6 +981,8 @@ impl Window {
                     | WS_MINIMIZEBOX
                     | WS_MAXIMIZEBOX
                     | WS_CLIPSIBLINGS
+            } else {
+                WS_POPUPWINDOW | WS_CAPTION | WS_VISIBLE | WS_MINIMIZEBOX | WS_CLIPSIBLINGS
             };
 
             if !parented {
@@ -524,24 +1003,75 @@ impl Window {
                 null_mut(),
                 null_mut(),
             );
-            // todo: manage error ^
+            if hwnd.is_null() {
+                let error_code = GetLastError();
+                unregister_wnd_class(window_class);
+
+                return Err(Error::WindowCreationFailed(format!(
+                    "CreateWindowExW failed with error code {}",
+                    error_code
+                )));
+            }
 
             #[cfg(feature = "opengl")]
-            let gl_context: Arc<Option<GlContext>> = Arc::new(options.gl_config.map(|gl_config| {
-                let mut handle = Win32Handle::empty();
-                handle.hwnd = hwnd as *mut c_void;
-                let handle = RawWindowHandleWrapper { handle: RawWindowHandle::Win32(handle) };
+            let gl_context = options
+                .gl_config
+                .map(|gl_config| {
+                    let mut handle = Win32Handle::empty();
+                    handle.hwnd = hwnd as *mut c_void;
+                    let handle = RawWindowHandleWrapper { handle: RawWindowHandle::Win32(handle) };
+
+                    GlContext::create(&handle, gl_config)
+                })
+                .transpose();
+            #[cfg(feature = "opengl")]
+            let gl_context: Arc<Option<GlContext>> = match gl_context {
+                Ok(gl_context) => Arc::new(gl_context),
+                Err(err) => {
+                    DestroyWindow(hwnd);
+                    unregister_wnd_class(window_class);
+
+                    return Err(Error::GlConfigFailed(format!("{:?}", err)));
+                }
+            };
 
-                GlContext::create(&handle, gl_config).expect("Could not create OpenGL context")
+            let (proxy, message_rx) = WindowProxy::new(hwnd);
+            let size_constraints = Rc::new(Cell::new(SizeConstraints {
+                min_size: options.min_size,
+                max_size: options.max_size,
+                resizable: options.resizable,
+                aspect_ratio: options.aspect_ratio,
             }));
+            let redraw_requested = Rc::new(Cell::new(false));
+            let locked_cursor_position = Rc::new(Cell::new(None));
+            let mouse_button_counter = Rc::new(Cell::new(0));
+            let mouse_captured = Rc::new(Cell::new(false));
 
-            let handler = Box::new(build(&mut crate::Window::new(&mut Window {
-                hwnd,
-                #[cfg(feature = "opengl")]
-                gl_context: gl_context.clone(),
-            })));
+            let handler = panic::catch_unwind(AssertUnwindSafe(|| {
+                build(&mut crate::Window::new(&mut Window {
+                    hwnd,
+                    window_info,
+                    proxy: proxy.clone(),
+                    size_constraints: size_constraints.clone(),
+                    redraw_requested: redraw_requested.clone(),
+                    locked_cursor_position: locked_cursor_position.clone(),
+                    mouse_button_counter: mouse_button_counter.clone(),
+                    mouse_captured: mouse_captured.clone(),
+                    #[cfg(feature = "opengl")]
+                    gl_context: gl_context.clone(),
+                }))
+            }));
+            let handler = match handler {
+                Ok(handler) => Box::new(handler),
+                Err(_) => {
+                    DestroyWindow(hwnd);
+                    unregister_wnd_class(window_class);
+
+                    return Err(Error::BuildPanicked);
+                }
+            };
 
-            let (parent_handle, window_handle) = ParentHandle::new(hwnd);
+            let (parent_handle, window_handle) = ParentHandle::new(hwnd, proxy.clone());
             let parent_handle = if parented { Some(parent_handle) } else { None };
 
             // Only works on Windows 10 unfortunately.
@@ -557,10 +1087,17 @@ impl Window {
                 window_info,
                 _parent_handle: parent_handle,
                 keyboard_state: KeyboardState::new(),
-                mouse_button_counter: 0,
+                mouse_button_counter,
+                mouse_captured,
                 handler,
                 dw_style: flags,
                 dpi,
+                scale_policy: options.scale,
+                proxy,
+                message_rx,
+                size_constraints,
+                redraw_requested,
+                locked_cursor_position,
 
                 #[cfg(feature = "opengl")]
                 gl_context,
@@ -571,7 +1108,10 @@ impl Window {
             // Adapt scale_factor without resizing actual window - we commit to the size we requested initially to prevent flickering etc.
             // TODO: Find out if that is actually fine.
 
-            let scale_factor = dpi as f64 / 96.0;
+            let scale_factor = match options.scale {
+                WindowScalePolicy::SystemScaleFactor => dpi as f64 / 96.0,
+                WindowScalePolicy::ScaleFactor(scale) => scale,
+            };
             let new_rect = if initial_scaling != scale_factor {
                 let mut window_state = window_state.get_mut();
                 window_state.window_info = WindowInfo::from_logical_size(
@@ -594,6 +1134,13 @@ impl Window {
                 None
             };
 
+            // This is done before the window state is attached, so the initial position isn't
+            // reported as a move
+            let window_info = window_state.get_mut().window_info;
+            if let Some(position) = initial_client_origin(hwnd, options.position, &window_info) {
+                set_client_origin(hwnd, position);
+            }
+
             SetWindowLongPtrW(hwnd, GWLP_USERDATA, Box::into_raw(window_state) as *const _ as _);
 
             if let Some(mut new_rect) = new_rect {
@@ -614,9 +1161,45 @@ impl Window {
                 );
             }
 
-            SetTimer(hwnd, WIN_FRAME_TIMER, 15, None);
+            SetTimer(hwnd, WIN_FRAME_TIMER, frame_timer_interval(options.frame_rate), None);
+
+            Ok((window_handle, hwnd))
+        }
+    }
+
+    pub fn position(&self) -> Point {
+        unsafe { client_origin(self.hwnd) }.to_logical(&self.window_info)
+    }
+
+    pub fn set_position(&mut self, position: Point) {
+        unsafe { set_client_origin(self.hwnd, position.to_physical(&self.window_info)) };
+    }
+
+    pub fn set_min_size(&mut self, min_size: Option<Size>) {
+        self.update_size_constraints(|constraints| constraints.min_size = min_size);
+    }
 
-            (window_handle, hwnd)
+    pub fn set_max_size(&mut self, max_size: Option<Size>) {
+        self.update_size_constraints(|constraints| constraints.max_size = max_size);
+    }
+
+    pub fn set_resizable(&mut self, resizable: bool) {
+        self.update_size_constraints(|constraints| constraints.resizable = resizable);
+    }
+
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: Option<f64>) {
+        self.update_size_constraints(|constraints| constraints.aspect_ratio = aspect_ratio);
+    }
+
+    /// Change the size constraints, and let the window procedure apply them to the window's
+    /// style and size once the handler has returned.
+    fn update_size_constraints(&self, f: impl FnOnce(&mut SizeConstraints)) {
+        let mut constraints = self.size_constraints.get();
+        f(&mut constraints);
+        self.size_constraints.set(constraints);
+
+        unsafe {
+            PostMessageW(self.hwnd, BV_WINDOW_SIZE_CONSTRAINTS_CHANGED, 0, 0);
         }
     }
 
@@ -629,18 +1212,114 @@ impl Window {
         }
     }
 
+    pub fn set_cursor_position(&mut self, position: Point) {
+        unsafe {
+            move_cursor(self.hwnd, position.to_physical(&self.window_info));
+        }
+    }
+
+    pub fn lock_pointer(&mut self) {
+        if self.locked_cursor_position.get().is_some() {
+            return;
+        }
+
+        unsafe {
+            let mut position = POINT { x: 0, y: 0 };
+            if GetCursorPos(&mut position) == 0 {
+                return;
+            }
+            ScreenToClient(self.hwnd, &mut position);
+            self.locked_cursor_position.set(Some(PhyPoint::new(position.x, position.y)));
+
+            // The cursor could otherwise leave the window before it has been moved back
+            let mut rect: RECT = std::mem::zeroed();
+            GetClientRect(self.hwnd, &mut rect);
+            let mut top_left = POINT { x: rect.left, y: rect.top };
+            let mut bottom_right = POINT { x: rect.right, y: rect.bottom };
+            ClientToScreen(self.hwnd, &mut top_left);
+            ClientToScreen(self.hwnd, &mut bottom_right);
+            ClipCursor(&RECT {
+                left: top_left.x,
+                top: top_left.y,
+                right: bottom_right.x,
+                bottom: bottom_right.y,
+            });
+        }
+    }
+
+    pub fn unlock_pointer(&mut self) {
+        if let Some(position) = self.locked_cursor_position.take() {
+            unsafe {
+                ClipCursor(std::ptr::null());
+                move_cursor(self.hwnd, position);
+            }
+        }
+    }
+
+    pub fn capture_mouse(&mut self) {
+        self.mouse_captured.set(true);
+        unsafe {
+            SetCapture(self.hwnd);
+        }
+    }
+
+    pub fn release_mouse(&mut self) {
+        self.mouse_captured.set(false);
+
+        // The mouse stays captured until all buttons are released
+        unsafe {
+            if self.mouse_button_counter.get() == 0 && GetCapture() == self.hwnd {
+                ReleaseCapture();
+            }
+        }
+    }
+
+    pub fn has_focus(&self) -> bool {
+        unsafe { GetFocus() == self.hwnd }
+    }
+
     pub fn close(&mut self) {
         unsafe {
             PostMessageW(self.hwnd, BV_WINDOW_MUST_CLOSE, 0, 0);
         }
     }
 
+    pub fn proxy(&self) -> WindowProxy {
+        self.proxy.clone()
+    }
+
+    pub fn request_redraw(&mut self) {
+        if !self.redraw_requested.replace(true) {
+            unsafe {
+                PostMessageW(self.hwnd, BV_WINDOW_REDRAW, 0, 0);
+            }
+        }
+    }
+
+    pub fn set_frame_rate(&mut self, frame_rate: FrameRate) {
+        unsafe {
+            // Calling `SetTimer()` with an existing timer ID replaces that timer
+            SetTimer(self.hwnd, WIN_FRAME_TIMER, frame_timer_interval(frame_rate), None);
+        }
+    }
+
     #[cfg(feature = "opengl")]
     pub fn gl_context(&self) -> Option<&GlContext> {
         self.gl_context.as_ref().as_ref()
     }
 }
 
+/// The frame timer's interval in milliseconds.
+fn frame_timer_interval(frame_rate: FrameRate) -> u32 {
+    // TODO: Query the monitor's refresh rate for `FrameRate::MonitorRefreshRate`
+    let interval = frame_rate
+        .fixed_interval()
+        .unwrap_or_else(|| Duration::from_secs_f64(1.0 / FrameRate::FALLBACK_HZ));
+
+    // `WM_TIMER` only has millisecond precision
+    (interval.as_secs_f64() * 1000.0).round().max(1.0) as u32
+}
+
 unsafe impl HasRawWindowHandle for Window {
     fn raw_window_handle(&self) -> RawWindowHandle {
         let mut handle = Win32Handle::empty();
@@ -1,11 +1,17 @@
+use std::any::Any;
 use std::marker::PhantomData;
 
 use raw_window_handle::{HasRawWindowHandle, RawWindowHandle};
 
 use crate::event::{Event, EventStatus};
-use crate::window_open_options::WindowOpenOptions;
-use crate::Size;
+use crate::window_open_options::{FrameRate, WindowOpenOptions};
+#[cfg(target_os = "linux")]
+use crate::ClipboardKind;
+use crate::Error;
 use crate::MouseCursor;
+#[cfg(target_os = "linux")]
+use crate::{DragData, DropAction};
+use crate::{Point, Size};
 
 #[cfg(target_os = "macos")]
 use crate::macos as platform;
@@ -22,13 +28,14 @@ pub struct WindowHandle {
 
 /// Quick wrapper to satisfy [HasRawWindowHandle], because of course a raw window handle wouldn't
 /// have a raw window handle, that would be silly.
+#[cfg(feature = "opengl")]
 pub(crate) struct RawWindowHandleWrapper {
     pub handle: RawWindowHandle,
 }
 
 impl WindowHandle {
     fn new(window_handle: platform::WindowHandle) -> Self {
-        Self { window_handle, phantom: PhantomData::default() }
+        Self { window_handle, phantom: PhantomData }
     }
 
     pub fn request_keyboard_focus(&mut self) {
@@ -39,7 +46,14 @@ impl WindowHandle {
         self.window_handle.resize(size);
     }
 
-    /// Close the window
+    /// Close the window.
+    ///
+    /// On Linux this is also done when the handle is dropped, and this blocks until the window
+    /// has received [`WindowEvent::WillClose`], has been destroyed and its thread has finished.
+    /// When called from the window's own thread this returns right away instead, and the window
+    /// closes once control returns to its event loop.
+    ///
+    /// [`WindowEvent::WillClose`]: crate::WindowEvent::WillClose
     pub fn close(&mut self) {
         self.window_handle.close();
     }
@@ -49,6 +63,11 @@ impl WindowHandle {
     pub fn is_open(&self) -> bool {
         self.window_handle.is_open()
     }
+
+    /// Get a [`WindowProxy`] for communicating with the window from other threads.
+    pub fn proxy(&self) -> WindowProxy {
+        WindowProxy { window_proxy: self.window_handle.proxy() }
+    }
 }
 
 unsafe impl HasRawWindowHandle for WindowHandle {
@@ -57,9 +76,52 @@ unsafe impl HasRawWindowHandle for WindowHandle {
     }
 }
 
+/// A handle for communicating with a window from other threads. This can be obtained through
+/// [`Window::proxy`] or [`WindowHandle::proxy`].
+#[derive(Clone)]
+pub struct WindowProxy {
+    window_proxy: platform::WindowProxy,
+}
+
+impl WindowProxy {
+    /// Send a message to the window. The window's handler receives it through
+    /// [`WindowHandler::on_message`] on the window's thread. Messages sent after the window has
+    /// been closed are dropped.
+    pub fn post<M: Any + Send>(&self, message: M) {
+        self.window_proxy.post(Box::new(message));
+    }
+
+    /// Wake up the window's event loop, which then calls [`WindowHandler::on_wake`] on the
+    /// window's thread. Multiple calls before the handler gets to run result in a single
+    /// `on_wake` call.
+    ///
+    /// Unlike [`post`](Self::post), this neither allocates nor blocks, so it can be called from
+    /// realtime threads such as an audio thread.
+    ///
+    /// # TODO
+    ///
+    /// On macOS this does not wake up the event loop yet, and `on_wake` is only called before the
+    /// next frame.
+    pub fn wake(&self) {
+        self.window_proxy.wake();
+    }
+}
+
 pub trait WindowHandler {
     fn on_frame(&mut self, window: &mut Window);
     fn on_event(&mut self, window: &mut Window, event: Event) -> EventStatus;
+
+    /// Called on the window's thread for every message sent through [`WindowProxy::post`].
+    #[allow(unused_variables)]
+    fn on_message(&mut self, window: &mut Window, message: Box<dyn Any + Send>) {}
+
+    /// Called on the window's thread after [`WindowProxy::wake`] has been called.
+    #[allow(unused_variables)]
+    fn on_wake(&mut self, window: &mut Window) {}
+
+    /// Called on the window's thread after [`Window::request_redraw`] has been called.
+    #[allow(unused_variables)]
+    fn on_redraw(&mut self, window: &mut Window) {}
 }
 
 pub struct Window<'a> {
@@ -69,32 +131,40 @@ pub struct Window<'a> {
 }
 
 impl<'a> Window<'a> {
-    pub(crate) fn new(window: &mut platform::Window) -> Window {
+    pub(crate) fn new(window: &mut platform::Window) -> Window<'_> {
         Window { window, phantom: PhantomData }
     }
 
-    pub fn open_parented<P, H, B>(parent: &P, options: WindowOpenOptions, build: B) -> WindowHandle
+    /// Open a window as a child of `parent`. Returns an error if the window could not be created,
+    /// in which case `build` may or may not have been called.
+    pub fn open_parented<P, H, B>(
+        parent: &P, options: WindowOpenOptions, build: B,
+    ) -> Result<WindowHandle, Error>
     where
         P: HasRawWindowHandle,
         H: WindowHandler + 'static,
         B: FnOnce(&mut Window) -> H,
         B: Send + 'static,
     {
-        let window_handle = platform::Window::open_parented::<P, H, B>(parent, options, build);
-        WindowHandle::new(window_handle)
+        let window_handle = platform::Window::open_parented::<P, H, B>(parent, options, build)?;
+        Ok(WindowHandle::new(window_handle))
     }
 
-    pub fn open_as_if_parented<H, B>(options: WindowOpenOptions, build: B) -> WindowHandle
+    pub fn open_as_if_parented<H, B>(
+        options: WindowOpenOptions, build: B,
+    ) -> Result<WindowHandle, Error>
     where
         H: WindowHandler + 'static,
         B: FnOnce(&mut Window) -> H,
         B: Send + 'static,
     {
-        let window_handle = platform::Window::open_as_if_parented::<H, B>(options, build);
-        WindowHandle::new(window_handle)
+        let window_handle = platform::Window::open_as_if_parented::<H, B>(options, build)?;
+        Ok(WindowHandle::new(window_handle))
     }
 
-    pub fn open_blocking<H, B>(options: WindowOpenOptions, build: B)
+    /// Open a window and block until it has been closed. Returns an error if the window could
+    /// not be created.
+    pub fn open_blocking<H, B>(options: WindowOpenOptions, build: B) -> Result<(), Error>
     where
         H: WindowHandler + 'static,
         B: FnOnce(&mut Window) -> H,
@@ -108,6 +178,93 @@ impl<'a> Window<'a> {
         self.window.close();
     }
 
+    /// Get the clipboard's contents as text. Returns `None` if the clipboard is empty or doesn't
+    /// contain text.
+    ///
+    /// This blocks until the application owning the clipboard has responded, or for at most
+    /// half a second.
+    #[cfg(target_os = "linux")]
+    pub fn clipboard_get_text(&mut self, kind: ClipboardKind) -> Option<String> {
+        self.window.clipboard_get_text(kind)
+    }
+
+    /// Replace the clipboard's contents with `text`.
+    #[cfg(target_os = "linux")]
+    pub fn clipboard_set_text(&mut self, kind: ClipboardKind, text: &str) {
+        self.window.clipboard_set_text(kind, text);
+    }
+
+    /// Get the clipboard's contents in the format described by `mime_type`, e.g.
+    /// `application/x-my-plugin-preset`. Returns `None` if the clipboard is empty or doesn't
+    /// contain data in that format.
+    ///
+    /// This blocks until the application owning the clipboard has responded, or for at most
+    /// half a second.
+    #[cfg(target_os = "linux")]
+    pub fn clipboard_get(&mut self, kind: ClipboardKind, mime_type: &str) -> Option<Vec<u8>> {
+        self.window.clipboard_get(kind, mime_type)
+    }
+
+    /// Replace the clipboard's contents with `data` in the format described by `mime_type`.
+    #[cfg(target_os = "linux")]
+    pub fn clipboard_set(&mut self, kind: ClipboardKind, mime_type: &str, data: Vec<u8>) {
+        self.window.clipboard_set(kind, mime_type, data);
+    }
+
+    /// Start dragging `data` out of the window. This should be called while a mouse button is
+    /// held down, and the data is dropped when that button is released. `allowed_actions` lists
+    /// the actions the drop target may perform in order of preference. If more than one action is
+    /// allowed, the drop target may also let the user choose between them. Dragged files should
+    /// use absolute paths.
+    ///
+    /// The outcome is reported through [`DragDropEvent::DragEnded`]. Nothing happens if another
+    /// drag from this window is still in progress.
+    ///
+    /// [`DragDropEvent::DragEnded`]: crate::DragDropEvent::DragEnded
+    #[cfg(target_os = "linux")]
+    pub fn start_drag(&mut self, data: DragData, allowed_actions: &[DropAction]) {
+        self.window.start_drag(data, allowed_actions);
+    }
+
+    /// Enable or disable the input method for this window. While it's enabled, key presses may be
+    /// used to compose text, which is sent through [`Event::Ime`] events instead of as keyboard
+    /// events. This is disabled by default, and should be enabled while a text field has focus.
+    ///
+    /// The connection to the input method is opened the first time this is enabled. Input methods
+    /// need the host to have set the locale with `setlocale(LC_CTYPE, "")`, otherwise the window
+    /// only receives keyboard events.
+    #[cfg(target_os = "linux")]
+    pub fn set_ime_allowed(&mut self, allowed: bool) {
+        self.window.set_ime_allowed(allowed);
+    }
+
+    /// Tell the input method where the text being edited is, so it can place its candidate
+    /// window next to it. The position is relative to the window.
+    #[cfg(target_os = "linux")]
+    pub fn set_ime_cursor_area(&mut self, position: Point, size: Size) {
+        self.window.set_ime_cursor_area(position, size);
+    }
+
+    /// Schedule a call to [`WindowHandler::on_redraw`]. Multiple requests made before the
+    /// handler runs result in a single call.
+    ///
+    /// # TODO
+    ///
+    /// On macOS the handler only runs before the next frame.
+    pub fn request_redraw(&mut self) {
+        self.window.request_redraw();
+    }
+
+    /// Change how often [`WindowHandler::on_frame`] is called.
+    pub fn set_frame_rate(&mut self, frame_rate: FrameRate) {
+        self.window.set_frame_rate(frame_rate);
+    }
+
+    /// Get a [`WindowProxy`] for communicating with this window from other threads.
+    pub fn proxy(&self) -> WindowProxy {
+        WindowProxy { window_proxy: self.window.proxy() }
+    }
+
     /// Resize the window to the given size.
     ///
     /// # TODO
@@ -118,11 +275,93 @@ impl<'a> Window<'a> {
         self.window.resize(size);
     }
 
+    /// The logical position of the window's top left corner, relative to the parent window for
+    /// parented windows and relative to the screen otherwise.
+    pub fn position(&self) -> Point {
+        self.window.position()
+    }
+
+    /// Move the window's top left corner to the given logical position, relative to the parent
+    /// window for parented windows and relative to the screen otherwise.
+    pub fn set_position(&mut self, position: Point) {
+        self.window.set_position(position);
+    }
+
+    /// Set the minimum logical size of the window, or remove the constraint with `None`.
+    pub fn set_min_size(&mut self, min_size: Option<Size>) {
+        self.window.set_min_size(min_size);
+    }
+
+    /// Set the maximum logical size of the window, or remove the constraint with `None`.
+    pub fn set_max_size(&mut self, max_size: Option<Size>) {
+        self.window.set_max_size(max_size);
+    }
+
+    /// Set whether the user can resize the window.
+    pub fn set_resizable(&mut self, resizable: bool) {
+        self.window.set_resizable(resizable);
+    }
+
+    /// Constrain the window's size to a fixed aspect ratio (width divided by height), or remove
+    /// the constraint with `None`.
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: Option<f64>) {
+        self.window.set_aspect_ratio(aspect_ratio);
+    }
+
     /// Set the cursor to the given cursor type
     pub fn set_mouse_cursor(&mut self, cursor: MouseCursor) {
         self.window.set_mouse_cursor(cursor);
     }
 
+    /// Move the cursor to a position relative to the window.
+    #[cfg(any(target_os = "linux", target_os = "windows"))]
+    pub fn set_cursor_position(&mut self, position: Point) {
+        self.window.set_cursor_position(position);
+    }
+
+    /// Keep the cursor in place and report the pointer's movement through
+    /// [`MouseEvent::RawMotion`](crate::MouseEvent::RawMotion) events instead, until
+    /// [`unlock_pointer`](Self::unlock_pointer) is called. This is useful for dragging knobs
+    /// without being limited by the edges of the screen, usually combined with
+    /// [`MouseCursor::Hidden`].
+    #[cfg(any(target_os = "linux", target_os = "windows"))]
+    pub fn lock_pointer(&mut self) {
+        self.window.lock_pointer();
+    }
+
+    /// Stop locking the pointer and move the cursor back to where it was when the pointer was
+    /// locked.
+    #[cfg(any(target_os = "linux", target_os = "windows"))]
+    pub fn unlock_pointer(&mut self) {
+        self.window.unlock_pointer();
+    }
+
+    /// Keep receiving mouse events when the pointer leaves the window, until
+    /// [`release_mouse`](Self::release_mouse) is called. The mouse is already captured while any
+    /// mouse button is held down, so this is only needed for drags that don't start with a button
+    /// press.
+    #[cfg(any(target_os = "linux", target_os = "windows"))]
+    pub fn capture_mouse(&mut self) {
+        self.window.capture_mouse();
+    }
+
+    /// Stop capturing the mouse after [`capture_mouse`](Self::capture_mouse). If a mouse button is
+    /// still held down, the mouse stays captured until it's released.
+    #[cfg(any(target_os = "linux", target_os = "windows"))]
+    pub fn release_mouse(&mut self) {
+        self.window.release_mouse();
+    }
+
+    /// Whether the window currently has the keyboard focus. This changes together with the
+    /// [`WindowEvent::Focused`](crate::WindowEvent::Focused) and
+    /// [`WindowEvent::Unfocused`](crate::WindowEvent::Unfocused) events.
+    ///
+    /// # TODO
+    ///
+    /// Those events are currently only sent on Linux.
+    pub fn has_focus(&self) -> bool {
+        self.window.has_focus()
+    }
 
     /// If provided, then an OpenGL context will be created for this window. You'll be able to
     /// access this context through [crate::Window::gl_context].
@@ -138,6 +377,7 @@ unsafe impl<'a> HasRawWindowHandle for Window<'a> {
     }
 }
 
+#[cfg(feature = "opengl")]
 unsafe impl HasRawWindowHandle for RawWindowHandleWrapper {
     fn raw_window_handle(&self) -> RawWindowHandle {
         self.handle
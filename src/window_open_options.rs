@@ -1,13 +1,100 @@
-use crate::Size;
+use std::time::Duration;
+
+use crate::{Point, Size};
+
+/// The dpi scaling policy of the window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowScalePolicy {
+    /// Use the system's dpi scale factor
+    SystemScaleFactor,
+    /// Use the given dpi scale factor (e.g. `1.0` = 96 dpi)
+    ScaleFactor(f64),
+}
+
+/// How often [`WindowHandler::on_frame`](crate::WindowHandler::on_frame) should be called
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameRate {
+    /// Call `on_frame` at a fixed rate, in Hz
+    Fixed(f64),
+    /// Call `on_frame` at the refresh rate of the monitor the window is on. If the refresh rate
+    /// cannot be determined, 60 Hz is used instead.
+    ///
+    /// # TODO
+    ///
+    /// This is currently not supported on Windows, which always uses 60 Hz. On macOS this requires
+    /// macOS 12, and the refresh rate is only queried when the window is opened and when the frame
+    /// rate is changed.
+    MonitorRefreshRate,
+}
+
+impl FrameRate {
+    /// The frame rate used when the monitor's refresh rate cannot be determined
+    pub(crate) const FALLBACK_HZ: f64 = 60.0;
+
+    /// The interval between two frames, or `None` if the frame rate depends on the monitor.
+    pub(crate) fn fixed_interval(&self) -> Option<Duration> {
+        match self {
+            FrameRate::Fixed(hz) if *hz > 0.0 && hz.is_finite() => {
+                Some(Duration::from_secs_f64(1.0 / hz))
+            }
+            FrameRate::Fixed(_) => Some(Duration::from_secs_f64(1.0 / Self::FALLBACK_HZ)),
+            FrameRate::MonitorRefreshRate => None,
+        }
+    }
+}
+
+/// The initial position of the window
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WindowPosition {
+    /// Let the platform (or the window manager) decide where to place the window
+    #[default]
+    Default,
+    /// Place the window's top left corner at the given logical position, relative to the parent
+    /// window for parented windows and relative to the screen otherwise
+    Absolute(Point),
+    /// Center the window on the screen
+    CenteredOnScreen,
+    /// Center the window within its parent window. Behaves like `CenteredOnScreen` for windows
+    /// that aren't parented.
+    CenteredOnParent,
+}
 
 /// The options for opening a new window
 #[derive(Debug, Clone)]
 pub struct WindowOpenOptions {
     pub title: String,
 
-    /// The physical size of the window.
+    /// The logical size of the window.
+    ///
+    /// These dimensions will be scaled by the scaling policy specified in `scale`. Mouse
+    /// position will be passed back as logical coordinates.
     pub size: Size,
 
+    /// The initial position of the window.
+    pub position: WindowPosition,
+
+    /// The minimum logical size of the window, if any.
+    pub min_size: Option<Size>,
+
+    /// The maximum logical size of the window, if any.
+    pub max_size: Option<Size>,
+
+    /// Whether the user can resize the window. This only affects standalone windows, a parented
+    /// window's size is always determined by its parent.
+    pub resizable: bool,
+
+    /// A fixed aspect ratio (width divided by height) the window's size should be constrained to,
+    /// if any.
+    pub aspect_ratio: Option<f64>,
+
+    /// The dpi scaling policy
+    ///
+    /// This is ignored on macOS, where the system handles the scaling on its own.
+    pub scale: WindowScalePolicy,
+
+    /// How often `on_frame` should be called
+    pub frame_rate: FrameRate,
+
     /// If provided, then an OpenGL context will be created for this window. You'll be able to
     /// access this context through [crate::Window::gl_context].
     #[cfg(feature = "opengl")]
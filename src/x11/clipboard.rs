@@ -0,0 +1,439 @@
+//! Clipboard support through X11 selections. See the ICCCM's section on selections for the
+//! protocol:
+//! <https://www.x.org/releases/X11R7.7/doc/xorg-docs/icccm/icccm.html#Peer_to_Peer_Communication_by_Means_of_Selections>
+
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use nix::poll::{poll, PollFd, PollFlags};
+
+use super::XcbConnection;
+use crate::ClipboardKind;
+
+/// How long to wait for the selection owner to respond before giving up. This is the total for
+/// all targets that are tried. Once an INCR transfer is in progress, each chunk may take this long.
+pub(super) const TRANSFER_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long an INCR transfer to another client waits for the requestor to delete the previous chunk
+/// before it's abandoned.
+const INCR_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The largest chunk of data that's sent at once. Larger data is sent in multiple parts using
+/// INCR transfers.
+const MAX_CHUNK_SIZE: usize = 256 * 1024;
+
+/// The targets text is offered as. `STRING`, `TEXT` and `text/plain` are only offered for ASCII
+/// text, since they're not necessarily UTF-8.
+const UTF8_TEXT_TARGETS: &[&str] = &["UTF8_STRING", "text/plain;charset=utf-8"];
+const ASCII_TEXT_TARGETS: &[&str] = &["STRING", "TEXT", "text/plain"];
+
+pub(super) struct Clipboard {
+    /// The `CLIPBOARD` selection. `PRIMARY` is predefined.
+    clipboard: u32,
+    targets: u32,
+    incr: u32,
+    /// The property on our window the selection owner stores the converted data in
+    transfer_property: u32,
+
+    /// The data we offer for each selection this window owns, by target
+    owned: HashMap<u32, HashMap<u32, Rc<[u8]>>>,
+    /// INCR transfers to other clients that are in progress
+    incr_transfers: Vec<IncrTransfer>,
+}
+
+/// An INCR transfer to another client. The next chunk is sent once the requestor has deleted the
+/// property containing the previous one.
+struct IncrTransfer {
+    selection: u32,
+    requestor: u32,
+    property: u32,
+    target: u32,
+    data: Rc<[u8]>,
+    offset: usize,
+    /// When the transfer is abandoned if the requestor hasn't deleted the last chunk
+    deadline: Instant,
+}
+
+impl Clipboard {
+    pub fn new(xcb_connection: &XcbConnection) -> Self {
+        Self {
+            clipboard: xcb_connection.intern_atom("CLIPBOARD"),
+            targets: xcb_connection.intern_atom("TARGETS"),
+            incr: xcb_connection.intern_atom("INCR"),
+            transfer_property: xcb_connection.intern_atom("BASEVIEW_SELECTION"),
+
+            owned: HashMap::new(),
+            incr_transfers: Vec::new(),
+        }
+    }
+
+    fn selection(&self, kind: ClipboardKind) -> u32 {
+        match kind {
+            ClipboardKind::Clipboard => self.clipboard,
+            ClipboardKind::Primary => xcb::ATOM_PRIMARY,
+        }
+    }
+
+    pub fn get_text(
+        &mut self, xcb_connection: &XcbConnection, window_id: u32,
+        pending_events: &mut VecDeque<xcb::GenericEvent>, kind: ClipboardKind,
+    ) -> Option<String> {
+        let selection = self.selection(kind);
+        let deadline = Instant::now() + TRANSFER_TIMEOUT;
+        let mut get = |target| {
+            self.get_selection(
+                xcb_connection,
+                window_id,
+                pending_events,
+                selection,
+                target,
+                xcb::CURRENT_TIME,
+                deadline,
+            )
+        };
+
+        for target in UTF8_TEXT_TARGETS {
+            if let Some(data) = get(xcb_connection.intern_atom(target)) {
+                return Some(String::from_utf8_lossy(&data).into_owned());
+            }
+        }
+
+        // `STRING` is encoded as Latin-1, which maps directly onto the first 256 code points
+        let data = get(xcb::ATOM_STRING)?;
+        Some(data.iter().map(|&byte| byte as char).collect())
+    }
+
+    pub fn set_text(
+        &mut self, xcb_connection: &XcbConnection, window_id: u32, kind: ClipboardKind, text: &str,
+        time: u32,
+    ) {
+        let contents = Self::text_contents(xcb_connection, text);
+
+        self.set(xcb_connection, window_id, kind, contents, time);
+    }
+
+    /// The targets and data text is offered as.
+    pub fn text_contents(xcb_connection: &XcbConnection, text: &str) -> HashMap<u32, Rc<[u8]>> {
+        let data: Rc<[u8]> = text.as_bytes().into();
+
+        let mut contents = HashMap::new();
+        for target in UTF8_TEXT_TARGETS {
+            contents.insert(xcb_connection.intern_atom(target), data.clone());
+        }
+        if text.is_ascii() {
+            for target in ASCII_TEXT_TARGETS {
+                contents.insert(xcb_connection.intern_atom(target), data.clone());
+            }
+        }
+
+        contents
+    }
+
+    pub fn get(
+        &mut self, xcb_connection: &XcbConnection, window_id: u32,
+        pending_events: &mut VecDeque<xcb::GenericEvent>, kind: ClipboardKind, target: u32,
+    ) -> Option<Vec<u8>> {
+        let selection = self.selection(kind);
+
+        self.get_selection(
+            xcb_connection,
+            window_id,
+            pending_events,
+            selection,
+            target,
+            xcb::CURRENT_TIME,
+            Instant::now() + TRANSFER_TIMEOUT,
+        )
+    }
+
+    /// Request the selection's contents converted to `target`. This blocks until the selection
+    /// owner has responded or until `deadline` has passed. Any unrelated events received in the
+    /// meantime are stored in `pending_events`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn get_selection(
+        &mut self, xcb_connection: &XcbConnection, window_id: u32,
+        pending_events: &mut VecDeque<xcb::GenericEvent>, selection: u32, target: u32, time: u32,
+        deadline: Instant,
+    ) -> Option<Vec<u8>> {
+        let conn = &xcb_connection.conn;
+
+        // We can't answer our own requests while we're blocking here
+        if let Some(contents) = self.owned.get(&selection) {
+            return contents.get(&target).map(|data| data.to_vec());
+        }
+
+        let property = self.transfer_property;
+        xcb::delete_property(conn, window_id, property);
+        xcb::convert_selection(conn, window_id, selection, target, property, time);
+        conn.flush();
+
+        let notify = wait_for_event(conn, pending_events, deadline, |event| {
+            if event.response_type() & !0x80 != xcb::SELECTION_NOTIFY {
+                return false;
+            }
+
+            let event = unsafe { xcb::cast_event::<xcb::SelectionNotifyEvent>(event) };
+            event.requestor() == window_id && event.selection() == selection
+        })?;
+        let notify = unsafe { xcb::cast_event::<xcb::SelectionNotifyEvent>(&notify) };
+
+        // The owner couldn't convert the selection to the requested target
+        if notify.property() == xcb::NONE {
+            return None;
+        }
+
+        // Deleting the property also tells the owner to start an INCR transfer
+        let reply = xcb::get_property(
+            conn,
+            true,
+            window_id,
+            property,
+            xcb::GET_PROPERTY_TYPE_ANY,
+            0,
+            u32::MAX / 4,
+        )
+        .get_reply()
+        .ok()?;
+        if reply.type_() != self.incr {
+            return if reply.format() == 8 { Some(reply.value::<u8>().to_vec()) } else { None };
+        }
+        conn.flush();
+
+        // The data is sent in chunks, each of which replaces the property. The end of the transfer
+        // is marked by an empty chunk.
+        let mut data = Vec::new();
+        loop {
+            let chunk_deadline = Instant::now() + TRANSFER_TIMEOUT;
+            wait_for_event(conn, pending_events, chunk_deadline, |event| {
+                if event.response_type() & !0x80 != xcb::PROPERTY_NOTIFY {
+                    return false;
+                }
+
+                let event = unsafe { xcb::cast_event::<xcb::PropertyNotifyEvent>(event) };
+                event.window() == window_id
+                    && event.atom() == property
+                    && event.state() == xcb::PROPERTY_NEW_VALUE as u8
+            })?;
+
+            let reply = xcb::get_property(
+                conn,
+                true,
+                window_id,
+                property,
+                xcb::GET_PROPERTY_TYPE_ANY,
+                0,
+                u32::MAX / 4,
+            )
+            .get_reply()
+            .ok()?;
+            conn.flush();
+
+            let chunk = reply.value::<u8>();
+            if chunk.is_empty() {
+                return Some(data);
+            }
+            data.extend_from_slice(chunk);
+        }
+    }
+
+    pub fn set(
+        &mut self, xcb_connection: &XcbConnection, window_id: u32, kind: ClipboardKind,
+        contents: HashMap<u32, Rc<[u8]>>, time: u32,
+    ) {
+        let selection = self.selection(kind);
+
+        self.set_selection(xcb_connection, window_id, selection, contents, time);
+    }
+
+    /// Take ownership of the selection and offer `contents`, which maps targets to data. `time`
+    /// should be the timestamp of the event that caused this, since the ICCCM doesn't allow
+    /// `CurrentTime` here. Returns `false` if another client took ownership in the meantime.
+    pub fn set_selection(
+        &mut self, xcb_connection: &XcbConnection, window_id: u32, selection: u32,
+        contents: HashMap<u32, Rc<[u8]>>, time: u32,
+    ) -> bool {
+        let conn = &xcb_connection.conn;
+
+        xcb::set_selection_owner(conn, window_id, selection, time);
+        let owner = xcb::get_selection_owner(conn, selection)
+            .get_reply()
+            .map(|r| r.owner())
+            .unwrap_or(xcb::NONE);
+
+        if owner == window_id {
+            self.owned.insert(selection, contents);
+            true
+        } else {
+            self.owned.remove(&selection);
+            self.incr_transfers.retain(|transfer| transfer.selection != selection);
+            false
+        }
+    }
+
+    /// Answer another client's request for one of our selections.
+    pub fn handle_selection_request(
+        &mut self, xcb_connection: &XcbConnection, event: &xcb::SelectionRequestEvent,
+    ) {
+        let conn = &xcb_connection.conn;
+        let requestor = event.requestor();
+        let target = event.target();
+        // Obsolete clients don't specify a property, in which case the target should be used
+        let property = if event.property() == xcb::NONE { target } else { event.property() };
+
+        // A new request for the same property replaces a transfer the requestor abandoned
+        let now = Instant::now();
+        self.incr_transfers.retain(|transfer| {
+            (transfer.requestor != requestor || transfer.property != property)
+                && transfer.deadline > now
+        });
+
+        let converted = match self.owned.get(&event.selection()) {
+            Some(contents) if target == self.targets => {
+                let mut targets = vec![self.targets];
+                targets.extend(contents.keys());
+
+                xcb::change_property(
+                    conn,
+                    xcb::PROP_MODE_REPLACE as u8,
+                    requestor,
+                    property,
+                    xcb::ATOM_ATOM,
+                    32,
+                    &targets,
+                );
+
+                true
+            }
+            Some(contents) => match contents.get(&target) {
+                Some(data) if data.len() > MAX_CHUNK_SIZE => {
+                    // The requestor deleting the property starts the transfer
+                    xcb::change_window_attributes(
+                        conn,
+                        requestor,
+                        &[(xcb::CW_EVENT_MASK, xcb::EVENT_MASK_PROPERTY_CHANGE)],
+                    );
+                    xcb::change_property(
+                        conn,
+                        xcb::PROP_MODE_REPLACE as u8,
+                        requestor,
+                        property,
+                        self.incr,
+                        32,
+                        &[data.len() as u32],
+                    );
+
+                    self.incr_transfers.push(IncrTransfer {
+                        selection: event.selection(),
+                        requestor,
+                        property,
+                        target,
+                        data: data.clone(),
+                        offset: 0,
+                        deadline: now + INCR_TIMEOUT,
+                    });
+
+                    true
+                }
+                Some(data) => {
+                    xcb::change_property(
+                        conn,
+                        xcb::PROP_MODE_REPLACE as u8,
+                        requestor,
+                        property,
+                        target,
+                        8,
+                        data,
+                    );
+
+                    true
+                }
+                None => false,
+            },
+            None => false,
+        };
+
+        let notify = xcb::SelectionNotifyEvent::new(
+            event.time(),
+            requestor,
+            event.selection(),
+            target,
+            if converted { property } else { xcb::NONE },
+        );
+        xcb::send_event(conn, false, requestor, xcb::EVENT_MASK_NO_EVENT, &notify);
+        conn.flush();
+    }
+
+    /// Another client has taken ownership of one of our selections.
+    pub fn handle_selection_clear(&mut self, event: &xcb::SelectionClearEvent) {
+        self.owned.remove(&event.selection());
+        self.incr_transfers.retain(|transfer| transfer.selection != event.selection());
+    }
+
+    /// Continue an INCR transfer if the requestor has deleted the previous chunk.
+    pub fn handle_property_notify(
+        &mut self, xcb_connection: &XcbConnection, event: &xcb::PropertyNotifyEvent,
+    ) {
+        if event.state() != xcb::PROPERTY_DELETE as u8 {
+            return;
+        }
+
+        let now = Instant::now();
+        self.incr_transfers.retain(|transfer| transfer.deadline > now);
+
+        let index = match self.incr_transfers.iter().position(|transfer| {
+            transfer.requestor == event.window() && transfer.property == event.atom()
+        }) {
+            Some(index) => index,
+            None => return,
+        };
+
+        let transfer = &mut self.incr_transfers[index];
+        let end = (transfer.offset + MAX_CHUNK_SIZE).min(transfer.data.len());
+        xcb::change_property(
+            &xcb_connection.conn,
+            xcb::PROP_MODE_REPLACE as u8,
+            transfer.requestor,
+            transfer.property,
+            transfer.target,
+            8,
+            &transfer.data[transfer.offset..end],
+        );
+        xcb_connection.conn.flush();
+
+        // The empty chunk marking the end of the transfer has been sent
+        if transfer.offset == end {
+            self.incr_transfers.remove(index);
+        } else {
+            transfer.offset = end;
+            transfer.deadline = now + INCR_TIMEOUT;
+        }
+    }
+}
+
+/// Block until an event matching `predicate` is received or until `deadline` has passed. Other
+/// events are appended to `pending_events`.
+fn wait_for_event(
+    conn: &xcb::Connection, pending_events: &mut VecDeque<xcb::GenericEvent>, deadline: Instant,
+    mut predicate: impl FnMut(&xcb::GenericEvent) -> bool,
+) -> Option<xcb::GenericEvent> {
+    let xcb_fd = unsafe { xcb::ffi::xcb_get_file_descriptor(conn.get_raw_conn()) };
+
+    loop {
+        while let Some(event) = conn.poll_for_event() {
+            if predicate(&event) {
+                return Some(event);
+            }
+
+            pending_events.push_back(event);
+        }
+
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if timeout.is_zero() {
+            return None;
+        }
+
+        let timeout_ms = timeout.as_millis().clamp(1, i32::MAX as u128) as i32;
+        poll(&mut [PollFd::new(xcb_fd, PollFlags::POLLIN)], timeout_ms).ok()?;
+    }
+}
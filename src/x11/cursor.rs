@@ -1,6 +1,7 @@
-use std::os::raw::c_char;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int};
 
-use crate::MouseCursor;
+use crate::{CursorImage, CustomCursor, MouseCursor};
 
 fn create_empty_cursor(display: *mut x11::xlib::Display) -> Option<u32> {
     let data = 0;
@@ -34,9 +35,24 @@ fn create_empty_cursor(display: *mut x11::xlib::Display) -> Option<u32> {
     }
 }
 
-fn load_cursor(display: *mut x11::xlib::Display, name: &[u8]) -> Option<u32> {
-    let xcursor =
-        unsafe { x11::xcursor::XcursorLibraryLoadCursor(display, name.as_ptr() as *const c_char) };
+/// Load a cursor from the given theme, or from the default theme if `theme` is `None` or the
+/// theme doesn't contain the cursor. The theme's image closest to `size` is used.
+fn load_cursor(
+    display: *mut x11::xlib::Display, name: &[u8], theme: Option<&CStr>, size: i32,
+) -> Option<u32> {
+    let xcursor = unsafe {
+        let theme = theme.map_or(std::ptr::null(), CStr::as_ptr);
+        let images =
+            x11::xcursor::XcursorLibraryLoadImages(name.as_ptr() as *const c_char, theme, size);
+        if images.is_null() {
+            return None;
+        }
+
+        let xcursor = x11::xcursor::XcursorImagesLoadCursor(display, images);
+        x11::xcursor::XcursorImagesDestroy(images);
+
+        xcursor
+    };
 
     if xcursor == 0 {
         None
@@ -45,17 +61,63 @@ fn load_cursor(display: *mut x11::xlib::Display, name: &[u8]) -> Option<u32> {
     }
 }
 
-fn load_first_existing_cursor(display: *mut x11::xlib::Display, names: &[&[u8]]) -> Option<u32> {
+fn load_first_existing_cursor(
+    display: *mut x11::xlib::Display, names: &[&[u8]], theme: Option<&CStr>, size: i32,
+) -> Option<u32> {
     names
         .iter()
-        .map(|name| load_cursor(display, name))
+        .map(|name| load_cursor(display, name, theme, size))
         .find(|xcursor| xcursor.is_some())
         .unwrap_or(None)
 }
 
-pub(super) fn get_xcursor(display: *mut x11::xlib::Display, cursor: MouseCursor) -> u32 {
-    let load = |name: &[u8]| load_cursor(display, name);
-    let loadn = |names: &[&[u8]]| load_first_existing_cursor(display, names);
+/// Pick the index of the image to use for a custom cursor at the given scale factor.
+pub(super) fn custom_cursor_image(cursor: &CustomCursor, scale: f64) -> usize {
+    let images = cursor.images();
+    let width = images[0].width() as f64 * scale;
+
+    images.iter().position(|image| image.width() as f64 >= width).unwrap_or(images.len() - 1)
+}
+
+pub(super) fn create_custom_cursor(display: *mut x11::xlib::Display, image: &CursorImage) -> u32 {
+    unsafe {
+        let xcursor_image =
+            x11::xcursor::XcursorImageCreate(image.width() as c_int, image.height() as c_int);
+        if xcursor_image.is_null() {
+            return 0;
+        }
+
+        let (hotspot_x, hotspot_y) = image.hotspot();
+        (*xcursor_image).xhot = hotspot_x;
+        (*xcursor_image).yhot = hotspot_y;
+
+        // Xcursor wants ARGB pixels with premultiplied alpha
+        let pixels = std::slice::from_raw_parts_mut(
+            (*xcursor_image).pixels,
+            image.width() as usize * image.height() as usize,
+        );
+        for (pixel, rgba) in pixels.iter_mut().zip(image.rgba().chunks_exact(4)) {
+            let alpha = rgba[3] as u32;
+            let premultiply = |channel: u8| (channel as u32 * alpha + 127) / 255;
+            *pixel = alpha << 24
+                | premultiply(rgba[0]) << 16
+                | premultiply(rgba[1]) << 8
+                | premultiply(rgba[2]);
+        }
+
+        let cursor = x11::xcursor::XcursorImageLoadCursor(display, xcursor_image);
+        x11::xcursor::XcursorImageDestroy(xcursor_image);
+
+        cursor as u32
+    }
+}
+
+/// Load a system cursor from the given cursor theme at the given size in physical pixels.
+pub(super) fn get_xcursor(
+    display: *mut x11::xlib::Display, cursor: &MouseCursor, theme: Option<&CStr>, size: i32,
+) -> u32 {
+    let load = |name: &[u8]| load_cursor(display, name, theme, size);
+    let loadn = |names: &[&[u8]]| load_first_existing_cursor(display, names, theme, size);
 
     let cursor = match cursor {
         MouseCursor::Default => None, // catch this in the fallback case below
@@ -100,6 +162,9 @@ pub(super) fn get_xcursor(display: *mut x11::xlib::Display, cursor: MouseCursor)
         MouseCursor::NeswResize => loadn(&[b"fd_double_arrow\0", b"size_fdiag\0"]),
         MouseCursor::ColResize => loadn(&[b"split_h\0", b"h_double_arrow\0"]),
         MouseCursor::RowResize => loadn(&[b"split_v\0", b"v_double_arrow\0"]),
+
+        // These are created through `create_custom_cursor()` instead
+        MouseCursor::Custom(_) => None,
     };
 
     cursor.or_else(|| load(b"left_ptr\0")).unwrap_or(0)
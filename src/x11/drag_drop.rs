@@ -0,0 +1,706 @@
+//! Drag and drop through the XDND protocol:
+//! <https://www.freedesktop.org/wiki/Specifications/XDND/>
+
+use std::collections::{HashMap, VecDeque};
+use std::ffi::OsString;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Instant;
+
+use super::clipboard::{Clipboard, TRANSFER_TIMEOUT};
+use super::XcbConnection;
+use crate::{DragData, DropAction, Point};
+
+/// The version of the XDND protocol we implement
+pub(super) const XDND_VERSION: u32 = 5;
+
+/// The targets text can be requested as, in order of preference
+const TEXT_TARGETS: &[&str] = &["UTF8_STRING", "text/plain;charset=utf-8", "text/plain"];
+
+pub(super) struct XdndAtoms {
+    pub aware: u32,
+    pub proxy: u32,
+    pub enter: u32,
+    pub position: u32,
+    pub status: u32,
+    pub leave: u32,
+    pub drop: u32,
+    pub finished: u32,
+    pub selection: u32,
+    pub type_list: u32,
+    pub action_copy: u32,
+    pub action_move: u32,
+    pub action_link: u32,
+    pub action_ask: u32,
+    pub action_list: u32,
+    pub action_description: u32,
+    pub uri_list: u32,
+}
+
+impl XdndAtoms {
+    pub fn new(xcb_connection: &XcbConnection) -> Self {
+        Self {
+            aware: xcb_connection.intern_atom("XdndAware"),
+            proxy: xcb_connection.intern_atom("XdndProxy"),
+            enter: xcb_connection.intern_atom("XdndEnter"),
+            position: xcb_connection.intern_atom("XdndPosition"),
+            status: xcb_connection.intern_atom("XdndStatus"),
+            leave: xcb_connection.intern_atom("XdndLeave"),
+            drop: xcb_connection.intern_atom("XdndDrop"),
+            finished: xcb_connection.intern_atom("XdndFinished"),
+            selection: xcb_connection.intern_atom("XdndSelection"),
+            type_list: xcb_connection.intern_atom("XdndTypeList"),
+            action_copy: xcb_connection.intern_atom("XdndActionCopy"),
+            action_move: xcb_connection.intern_atom("XdndActionMove"),
+            action_link: xcb_connection.intern_atom("XdndActionLink"),
+            action_ask: xcb_connection.intern_atom("XdndActionAsk"),
+            action_list: xcb_connection.intern_atom("XdndActionList"),
+            action_description: xcb_connection.intern_atom("XdndActionDescription"),
+            uri_list: xcb_connection.intern_atom("text/uri-list"),
+        }
+    }
+
+    pub fn action_atom(&self, action: DropAction) -> u32 {
+        match action {
+            DropAction::Copy => self.action_copy,
+            DropAction::Move => self.action_move,
+            DropAction::Link => self.action_link,
+        }
+    }
+
+    pub fn atom_action(&self, atom: u32) -> Option<DropAction> {
+        if atom == self.action_copy {
+            Some(DropAction::Copy)
+        } else if atom == self.action_move {
+            Some(DropAction::Move)
+        } else if atom == self.action_link {
+            Some(DropAction::Link)
+        } else {
+            None
+        }
+    }
+}
+
+/// A drag from another window that's currently over our window.
+pub(super) struct DropTarget {
+    /// The window the data is being dragged from
+    pub source: u32,
+    /// The window the source sends its messages to. This is the top level window when the
+    /// messages are forwarded to us through `XdndProxy`, and it's used in our replies.
+    pub window: u32,
+    /// The targets the source offers the data as
+    pub types: Vec<u32>,
+    /// The actions the source offers in `XdndActionList`. One of these is picked when the source
+    /// proposes `XdndActionAsk`.
+    pub source_actions: Vec<u32>,
+    /// The action the source requested in the last `XdndPosition` message
+    pub action: u32,
+    /// The dragged data. This is only requested on the first `XdndPosition` message, since the
+    /// source may not be able to convert the selection before that.
+    pub data: Option<DragData>,
+    /// The last logical position of the pointer
+    pub position: Point,
+    /// Whether `DragDropEvent::Entered` has been sent
+    pub entered: bool,
+    /// Whether the handler accepted the data at the last position
+    pub accepted: bool,
+}
+
+impl DropTarget {
+    /// Start tracking a drag from the contents of an `XdndEnter` message. If the source offers
+    /// more than three types, those are only read by [`from_enter_message()`].
+    ///
+    /// [`from_enter_message()`]: Self::from_enter_message
+    pub fn new(window: u32, data: &[u32]) -> Self {
+        Self {
+            source: data[0],
+            window,
+            types: data[2..5].iter().copied().filter(|&atom| atom != xcb::NONE).collect(),
+            source_actions: Vec::new(),
+            action: xcb::NONE,
+            data: None,
+            position: Point::new(0.0, 0.0),
+            entered: false,
+            accepted: false,
+        }
+    }
+
+    /// Start tracking a drag from the contents of an `XdndEnter` message, and read the types and
+    /// actions the source stored in properties on its window.
+    pub fn from_enter_message(
+        xcb_connection: &XcbConnection, atoms: &XdndAtoms, window: u32, data: &[u32],
+    ) -> Self {
+        let mut drop_target = Self::new(window, data);
+        let get_atoms = |property| {
+            xcb::get_property(
+                &xcb_connection.conn,
+                false,
+                drop_target.source,
+                property,
+                xcb::ATOM_ATOM,
+                0,
+                u32::MAX / 4,
+            )
+            .get_reply()
+            .ok()
+            .filter(|reply| reply.format() == 32)
+            .map(|reply| reply.value::<u32>().to_vec())
+            .unwrap_or_default()
+        };
+
+        // If the source offers more than three types, they're stored in a property on the source
+        // window instead
+        let types = if data[1] & 1 != 0 { Some(get_atoms(atoms.type_list)) } else { None };
+        let source_actions = get_atoms(atoms.action_list);
+
+        if let Some(types) = types {
+            drop_target.types = types;
+        }
+        drop_target.source_actions = source_actions;
+
+        drop_target
+    }
+
+    /// The action the drop will be performed with. This is the action the source requested, or
+    /// the first action it offers when it asks us to choose. Sources that don't request an action
+    /// or request one we don't know about get a copy.
+    pub fn drop_action(&self, atoms: &XdndAtoms) -> DropAction {
+        let action = if self.action == atoms.action_ask {
+            self.source_actions.iter().find_map(|&action| atoms.atom_action(action))
+        } else {
+            atoms.atom_action(self.action)
+        };
+
+        action.unwrap_or(DropAction::Copy)
+    }
+
+    /// The data of the `XdndStatus` message replying to the last `XdndPosition` message. We want
+    /// to receive a new position message for every movement, so the rectangle is left empty.
+    pub fn status_message(&self, atoms: &XdndAtoms) -> [u32; 5] {
+        let action =
+            if self.accepted { atoms.action_atom(self.drop_action(atoms)) } else { xcb::NONE };
+
+        [self.window, self.accepted as u32 | 0b10, 0, 0, action]
+    }
+
+    /// The data of the `XdndFinished` message sent after the data was dropped.
+    pub fn finished_message(&self, atoms: &XdndAtoms, accepted: bool) -> [u32; 5] {
+        let action = if accepted { atoms.action_atom(self.drop_action(atoms)) } else { xcb::NONE };
+
+        [self.window, accepted as u32, action, 0, 0]
+    }
+
+    /// Request the dragged data from the source in the most suitable format. Returns `None` if the
+    /// source doesn't offer files or text.
+    pub fn fetch_data(
+        &self, xcb_connection: &XcbConnection, atoms: &XdndAtoms, clipboard: &mut Clipboard,
+        window_id: u32, pending_events: &mut VecDeque<xcb::GenericEvent>, time: u32,
+    ) -> Option<DragData> {
+        let deadline = Instant::now() + TRANSFER_TIMEOUT;
+        let mut get = |target| {
+            clipboard.get_selection(
+                xcb_connection,
+                window_id,
+                pending_events,
+                atoms.selection,
+                target,
+                time,
+                deadline,
+            )
+        };
+
+        if self.types.contains(&atoms.uri_list) {
+            let files = parse_uri_list(&get(atoms.uri_list)?);
+            return if files.is_empty() { None } else { Some(DragData::Files(files)) };
+        }
+
+        let target = TEXT_TARGETS
+            .iter()
+            .map(|target| xcb_connection.intern_atom(target))
+            .find(|target| self.types.contains(target))?;
+        let text = String::from_utf8_lossy(&get(target)?).into_owned();
+
+        Some(DragData::Text(text))
+    }
+}
+
+/// A drag started from our window through `Window::start_drag()`.
+pub(super) struct DragSource {
+    /// The targets we offer the data as
+    pub types: Vec<u32>,
+    /// The action we propose to the target
+    pub action: u32,
+    /// The actions the target may perform. This includes `XdndActionAsk` if we proposed it.
+    pub allowed_actions: Vec<u32>,
+    /// The XDND aware window currently under the pointer
+    pub target: Option<XdndTarget>,
+    /// Whether the target accepts the drop at the last position
+    pub accepted: bool,
+    /// The action the target said it would perform
+    pub accepted_action: u32,
+    /// We may only send a new `XdndPosition` message after the target has replied to the last one
+    pub waiting_for_status: bool,
+    /// The packed root position and the time of the last motion event while we were waiting for a
+    /// status message
+    pub pending_position: Option<(u32, u32)>,
+    /// When the data was dropped. We're waiting for `XdndFinished` after this.
+    pub dropped_at: Option<Instant>,
+}
+
+impl DragSource {
+    pub fn new(types: Vec<u32>, action: u32, allowed_actions: Vec<u32>) -> Self {
+        Self {
+            types,
+            action,
+            allowed_actions,
+            target: None,
+            accepted: false,
+            accepted_action: xcb::NONE,
+            waiting_for_status: false,
+            pending_position: None,
+            dropped_at: None,
+        }
+    }
+
+    /// The data of the `XdndEnter` message sent to a new target. If we offer more than three
+    /// types, the target reads them from the `XdndTypeList` property on `window_id`.
+    pub fn enter_message(&self, window_id: u32) -> [u32; 5] {
+        let version = self.target.map_or(XDND_VERSION, |target| target.version);
+        let flags = (version << 24) | (self.types.len() > 3) as u32;
+        let mut types = [xcb::NONE; 3];
+        for (slot, &type_) in types.iter_mut().zip(&self.types) {
+            *slot = type_;
+        }
+
+        [window_id, flags, types[0], types[1], types[2]]
+    }
+
+    /// The data of an `XdndPosition` message for the packed root position `position`.
+    pub fn position_message(&self, window_id: u32, position: u32, time: u32) -> [u32; 5] {
+        [window_id, 0, position, time, self.action]
+    }
+
+    /// Update the target's state from the contents of an `XdndStatus` message.
+    pub fn handle_status(&mut self, atoms: &XdndAtoms, data: &[u32]) {
+        // Targets may not perform actions we didn't allow. Targets before version 2 always copy
+        // the data.
+        let version = self.target.map_or(0, |target| target.version);
+        let action = if version < 2 { atoms.action_copy } else { data[4] };
+        self.accepted = data[1] & 1 != 0 && self.allowed_actions.contains(&action);
+        self.accepted_action = action;
+        self.waiting_for_status = false;
+    }
+
+    /// The action the target performed according to the contents of an `XdndFinished` message,
+    /// or `None` if it didn't accept the drop.
+    pub fn finished_action(&self, atoms: &XdndAtoms, data: &[u32]) -> Option<DropAction> {
+        // Only version 5 targets tell us whether they accepted the drop and what they did
+        let version = self.target.map_or(0, |target| target.version);
+        if version < 5 {
+            atoms.atom_action(self.accepted_action)
+        } else if data[1] & 1 != 0 && self.allowed_actions.contains(&data[2]) {
+            atoms.atom_action(data[2])
+        } else {
+            None
+        }
+    }
+}
+
+/// An XDND aware window a drag started from our window is over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct XdndTarget {
+    /// The target window. This is the window in all messages sent to and received from the
+    /// target.
+    pub window: u32,
+    /// The window the messages are sent to. This is the window from the target's `XdndProxy`
+    /// property if it has a valid one, and the target window itself otherwise.
+    pub proxy: u32,
+    /// The XDND version the target supports, limited to the version we implement
+    pub version: u32,
+}
+
+impl XdndTarget {
+    /// Send an XDND client message to the target, through its proxy if it has one.
+    pub fn send_message(&self, conn: &xcb::Connection, type_: u32, data: [u32; 5]) {
+        send_client_message(conn, self.proxy, self.window, type_, data);
+    }
+}
+
+/// The targets and data `data` is offered as when dragged from our window.
+pub(super) fn drag_contents(
+    xcb_connection: &XcbConnection, atoms: &XdndAtoms, data: &DragData,
+) -> HashMap<u32, Rc<[u8]>> {
+    match data {
+        DragData::Files(paths) => HashMap::from([(atoms.uri_list, encode_uri_list(paths).into())]),
+        DragData::Text(text) => Clipboard::text_contents(xcb_connection, text),
+    }
+}
+
+/// Most drag sources only look for `XdndAware` on top level windows, so drops onto a parented
+/// window nested inside a host's window would never reach it. If the top level window containing
+/// `parent` doesn't accept drops itself, we make the sources forward all XDND messages for that
+/// window to `window_id` through `XdndProxy`. Returns the top level window if that was done.
+///
+/// This changes the properties of the host's top level window, which we don't own. They're only
+/// set if the host's window has no `XdndAware` or `XdndProxy` property yet, so the host's own
+/// drag and drop handling and other parented windows that got there first are left alone. That
+/// also means that only the first of multiple parented windows in the same top level window can
+/// receive drops. [`remove_xdnd_proxy()`] needs to be called before `window_id` is destroyed.
+pub(super) fn set_xdnd_proxy(
+    xcb_connection: &XcbConnection, atoms: &XdndAtoms, window_id: u32, parent: u32,
+) -> Option<u32> {
+    let conn = &xcb_connection.conn;
+    let root = xcb_connection.root_window();
+    let wm_state = xcb_connection.intern_atom("WM_STATE");
+    let has_property = |window, property| {
+        xcb::get_property(conn, false, window, property, xcb::ATOM_ANY, 0, 0)
+            .get_reply()
+            .is_ok_and(|reply| reply.type_() != xcb::NONE)
+    };
+
+    // The top level window is the one the window manager set `WM_STATE` on, or the last window
+    // below the root window if there is no window manager
+    let mut top_level = parent;
+    while !has_property(top_level, wm_state) {
+        let parent = xcb::query_tree(conn, top_level).get_reply().ok()?.parent();
+        if parent == xcb::NONE || parent == root {
+            break;
+        }
+
+        top_level = parent;
+    }
+
+    if has_property(top_level, atoms.aware) || has_property(top_level, atoms.proxy) {
+        return None;
+    }
+
+    // The proxy window also needs to point to itself, so sources can tell that the property on
+    // the top level window isn't left over from a proxy that no longer exists
+    xcb::change_property(
+        conn,
+        xcb::PROP_MODE_REPLACE as u8,
+        top_level,
+        atoms.aware,
+        xcb::ATOM_ATOM,
+        32,
+        &[XDND_VERSION],
+    );
+    for window in [top_level, window_id] {
+        xcb::change_property(
+            conn,
+            xcb::PROP_MODE_REPLACE as u8,
+            window,
+            atoms.proxy,
+            xcb::ATOM_WINDOW,
+            32,
+            &[window_id],
+        );
+    }
+    conn.flush();
+
+    Some(top_level)
+}
+
+/// Undo [`set_xdnd_proxy()`] before `window_id` is destroyed. The properties are only removed if
+/// `XdndProxy` still points to `window_id`, in case the host has replaced them in the meantime.
+pub(super) fn remove_xdnd_proxy(
+    xcb_connection: &XcbConnection, atoms: &XdndAtoms, window_id: u32, top_level: u32,
+) {
+    let conn = &xcb_connection.conn;
+    let proxy = xcb::get_property(conn, false, top_level, atoms.proxy, xcb::ATOM_WINDOW, 0, 1)
+        .get_reply()
+        .ok()
+        .filter(|reply| reply.format() == 32)
+        .and_then(|reply| reply.value::<u32>().first().copied());
+    if proxy == Some(window_id) {
+        xcb::delete_property(conn, top_level, atoms.aware);
+        xcb::delete_property(conn, top_level, atoms.proxy);
+        conn.flush();
+    }
+}
+
+/// Find the deepest XDND aware window under the given root window coordinates. Window managers
+/// reparent top level windows into frames, and plugin windows may be nested inside their host's
+/// windows, so we need to walk the entire window tree.
+pub(super) fn find_xdnd_target(
+    xcb_connection: &XcbConnection, atoms: &XdndAtoms, root_x: i16, root_y: i16,
+) -> Option<XdndTarget> {
+    let conn = &xcb_connection.conn;
+    let root = xcb_connection.root_window();
+    let get_property = |window, property, type_| {
+        xcb::get_property(conn, false, window, property, type_, 0, 1)
+            .get_reply()
+            .ok()
+            .filter(|reply| reply.format() == 32)
+            .and_then(|reply| reply.value::<u32>().first().copied())
+    };
+
+    let mut target = None;
+    let mut window = root;
+    loop {
+        let child = xcb::translate_coordinates(conn, root, window, root_x, root_y)
+            .get_reply()
+            .ok()?
+            .child();
+        if child == xcb::NONE {
+            return target;
+        }
+
+        // Windows can forward the messages to another window through `XdndProxy`, which then also
+        // has the `XdndAware` property. The proxy window's `XdndProxy` needs to point to itself,
+        // otherwise the property on the target window is left over from a proxy that no longer
+        // exists and it's ignored.
+        let proxy = get_property(child, atoms.proxy, xcb::ATOM_WINDOW)
+            .filter(|&proxy| get_property(proxy, atoms.proxy, xcb::ATOM_WINDOW) == Some(proxy))
+            .unwrap_or(child);
+        if let Some(version) = get_property(proxy, atoms.aware, xcb::ATOM_ATOM) {
+            target = Some(XdndTarget { window: child, proxy, version: version.min(XDND_VERSION) });
+        }
+
+        window = child;
+    }
+}
+
+/// Send an XDND client message to `window`.
+pub(super) fn send_xdnd_message(conn: &xcb::Connection, window: u32, type_: u32, data: [u32; 5]) {
+    send_client_message(conn, window, window, type_, data);
+}
+
+/// Send a client message for `window` to `destination`. These are only different when the
+/// messages are forwarded to a proxy window.
+fn send_client_message(
+    conn: &xcb::Connection, destination: u32, window: u32, type_: u32, data: [u32; 5],
+) {
+    let event =
+        xcb::ClientMessageEvent::new(32, window, type_, xcb::ClientMessageData::from_data32(data));
+    xcb::send_event(conn, false, destination, xcb::EVENT_MASK_NO_EVENT, &event);
+    conn.flush();
+}
+
+/// Encode `paths` as a `text/uri-list` of `file://` URIs.
+fn encode_uri_list(paths: &[PathBuf]) -> Vec<u8> {
+    let mut list = Vec::new();
+    for path in paths {
+        list.extend_from_slice(b"file://");
+        for &byte in path.as_os_str().as_bytes() {
+            if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+                list.push(byte);
+            } else {
+                list.extend_from_slice(format!("%{:02X}", byte).as_bytes());
+            }
+        }
+        list.extend_from_slice(b"\r\n");
+    }
+
+    list
+}
+
+/// Parse a `text/uri-list` into the paths of the local files it contains. Other URIs, including
+/// `file://` URIs for files on other hosts, are skipped.
+fn parse_uri_list(data: &[u8]) -> Vec<PathBuf> {
+    let mut buffer = [0u8; 256];
+    let hostname = nix::unistd::gethostname(&mut buffer).ok().and_then(|name| name.to_str().ok());
+
+    parse_uri_list_for_host(data, hostname)
+}
+
+/// [`parse_uri_list()`], with the local machine's hostname passed in.
+fn parse_uri_list_for_host(data: &[u8], hostname: Option<&str>) -> Vec<PathBuf> {
+    let is_local_host = |host: &str| {
+        host.is_empty()
+            || host.eq_ignore_ascii_case("localhost")
+            || hostname.is_some_and(|hostname| host.eq_ignore_ascii_case(hostname))
+    };
+
+    String::from_utf8_lossy(data)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|uri| uri.strip_prefix("file:"))
+        .filter_map(|uri| match uri.strip_prefix("//") {
+            Some(uri) => {
+                let path_start = uri.find('/')?;
+                Some(&uri[path_start..]).filter(|_| is_local_host(&uri[..path_start]))
+            }
+            // Some applications leave out the host part entirely, e.g. `file:/tmp/foo`
+            None => Some(uri).filter(|path| path.starts_with('/')),
+        })
+        .map(|path| PathBuf::from(OsString::from_vec(percent_decode(path))))
+        .collect()
+}
+
+fn percent_decode(input: &str) -> Vec<u8> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match hex.filter(|_| bytes[i] == b'%').and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    fn atoms() -> XdndAtoms {
+        XdndAtoms {
+            aware: 1,
+            proxy: 2,
+            enter: 3,
+            position: 4,
+            status: 5,
+            leave: 6,
+            drop: 7,
+            finished: 8,
+            selection: 9,
+            type_list: 10,
+            action_copy: 11,
+            action_move: 12,
+            action_link: 13,
+            action_ask: 14,
+            action_list: 15,
+            action_description: 16,
+            uri_list: 17,
+        }
+    }
+
+    /// Drag data from one of our windows to another one, the way `start_drag()` sets up the drag
+    /// and the windows exchange the XDND messages. Returns the actions the target saw and the one
+    /// reported to the source when the drag ended.
+    fn drag_between_windows(
+        allowed_actions: &[DropAction], target_accepts: bool,
+    ) -> (Vec<DropAction>, Option<DropAction>) {
+        const SOURCE_WINDOW: u32 = 100;
+        const TARGET_WINDOW: u32 = 200;
+        let atoms = atoms();
+
+        let mut actions: Vec<u32> = allowed_actions.iter().map(|&a| atoms.action_atom(a)).collect();
+        let action = if actions.len() > 1 { atoms.action_ask } else { actions[0] };
+        if actions.len() > 1 {
+            actions.push(atoms.action_ask);
+        }
+        let mut source = DragSource::new(vec![atoms.uri_list], action, actions.clone());
+        source.target =
+            Some(XdndTarget { window: TARGET_WINDOW, proxy: TARGET_WINDOW, version: XDND_VERSION });
+
+        // `XdndEnter`, with the `XdndActionList` property read from the source window
+        let mut target = DropTarget::new(TARGET_WINDOW, &source.enter_message(SOURCE_WINDOW));
+        target.source_actions = actions;
+        assert_eq!(target.source, SOURCE_WINDOW);
+        assert_eq!(target.types, vec![atoms.uri_list]);
+
+        // `XdndPosition` and `XdndStatus` for two movements
+        let mut seen_actions = Vec::new();
+        for position in 0..2 {
+            let position = source.position_message(SOURCE_WINDOW, position, 0);
+            target.action = position[4];
+            seen_actions.push(target.drop_action(&atoms));
+            target.accepted = target_accepts;
+
+            source.handle_status(&atoms, &target.status_message(&atoms));
+            assert_eq!(source.accepted, target_accepts);
+        }
+
+        // `XdndDrop` and `XdndFinished`
+        source.dropped_at = Some(Instant::now());
+        let finished = target.finished_message(&atoms, target.accepted);
+        assert_eq!(finished[0], TARGET_WINDOW);
+
+        (seen_actions, source.finished_action(&atoms, &finished))
+    }
+
+    #[test]
+    fn moves_between_windows() {
+        let (seen_actions, action) = drag_between_windows(&[DropAction::Move], true);
+        assert_eq!(seen_actions, vec![DropAction::Move; 2]);
+        assert_eq!(action, Some(DropAction::Move));
+    }
+
+    #[test]
+    fn picks_an_offered_action_when_asked() {
+        let (seen_actions, action) =
+            drag_between_windows(&[DropAction::Link, DropAction::Copy], true);
+        assert_eq!(seen_actions, vec![DropAction::Link; 2]);
+        assert_eq!(action, Some(DropAction::Link));
+    }
+
+    #[test]
+    fn reports_rejected_drags() {
+        let (_, action) = drag_between_windows(&[DropAction::Move], false);
+        assert_eq!(action, None);
+    }
+
+    #[test]
+    fn rejects_actions_that_were_not_allowed() {
+        let atoms = atoms();
+        let mut source =
+            DragSource::new(vec![atoms.uri_list], atoms.action_move, vec![atoms.action_move]);
+        source.target = Some(XdndTarget { window: 200, proxy: 300, version: XDND_VERSION });
+
+        source.handle_status(&atoms, &[200, 0b11, 0, 0, atoms.action_copy]);
+        assert!(!source.accepted);
+        source.handle_status(&atoms, &[200, 0b11, 0, 0, atoms.action_move]);
+        assert!(source.accepted);
+
+        // Targets before version 2 always copy
+        source.target = Some(XdndTarget { window: 200, proxy: 200, version: 1 });
+        source.handle_status(&atoms, &[200, 0b11, 0, 0, atoms.action_move]);
+        assert!(!source.accepted);
+    }
+
+    #[test]
+    fn parses_file_uris() {
+        let list = b"file:///tmp/a\r\nfile://localhost/tmp/b\r\nfile:/tmp/c\nfile://Host/tmp/d\n";
+        assert_eq!(
+            parse_uri_list_for_host(list, Some("host")),
+            paths(&["/tmp/a", "/tmp/b", "/tmp/c", "/tmp/d"])
+        );
+    }
+
+    #[test]
+    fn skips_files_on_other_hosts() {
+        let list = b"file://otherhost/tmp/a\r\nfile://host/tmp/b\r\nfile://hostname/tmp/c\r\n";
+        assert_eq!(parse_uri_list_for_host(list, Some("host")), paths(&["/tmp/b"]));
+        assert_eq!(parse_uri_list_for_host(list, None), paths(&[]));
+    }
+
+    #[test]
+    fn skips_comments_and_other_uris() {
+        let list = b"# comment\r\n\r\nhttps://example.com/a\r\nfile:relative\r\nfile://host\r\n  \
+            file:///tmp/a  \r\n";
+        assert_eq!(parse_uri_list(list), paths(&["/tmp/a"]));
+    }
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(parse_uri_list(b"file:///tmp/a%20b%2fc"), paths(&["/tmp/a b/c"]));
+        assert_eq!(percent_decode("%C3%A4%ff"), vec![0xc3, 0xa4, 0xff]);
+        // Invalid and truncated escapes are kept as is
+        assert_eq!(percent_decode("%zz%2%"), b"%zz%2%");
+        assert_eq!(percent_decode("100%"), b"100%");
+    }
+
+    #[test]
+    fn round_trips_paths() {
+        let original = paths(&["/tmp/a b", "/tmp/100%", "/tmp/ä#?"]);
+        assert_eq!(parse_uri_list(&encode_uri_list(&original)), original);
+    }
+}
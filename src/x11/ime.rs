@@ -0,0 +1,443 @@
+//! Input method support through XIM. The input method uses a separate Xlib connection, since Xlib
+//! needs to see the XIM protocol's messages itself while all of our other events are read through
+//! XCB.
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_ulong, c_ushort, c_void};
+use std::os::unix::io::RawFd;
+use std::ptr;
+
+use nix::libc;
+use x11::xlib;
+
+use crate::Ime;
+
+/// `XIMStyles` is missing from the `x11` crate
+#[repr(C)]
+struct XIMStyles {
+    count_styles: c_ushort,
+    supported_styles: *mut c_ulong,
+}
+
+/// Something that happened while passing events to the input method.
+pub(super) enum InputMethodEvent {
+    Ime(Ime),
+    /// A key event the input method passed back to us because it didn't handle it. The `bool` is
+    /// `true` for key presses.
+    Key(xcb::KeyPressEvent, bool),
+}
+
+pub(super) struct InputMethod {
+    display: *mut xlib::Display,
+    im: xlib::XIM,
+    ic: xlib::XIC,
+    window_id: u32,
+
+    /// Shared with the preedit callbacks, so this needs to stay at the same address
+    state: Box<CallbackState>,
+}
+
+#[derive(Default)]
+struct CallbackState {
+    preedit: Vec<char>,
+    /// The caret's position in `preedit`, in characters
+    caret: usize,
+    events: Vec<InputMethodEvent>,
+}
+
+impl InputMethod {
+    /// Connect to the user's input method. Returns `None` if there is none, or if Xlib doesn't
+    /// support the current locale. The locale is process wide, so setting it is left to the host.
+    pub fn new(window_id: u32) -> Option<Self> {
+        unsafe {
+            if xlib::XSupportsLocale() == 0 {
+                return None;
+            }
+            xlib::XSetLocaleModifiers(b"\0".as_ptr() as *const c_char);
+
+            let display = xlib::XOpenDisplay(ptr::null());
+            if display.is_null() {
+                return None;
+            }
+
+            let im = xlib::XOpenIM(display, ptr::null_mut(), ptr::null_mut(), ptr::null_mut());
+            if im.is_null() {
+                xlib::XCloseDisplay(display);
+                return None;
+            }
+
+            let mut input_method =
+                Self { display, im, ic: ptr::null_mut(), window_id, state: Box::default() };
+            input_method.ic = input_method.create_ic()?;
+
+            Some(input_method)
+        }
+    }
+
+    /// Create an input context that uses on-the-spot preediting if the input method supports it.
+    /// Otherwise the input method draws the preedit text itself, and we only receive the committed
+    /// text.
+    unsafe fn create_ic(&mut self) -> Option<xlib::XIC> {
+        let mut styles: *mut XIMStyles = ptr::null_mut();
+        let failed_argument = xlib::XGetIMValues(
+            self.im,
+            xlib::XNQueryInputStyle_0.as_ptr(),
+            &mut styles,
+            ptr::null_mut::<c_void>(),
+        );
+        if !failed_argument.is_null() || styles.is_null() {
+            return None;
+        }
+        let supported_styles =
+            std::slice::from_raw_parts((*styles).supported_styles, (*styles).count_styles as usize)
+                .to_vec();
+        xlib::XFree(styles as *mut c_void);
+
+        let window = self.window_id as c_ulong;
+        let callbacks_style = (xlib::XIMPreeditCallbacks | xlib::XIMStatusNothing) as c_ulong;
+        if supported_styles.contains(&callbacks_style) {
+            // Xlib copies these structs
+            let client_data = &mut *self.state as *mut CallbackState as xlib::XPointer;
+            let start_callback =
+                xlib::XICCallback { client_data, callback: Some(preedit_start_callback) };
+            let done_callback =
+                xlib::XIMCallback { client_data, callback: Some(preedit_done_callback) };
+            let draw_callback =
+                xlib::XIMCallback { client_data, callback: Some(preedit_draw_callback) };
+            let caret_callback =
+                xlib::XIMCallback { client_data, callback: Some(preedit_caret_callback) };
+
+            let preedit_attributes = xlib::XVaCreateNestedList(
+                0,
+                xlib::XNPreeditStartCallback_0.as_ptr(),
+                &start_callback,
+                xlib::XNPreeditDoneCallback_0.as_ptr(),
+                &done_callback,
+                xlib::XNPreeditDrawCallback_0.as_ptr(),
+                &draw_callback,
+                xlib::XNPreeditCaretCallback_0.as_ptr(),
+                &caret_callback,
+                ptr::null_mut::<c_void>(),
+            );
+            let ic = xlib::XCreateIC(
+                self.im,
+                xlib::XNInputStyle_0.as_ptr(),
+                callbacks_style,
+                xlib::XNClientWindow_0.as_ptr(),
+                window,
+                xlib::XNFocusWindow_0.as_ptr(),
+                window,
+                xlib::XNPreeditAttributes_0.as_ptr(),
+                preedit_attributes,
+                ptr::null_mut::<c_void>(),
+            );
+            xlib::XFree(preedit_attributes);
+
+            if !ic.is_null() {
+                return Some(ic);
+            }
+        }
+
+        for style in [
+            xlib::XIMPreeditNothing | xlib::XIMStatusNothing,
+            xlib::XIMPreeditNone | xlib::XIMStatusNone,
+        ] {
+            let style = style as c_ulong;
+            if !supported_styles.contains(&style) {
+                continue;
+            }
+
+            let ic = xlib::XCreateIC(
+                self.im,
+                xlib::XNInputStyle_0.as_ptr(),
+                style,
+                xlib::XNClientWindow_0.as_ptr(),
+                window,
+                xlib::XNFocusWindow_0.as_ptr(),
+                window,
+                ptr::null_mut::<c_void>(),
+            );
+            if !ic.is_null() {
+                return Some(ic);
+            }
+        }
+
+        None
+    }
+
+    /// The file descriptor of the input method's X11 connection.
+    pub fn fd(&self) -> RawFd {
+        unsafe { xlib::XConnectionNumber(self.display) }
+    }
+
+    pub fn set_focus(&mut self, focused: bool) {
+        unsafe {
+            if focused {
+                xlib::XSetICFocus(self.ic);
+            } else {
+                xlib::XUnsetICFocus(self.ic);
+            }
+            xlib::XFlush(self.display);
+        }
+    }
+
+    /// Cancel the current composition.
+    pub fn reset(&mut self) {
+        unsafe {
+            let text = xlib::Xutf8ResetIC(self.ic);
+            if !text.is_null() {
+                xlib::XFree(text as *mut c_void);
+            }
+            xlib::XFlush(self.display);
+        }
+
+        if !self.state.preedit.is_empty() {
+            self.state.preedit.clear();
+            self.state.caret = 0;
+            self.state
+                .events
+                .push(InputMethodEvent::Ime(Ime::Preedit { text: String::new(), cursor: None }));
+        }
+    }
+
+    /// Tell the input method where the text cursor is, in physical coordinates relative to the
+    /// window. This is used to position the candidate window.
+    pub fn set_spot_location(&mut self, x: i16, y: i16) {
+        unsafe {
+            let spot_location = xlib::XPoint { x, y };
+            let preedit_attributes = xlib::XVaCreateNestedList(
+                0,
+                xlib::XNSpotLocation_0.as_ptr(),
+                &spot_location,
+                ptr::null_mut::<c_void>(),
+            );
+            xlib::XSetICValues(
+                self.ic,
+                xlib::XNPreeditAttributes_0.as_ptr(),
+                preedit_attributes,
+                ptr::null_mut::<c_void>(),
+            );
+            xlib::XFree(preedit_attributes);
+            xlib::XFlush(self.display);
+        }
+    }
+
+    /// Pass a key event received by the window to the input method. Returns `true` if the input
+    /// method consumed the event, in which case it should not be handled any further.
+    pub fn filter_key_event(&mut self, event: &xcb::KeyPressEvent, pressed: bool) -> bool {
+        let mut xevent = xlib::XEvent {
+            key: xlib::XKeyEvent {
+                type_: if pressed { xlib::KeyPress } else { xlib::KeyRelease },
+                serial: 0,
+                send_event: xlib::False,
+                display: self.display,
+                window: event.event() as c_ulong,
+                root: event.root() as c_ulong,
+                subwindow: event.child() as c_ulong,
+                time: event.time() as c_ulong,
+                x: event.event_x() as c_int,
+                y: event.event_y() as c_int,
+                x_root: event.root_x() as c_int,
+                y_root: event.root_y() as c_int,
+                state: event.state() as u32,
+                keycode: event.detail() as u32,
+                same_screen: event.same_screen() as c_int,
+            },
+        };
+
+        let filtered = unsafe { xlib::XFilterEvent(&mut xevent, 0) != 0 };
+        unsafe { xlib::XFlush(self.display) };
+
+        filtered
+    }
+
+    /// Process the messages from the input method. This should be called whenever its connection
+    /// becomes readable.
+    pub fn process_events(&mut self) {
+        unsafe {
+            while xlib::XPending(self.display) > 0 {
+                let mut xevent: xlib::XEvent = std::mem::zeroed();
+                xlib::XNextEvent(self.display, &mut xevent);
+                if xlib::XFilterEvent(&mut xevent, 0) != 0 {
+                    continue;
+                }
+
+                let type_ = xevent.get_type();
+                if (type_ == xlib::KeyPress || type_ == xlib::KeyRelease)
+                    && xevent.key.window == self.window_id as c_ulong
+                {
+                    self.handle_key_event(xevent.key);
+                }
+            }
+        }
+    }
+
+    /// Handle a key event generated by the input method. Committed text is sent as a key press
+    /// without a key code, and everything else is a key event the input method doesn't want to
+    /// handle.
+    unsafe fn handle_key_event(&mut self, mut key: xlib::XKeyEvent) {
+        let pressed = key.type_ == xlib::KeyPress;
+        if key.keycode == 0 {
+            if let Some(text) = self.lookup_string(&mut key).filter(|_| pressed) {
+                self.state.events.push(InputMethodEvent::Ime(Ime::Commit(text)));
+            }
+
+            return;
+        }
+
+        let event = xcb::KeyPressEvent::new(
+            if pressed { xcb::KEY_PRESS } else { xcb::KEY_RELEASE },
+            key.keycode as u8,
+            key.time as u32,
+            key.root as u32,
+            key.window as u32,
+            key.subwindow as u32,
+            key.x_root as i16,
+            key.y_root as i16,
+            key.x as i16,
+            key.y as i16,
+            key.state as u16,
+            key.same_screen != 0,
+        );
+        self.state.events.push(InputMethodEvent::Key(event, pressed));
+    }
+
+    unsafe fn lookup_string(&mut self, key: &mut xlib::XKeyEvent) -> Option<String> {
+        let mut buffer = vec![0u8; 64];
+        loop {
+            let mut keysym = 0;
+            let mut status = 0;
+            let len = xlib::Xutf8LookupString(
+                self.ic,
+                key,
+                buffer.as_mut_ptr() as *mut c_char,
+                buffer.len() as c_int,
+                &mut keysym,
+                &mut status,
+            );
+
+            match status {
+                xlib::XBufferOverflow => buffer.resize(len as usize, 0),
+                xlib::XLookupChars | xlib::XLookupBoth => {
+                    return Some(String::from_utf8_lossy(&buffer[..len as usize]).into_owned());
+                }
+                _ => return None,
+            }
+        }
+    }
+
+    /// The events produced since the last call to this function.
+    pub fn take_events(&mut self) -> Vec<InputMethodEvent> {
+        std::mem::take(&mut self.state.events)
+    }
+}
+
+impl Drop for InputMethod {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.ic.is_null() {
+                xlib::XDestroyIC(self.ic);
+            }
+            xlib::XCloseIM(self.im);
+            xlib::XCloseDisplay(self.display);
+        }
+    }
+}
+
+impl CallbackState {
+    fn push_preedit(&mut self) {
+        let text = self.preedit.iter().collect();
+        let cursor = self.preedit[..self.caret].iter().map(|c| c.len_utf8()).sum();
+
+        self.events.push(InputMethodEvent::Ime(Ime::Preedit { text, cursor: Some(cursor) }));
+    }
+}
+
+unsafe extern "C" fn preedit_start_callback(
+    _ic: xlib::XIC, client_data: xlib::XPointer, _call_data: xlib::XPointer,
+) -> c_int {
+    let state = &mut *(client_data as *mut CallbackState);
+    state.preedit.clear();
+    state.caret = 0;
+
+    // There's no limit on the preedit text's length
+    -1
+}
+
+unsafe extern "C" fn preedit_done_callback(
+    _ic: xlib::XIM, client_data: xlib::XPointer, _call_data: xlib::XPointer,
+) {
+    let state = &mut *(client_data as *mut CallbackState);
+    state.preedit.clear();
+    state.caret = 0;
+
+    state.events.push(InputMethodEvent::Ime(Ime::Preedit { text: String::new(), cursor: None }));
+}
+
+unsafe extern "C" fn preedit_draw_callback(
+    _ic: xlib::XIM, client_data: xlib::XPointer, call_data: xlib::XPointer,
+) {
+    let state = &mut *(client_data as *mut CallbackState);
+    let call_data = &*(call_data as *const xlib::XIMPreeditDrawCallbackStruct);
+
+    // `chg_length` characters starting at `chg_first` are replaced with the new text
+    let start = (call_data.chg_first.max(0) as usize).min(state.preedit.len());
+    let end = (start + call_data.chg_length.max(0) as usize).min(state.preedit.len());
+    let mut new_text = Vec::new();
+    if !call_data.text.is_null() {
+        let text = &*call_data.text;
+        // The `x11` crate declares `wide_char` as a single `wchar_t` instead of a pointer, but both
+        // fields share the same pointer
+        if text.encoding_is_wchar == 0 && !text.string.multi_byte.is_null() {
+            new_text.extend(CStr::from_ptr(text.string.multi_byte).to_string_lossy().chars());
+        } else if text.encoding_is_wchar != 0 && !text.string.multi_byte.is_null() {
+            let wide_char = text.string.multi_byte as *const libc::wchar_t;
+            new_text.extend(wide_char_to_string(wide_char, text.length as usize).chars());
+        }
+    }
+    state.preedit.splice(start..end, new_text);
+    state.caret = (call_data.caret.max(0) as usize).min(state.preedit.len());
+
+    state.push_preedit();
+}
+
+unsafe extern "C" fn preedit_caret_callback(
+    _ic: xlib::XIM, client_data: xlib::XPointer, call_data: xlib::XPointer,
+) {
+    let state = &mut *(client_data as *mut CallbackState);
+    let call_data = &mut *(call_data as *mut xlib::XIMPreeditCaretCallbackStruct);
+
+    let len = state.preedit.len();
+    state.caret = match call_data.direction {
+        xlib::XIMCaretDirection::XIMAbsolutePosition => call_data.position.max(0) as usize,
+        xlib::XIMCaretDirection::XIMForwardChar => state.caret + 1,
+        xlib::XIMCaretDirection::XIMBackwardChar => state.caret.saturating_sub(1),
+        xlib::XIMCaretDirection::XIMLineStart => 0,
+        xlib::XIMCaretDirection::XIMLineEnd => len,
+        _ => state.caret,
+    }
+    .min(len);
+
+    // The input method expects the new position in return
+    call_data.position = state.caret as c_int;
+
+    state.push_preedit();
+}
+
+/// Convert `len` wide characters to a string through the locale's multibyte encoding, which Xlib
+/// also uses for all other text it passes to us.
+unsafe fn wide_char_to_string(text: *const libc::wchar_t, len: usize) -> String {
+    let mut wide_chars = std::slice::from_raw_parts(text, len).to_vec();
+    wide_chars.push(0);
+
+    let multi_byte_len = libc::wcstombs(ptr::null_mut(), wide_chars.as_ptr(), 0);
+    if multi_byte_len == usize::MAX {
+        return String::new();
+    }
+
+    let mut multi_byte = vec![0u8; multi_byte_len + 1];
+    libc::wcstombs(multi_byte.as_mut_ptr() as *mut c_char, wide_chars.as_ptr(), multi_byte.len());
+    multi_byte.truncate(multi_byte_len);
+
+    String::from_utf8_lossy(&multi_byte).into_owned()
+}
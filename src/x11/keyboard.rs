@@ -15,19 +15,541 @@
 // Baseview modifications to druid code:
 // - collect functions from various files
 // - update imports, paths etc
+// - translate keys using the user's keyboard layout through xkbcommon
 
 //! X11 keyboard handling
 
+use std::collections::HashSet;
+use std::env;
+use std::ffi::CString;
+use std::ptr;
+
+use x11::keysym::*;
 use xcb::xproto;
 
 use keyboard_types::*;
 
+use super::xkb::*;
+use super::XcbConnection;
 use crate::keyboard::code_to_location;
 
+/// The user's keyboard layout and the keyboard's current state, kept up to date through XKB
+/// events.
+pub(super) struct KeyboardState {
+    /// This is `None` if the X server doesn't support XKB, in which case keys are translated using
+    /// a hardcoded US layout
+    xkb: Option<Xkb>,
+    modifier_map: ModifierMap,
+    /// The keys that are currently held down, used to tell key repeats apart from new presses
+    pressed_keys: HashSet<u8>,
+}
+
+struct Xkb {
+    context: *mut xkb_context,
+    keymap: *mut xkb_keymap,
+    state: *mut xkb_state,
+    device_id: i32,
+    /// The XKB extension's event code. All XKB events share this code.
+    first_event: u8,
+    /// Whether the X server agreed to not send a key release event before every repeated key
+    /// press
+    detectable_auto_repeat: bool,
+    /// Tracks Compose key and dead key sequences using the compose table for the user's locale.
+    /// This is null if there is no compose table.
+    compose_state: *mut xkb_compose_state,
+}
+
+/// What feeding a key press to the compose state did.
+enum ComposeResult {
+    /// The key isn't part of a sequence, or it ended a sequence that didn't match anything
+    Nothing,
+    /// The key started or continued a sequence
+    Composing,
+    /// The key finished a sequence that produced this text
+    Composed(String),
+}
+
+impl KeyboardState {
+    pub fn new(xcb_connection: &XcbConnection) -> Self {
+        Self {
+            xkb: Xkb::new(xcb_connection),
+            modifier_map: ModifierMap::query(xcb_connection).unwrap_or_default(),
+            pressed_keys: HashSet::new(),
+        }
+    }
+
+    /// Extract the keyboard modifiers from the `state` field of an input event.
+    pub fn modifiers(&self, state: u16) -> Modifiers {
+        self.modifier_map.modifiers(state)
+    }
+
+    /// Whether held keys only produce key press events. If this is `false`, every repeated key
+    /// press is preceded by a key release event with the same timestamp.
+    pub fn detectable_auto_repeat(&self) -> bool {
+        self.xkb.as_ref().is_some_and(|xkb| xkb.detectable_auto_repeat)
+    }
+
+    /// Handle the event if it's an XKB event. Returns `false` for all other events.
+    pub fn handle_xkb_event(
+        &mut self, xcb_connection: &XcbConnection, event: &xcb::GenericEvent,
+    ) -> bool {
+        let xkb = match &mut self.xkb {
+            Some(xkb) if event.response_type() & !0x80 == xkb.first_event => xkb,
+            _ => return false,
+        };
+        let mut mapping_changed = false;
+
+        // All XKB events start with the same fields, so this is just used to read the event's
+        // XKB type and device
+        let any_event = unsafe { xcb::cast_event::<xcb::xkb::StateNotifyEvent>(event) };
+        if any_event.device_i_d() as i32 != xkb.device_id {
+            return true;
+        }
+
+        match any_event.xkb_type() {
+            xcb::xkb::NEW_KEYBOARD_NOTIFY | xcb::xkb::MAP_NOTIFY => {
+                xkb.reload_keymap(xcb_connection);
+                mapping_changed = true;
+            }
+            xcb::xkb::STATE_NOTIFY => unsafe {
+                xkb_state_update_mask(
+                    xkb.state,
+                    any_event.base_mods() as u32,
+                    any_event.latched_mods() as u32,
+                    any_event.locked_mods() as u32,
+                    any_event.base_group() as u32,
+                    any_event.latched_group() as u32,
+                    any_event.locked_group() as u32,
+                );
+            },
+            _ => {}
+        }
+
+        if mapping_changed {
+            self.update_modifier_map(xcb_connection);
+        }
+
+        true
+    }
+
+    /// Reload the keymap and the modifier mapping after the core `MappingNotify` event. XKB's own
+    /// events should already cover this, but not every tool that changes the keyboard mapping
+    /// goes through XKB.
+    pub fn reload_keymap(&mut self, xcb_connection: &XcbConnection) {
+        if let Some(xkb) = &mut self.xkb {
+            xkb.reload_keymap(xcb_connection);
+        }
+        self.update_modifier_map(xcb_connection);
+    }
+
+    fn update_modifier_map(&mut self, xcb_connection: &XcbConnection) {
+        if let Some(modifier_map) = ModifierMap::query(xcb_connection) {
+            self.modifier_map = modifier_map;
+        }
+    }
+
+    /// Forget the held keys and cancel any compose sequence after the window lost the keyboard
+    /// focus. The release events for the held keys are sent to the newly focused window instead.
+    pub fn focus_lost(&mut self) {
+        self.pressed_keys.clear();
+        if let Some(xkb) = &mut self.xkb {
+            xkb.reset_compose_state();
+        }
+    }
+
+    pub fn convert_key_press_event(&mut self, key_press: &xcb::KeyPressEvent) -> KeyboardEvent {
+        let repeat = !self.pressed_keys.insert(key_press.detail());
+        let mut event = self.convert_key_event(key_press, KeyState::Down, repeat);
+
+        if let Some(xkb) = &mut self.xkb {
+            match xkb.feed_compose_state(key_press.detail().into()) {
+                ComposeResult::Nothing => {}
+                ComposeResult::Composing => event.is_composing = true,
+                ComposeResult::Composed(text) => event.key = Key::Character(text),
+            }
+        }
+
+        event
+    }
+
+    pub fn convert_key_release_event(
+        &mut self, key_release: &xcb::KeyReleaseEvent,
+    ) -> KeyboardEvent {
+        self.pressed_keys.remove(&key_release.detail());
+        let mut event = self.convert_key_event(key_release, KeyState::Up, false);
+        event.is_composing = self.xkb.as_ref().is_some_and(Xkb::is_composing);
+
+        event
+    }
+
+    fn convert_key_event(
+        &self, event: &xcb::KeyPressEvent, state: KeyState, repeat: bool,
+    ) -> KeyboardEvent {
+        let hw_keycode = event.detail();
+        let code = hardware_keycode_to_code(hw_keycode.into());
+        let modifiers = self.modifiers(event.state());
+        let key = match &self.xkb {
+            Some(xkb) => xkb.keycode_to_key(hw_keycode.into()),
+            None => code_to_key(code, modifiers),
+        };
+        let location = code_to_location(code);
+
+        KeyboardEvent { code, key, modifiers, location, state, repeat, is_composing: false }
+    }
+}
+
+impl Xkb {
+    fn new(xcb_connection: &XcbConnection) -> Option<Self> {
+        let raw_conn = xcb_connection.conn.get_raw_conn();
+
+        unsafe {
+            let mut first_event = 0;
+            let supported = xkb_x11_setup_xkb_extension(
+                raw_conn,
+                XKB_X11_MIN_MAJOR_XKB_VERSION,
+                XKB_X11_MIN_MINOR_XKB_VERSION,
+                XKB_X11_SETUP_XKB_EXTENSION_NO_FLAGS,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &mut first_event,
+                ptr::null_mut(),
+            );
+            if supported == 0 {
+                return None;
+            }
+
+            let device_id = xkb_x11_get_core_keyboard_device_id(raw_conn);
+            if device_id == -1 {
+                return None;
+            }
+
+            let context = xkb_context_new(XKB_CONTEXT_NO_FLAGS);
+            if context.is_null() {
+                return None;
+            }
+
+            let mut xkb = Self {
+                context,
+                keymap: ptr::null_mut(),
+                state: ptr::null_mut(),
+                device_id,
+                first_event,
+                detectable_auto_repeat: false,
+                compose_state: new_compose_state(context),
+            };
+            xkb.reload_keymap(xcb_connection);
+            if xkb.state.is_null() {
+                return None;
+            }
+
+            let events = xcb::xkb::EVENT_TYPE_NEW_KEYBOARD_NOTIFY
+                | xcb::xkb::EVENT_TYPE_MAP_NOTIFY
+                | xcb::xkb::EVENT_TYPE_STATE_NOTIFY;
+            let map_parts = xcb::xkb::MAP_PART_KEY_TYPES
+                | xcb::xkb::MAP_PART_KEY_SYMS
+                | xcb::xkb::MAP_PART_MODIFIER_MAP
+                | xcb::xkb::MAP_PART_EXPLICIT_COMPONENTS
+                | xcb::xkb::MAP_PART_KEY_ACTIONS
+                | xcb::xkb::MAP_PART_VIRTUAL_MODS
+                | xcb::xkb::MAP_PART_VIRTUAL_MOD_MAP;
+            xcb::xkb::select_events(
+                &xcb_connection.conn,
+                device_id as u16,
+                events as u16,
+                0,
+                events as u16,
+                map_parts as u16,
+                map_parts as u16,
+                None,
+            );
+
+            let auto_repeat_flag = xcb::xkb::PER_CLIENT_FLAG_DETECTABLE_AUTO_REPEAT;
+            xkb.detectable_auto_repeat = xcb::xkb::per_client_flags(
+                &xcb_connection.conn,
+                device_id as u16,
+                auto_repeat_flag,
+                auto_repeat_flag,
+                0,
+                0,
+                0,
+            )
+            .get_reply()
+            .map(|reply| reply.value() & auto_repeat_flag != 0)
+            .unwrap_or(false);
+
+            Some(xkb)
+        }
+    }
+
+    /// Fetch the keymap and the keyboard's state from the X server. If this fails the previous
+    /// keymap is kept.
+    fn reload_keymap(&mut self, xcb_connection: &XcbConnection) {
+        let raw_conn = xcb_connection.conn.get_raw_conn();
+
+        unsafe {
+            let keymap = xkb_x11_keymap_new_from_device(
+                self.context,
+                raw_conn,
+                self.device_id,
+                XKB_KEYMAP_COMPILE_NO_FLAGS,
+            );
+            if keymap.is_null() {
+                return;
+            }
+
+            let state = xkb_x11_state_new_from_device(keymap, raw_conn, self.device_id);
+            if state.is_null() {
+                xkb_keymap_unref(keymap);
+                return;
+            }
+
+            if !self.state.is_null() {
+                xkb_state_unref(self.state);
+            }
+            if !self.keymap.is_null() {
+                xkb_keymap_unref(self.keymap);
+            }
+            self.keymap = keymap;
+            self.state = state;
+        }
+    }
+
+    fn keycode_to_key(&self, keycode: u32) -> Key {
+        let keysym = unsafe { xkb_state_key_get_one_sym(self.state, keycode) };
+        if let Some(key) = keysym_to_key(keysym) {
+            return key;
+        }
+
+        // Anything else that produces text is a character key. This doesn't go through
+        // `xkb_state_key_get_utf8()` since that would turn Ctrl+C into a control character.
+        let mut buffer = [0u8; 8];
+        let len =
+            unsafe { xkb_keysym_to_utf8(keysym, buffer.as_mut_ptr() as *mut _, buffer.len()) };
+        // The length includes the terminating null byte
+        let text =
+            if len > 1 { std::str::from_utf8(&buffer[..len as usize - 1]).ok() } else { None };
+        match text {
+            Some(text) if !text.chars().any(char::is_control) => Key::Character(text.into()),
+            _ => Key::Unidentified,
+        }
+    }
+
+    fn feed_compose_state(&mut self, keycode: u32) -> ComposeResult {
+        if self.compose_state.is_null() {
+            return ComposeResult::Nothing;
+        }
+
+        unsafe {
+            // Modifier keys are ignored by the compose state, so they don't cancel a sequence
+            let keysym = xkb_state_key_get_one_sym(self.state, keycode);
+            if xkb_compose_state_feed(self.compose_state, keysym) != XKB_COMPOSE_FEED_ACCEPTED {
+                return if self.is_composing() {
+                    ComposeResult::Composing
+                } else {
+                    ComposeResult::Nothing
+                };
+            }
+
+            match xkb_compose_state_get_status(self.compose_state) {
+                XKB_COMPOSE_COMPOSING => ComposeResult::Composing,
+                XKB_COMPOSE_COMPOSED => {
+                    // A null pointer with a size of zero returns the length without the null
+                    // terminator
+                    let len =
+                        xkb_compose_state_get_utf8(self.compose_state, ptr::null_mut(), 0).max(0);
+                    let mut buffer = vec![0u8; len as usize + 1];
+                    xkb_compose_state_get_utf8(
+                        self.compose_state,
+                        buffer.as_mut_ptr() as *mut _,
+                        buffer.len(),
+                    );
+                    buffer.truncate(len as usize);
+                    xkb_compose_state_reset(self.compose_state);
+
+                    // Some sequences produce keysyms without any text, those are treated like
+                    // any other key press
+                    match String::from_utf8(buffer) {
+                        Ok(text) if !text.is_empty() => ComposeResult::Composed(text),
+                        _ => ComposeResult::Nothing,
+                    }
+                }
+                XKB_COMPOSE_CANCELLED => {
+                    xkb_compose_state_reset(self.compose_state);
+                    ComposeResult::Nothing
+                }
+                _ => ComposeResult::Nothing,
+            }
+        }
+    }
+
+    fn reset_compose_state(&mut self) {
+        if !self.compose_state.is_null() {
+            unsafe { xkb_compose_state_reset(self.compose_state) };
+        }
+    }
+
+    fn is_composing(&self) -> bool {
+        !self.compose_state.is_null()
+            && unsafe { xkb_compose_state_get_status(self.compose_state) } == XKB_COMPOSE_COMPOSING
+    }
+}
+
+/// Load the compose table for the user's locale. Returns a null pointer if there is none.
+fn new_compose_state(context: *mut xkb_context) -> *mut xkb_compose_state {
+    // This follows the same rules as `setlocale()`, without changing the process' locale
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|locale| !locale.is_empty())
+        .unwrap_or_else(|| String::from("C"));
+    let locale = match CString::new(locale) {
+        Ok(locale) => locale,
+        Err(_) => return ptr::null_mut(),
+    };
+
+    unsafe {
+        let table = xkb_compose_table_new_from_locale(
+            context,
+            locale.as_ptr(),
+            XKB_COMPOSE_COMPILE_NO_FLAGS,
+        );
+        if table.is_null() {
+            return ptr::null_mut();
+        }
+
+        // The state keeps its own reference to the table
+        let state = xkb_compose_state_new(table, XKB_COMPOSE_STATE_NO_FLAGS);
+        xkb_compose_table_unref(table);
+
+        state
+    }
+}
+
+impl Drop for Xkb {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.compose_state.is_null() {
+                xkb_compose_state_unref(self.compose_state);
+            }
+            if !self.state.is_null() {
+                xkb_state_unref(self.state);
+            }
+            if !self.keymap.is_null() {
+                xkb_keymap_unref(self.keymap);
+            }
+            xkb_context_unref(self.context);
+        }
+    }
+}
+
+/// Map the keysyms for non-character keys to a key. Returns `None` for keysyms that produce text.
+#[allow(non_upper_case_globals)]
+fn keysym_to_key(keysym: u32) -> Option<Key> {
+    let key = match keysym {
+        XK_BackSpace => Key::Backspace,
+        XK_Tab | XK_ISO_Left_Tab | XK_KP_Tab => Key::Tab,
+        XK_Clear | XK_KP_Begin => Key::Clear,
+        XK_Return | XK_KP_Enter => Key::Enter,
+        XK_Pause => Key::Pause,
+        XK_Scroll_Lock => Key::ScrollLock,
+        XK_Sys_Req => Key::PrintScreen,
+        XK_Escape => Key::Escape,
+        XK_Delete | XK_KP_Delete => Key::Delete,
+        XK_Multi_key => Key::Compose,
+        XK_Kanji => Key::KanjiMode,
+        XK_Muhenkan => Key::NonConvert,
+        XK_Henkan_Mode => Key::Convert,
+        XK_Romaji => Key::Romaji,
+        XK_Hiragana => Key::Hiragana,
+        XK_Katakana => Key::Katakana,
+        XK_Hiragana_Katakana => Key::HiraganaKatakana,
+        XK_Zenkaku => Key::Zenkaku,
+        XK_Hankaku => Key::Hankaku,
+        XK_Zenkaku_Hankaku => Key::ZenkakuHankaku,
+        XK_Kana_Lock => Key::KanaMode,
+        XK_Eisu_toggle => Key::Alphanumeric,
+        // These aren't exported by the `x11` crate
+        0xFF31 => Key::HangulMode,
+        0xFF34 => Key::HanjaMode,
+        0xFF37 => Key::CodeInput,
+        0xFF3C => Key::SingleCandidate,
+        0xFF3D => Key::AllCandidates,
+        0xFF3E => Key::PreviousCandidate,
+        XK_Home | XK_KP_Home => Key::Home,
+        XK_Left | XK_KP_Left => Key::ArrowLeft,
+        XK_Up | XK_KP_Up => Key::ArrowUp,
+        XK_Right | XK_KP_Right => Key::ArrowRight,
+        XK_Down | XK_KP_Down => Key::ArrowDown,
+        XK_Prior | XK_KP_Prior => Key::PageUp,
+        XK_Next | XK_KP_Next => Key::PageDown,
+        XK_End | XK_KP_End => Key::End,
+        XK_Select => Key::Select,
+        XK_Print => Key::PrintScreen,
+        XK_Execute => Key::Execute,
+        XK_Insert | XK_KP_Insert => Key::Insert,
+        XK_Undo => Key::Undo,
+        XK_Redo => Key::Redo,
+        XK_Menu => Key::ContextMenu,
+        XK_Find => Key::Find,
+        XK_Cancel => Key::Cancel,
+        XK_Help => Key::Help,
+        XK_Break => Key::Pause,
+        XK_Mode_switch => Key::ModeChange,
+        XK_Num_Lock => Key::NumLock,
+        XK_F1 => Key::F1,
+        XK_F2 => Key::F2,
+        XK_F3 => Key::F3,
+        XK_F4 => Key::F4,
+        XK_F5 => Key::F5,
+        XK_F6 => Key::F6,
+        XK_F7 => Key::F7,
+        XK_F8 => Key::F8,
+        XK_F9 => Key::F9,
+        XK_F10 => Key::F10,
+        XK_F11 => Key::F11,
+        XK_F12 => Key::F12,
+        XK_Shift_L | XK_Shift_R => Key::Shift,
+        XK_Control_L | XK_Control_R => Key::Control,
+        XK_Caps_Lock | XK_Shift_Lock => Key::CapsLock,
+        XK_Meta_L | XK_Meta_R | XK_Super_L | XK_Super_R => Key::Meta,
+        XK_Alt_L | XK_Alt_R => Key::Alt,
+        XK_Hyper_L | XK_Hyper_R => Key::Hyper,
+        XK_ISO_Level3_Shift => Key::AltGraph,
+        XK_ISO_Next_Group | XK_ISO_Prev_Group | XK_ISO_First_Group | XK_ISO_Last_Group => {
+            Key::GroupNext
+        }
+        XK_dead_grave..=XK_dead_currency => Key::Dead,
+        // XF86 keysyms
+        0x1008FF11 => Key::AudioVolumeDown,
+        0x1008FF12 => Key::AudioVolumeMute,
+        0x1008FF13 => Key::AudioVolumeUp,
+        0x1008FF14 => Key::MediaPlayPause,
+        0x1008FF15 => Key::MediaStop,
+        0x1008FF16 => Key::MediaTrackPrevious,
+        0x1008FF17 => Key::MediaTrackNext,
+        0x1008FF18 => Key::BrowserHome,
+        0x1008FF19 => Key::LaunchMail,
+        0x1008FF1B => Key::BrowserSearch,
+        0x1008FF26 => Key::BrowserBack,
+        0x1008FF27 => Key::BrowserForward,
+        0x1008FF28 => Key::BrowserStop,
+        0x1008FF29 => Key::BrowserRefresh,
+        0x1008FF2C => Key::Eject,
+        0x1008FF30 => Key::BrowserFavorites,
+        0x1008FF32 => Key::LaunchMediaPlayer,
+        0x1008FF2B => Key::WakeUp,
+        0x1008FF57 => Key::Copy,
+        0x1008FF58 => Key::Cut,
+        0x1008FF6D => Key::Paste,
+        _ => return None,
+    };
+
+    Some(key)
+}
+
 /// Convert a hardware scan code to a key.
 ///
-/// Note: this is a hardcoded layout. We need to detect the user's
-/// layout from the system and apply it.
+/// Note: this is a hardcoded US layout. It's only used when the X server doesn't support XKB.
 fn code_to_key(code: Code, m: Modifiers) -> Key {
     fn a(s: &str) -> Key {
         Key::Character(s.into())
@@ -360,47 +882,229 @@ fn hardware_keycode_to_code(hw_keycode: u16) -> Code {
     }
 }
 
-// Extracts the keyboard modifiers from, e.g., the `state` field of
-// `xcb::xproto::ButtonPressEvent`
-pub(super) fn key_mods(mods: u16) -> Modifiers {
-    let mut ret = Modifiers::default();
-    let mut key_masks = [
-        (xproto::MOD_MASK_SHIFT, Modifiers::SHIFT),
-        (xproto::MOD_MASK_CONTROL, Modifiers::CONTROL),
-        // X11's mod keys are configurable, but this seems
-        // like a reasonable default for US keyboards, at least,
-        // where the "windows" key seems to be MOD_MASK_4.
-        (xproto::MOD_MASK_1, Modifiers::ALT),
-        (xproto::MOD_MASK_2, Modifiers::NUM_LOCK),
-        (xproto::MOD_MASK_4, Modifiers::META),
-        (xproto::MOD_MASK_LOCK, Modifiers::CAPS_LOCK),
-    ];
-    for (mask, modifiers) in &mut key_masks {
-        if mods & (*mask as u16) != 0 {
-            ret |= *modifiers;
+/// The `Modifiers` each of the eight X11 modifier bits in an event's `state` field stands for.
+/// Which keys these bits belong to depends on the user's keyboard configuration.
+struct ModifierMap([Modifiers; 8]);
+
+impl ModifierMap {
+    /// Resolve the modifier bits from the keys the X server maps to them.
+    fn query(xcb_connection: &XcbConnection) -> Option<Self> {
+        let conn = &xcb_connection.conn;
+        let setup = conn.get_setup();
+        let min_keycode = setup.min_keycode();
+        let keycode_count = setup.max_keycode() - min_keycode + 1;
+
+        let modifier_mapping = xproto::get_modifier_mapping(conn).get_reply().ok()?;
+        let keyboard_mapping =
+            xproto::get_keyboard_mapping(conn, min_keycode, keycode_count).get_reply().ok()?;
+
+        Self::from_mapping(
+            modifier_mapping.keycodes(),
+            modifier_mapping.keycodes_per_modifier() as usize,
+            min_keycode,
+            keyboard_mapping.keysyms(),
+            keyboard_mapping.keysyms_per_keycode() as usize,
+        )
+    }
+
+    /// Resolve the modifier bits from the replies to `GetModifierMapping` and
+    /// `GetKeyboardMapping`.
+    ///
+    /// The same bit is often shared by related modifier keys. The default configuration puts both
+    /// `Alt_L` and a key that only produces `Meta_L` on Mod1, and `Super_L` and `Hyper_L` on
+    /// Mod4. Each bit is assigned a single modifier, so that pressing Alt doesn't also report
+    /// Meta. This is the modifier of the keys' first keysyms with the highest priority in
+    /// `MODIFIER_PRIORITY`, or of any of their keysyms if none of the first ones are modifiers.
+    fn from_mapping(
+        modifier_keycodes: &[u8], keycodes_per_modifier: usize, min_keycode: u8, keysyms: &[u32],
+        keysyms_per_keycode: usize,
+    ) -> Option<Self> {
+        if keycodes_per_modifier == 0 || keysyms_per_keycode == 0 {
+            return None;
+        }
+
+        let mut map = [Modifiers::empty(); 8];
+        for (modifiers, keycodes) in
+            map.iter_mut().zip(modifier_keycodes.chunks(keycodes_per_modifier))
+        {
+            let keycode_keysyms =
+                keycodes.iter().filter(|&&keycode| keycode >= min_keycode).filter_map(|&keycode| {
+                    let start = (keycode - min_keycode) as usize * keysyms_per_keycode;
+                    keysyms.get(start..start + keysyms_per_keycode)
+                });
+
+            let mut first_keysyms = Modifiers::empty();
+            let mut all_keysyms = Modifiers::empty();
+            for keycode_keysyms in keycode_keysyms {
+                first_keysyms |= keysym_to_modifier(keycode_keysyms[0]);
+                for &keysym in keycode_keysyms {
+                    all_keysyms |= keysym_to_modifier(keysym);
+                }
+            }
+
+            let candidates = if first_keysyms.is_empty() { all_keysyms } else { first_keysyms };
+            *modifiers = MODIFIER_PRIORITY
+                .iter()
+                .copied()
+                .find(|&modifier| candidates.contains(modifier))
+                .unwrap_or_else(Modifiers::empty);
+        }
+
+        Some(Self(map))
+    }
+
+    fn modifiers(&self, state: u16) -> Modifiers {
+        let mut modifiers = Modifiers::empty();
+        for (bit, bit_modifiers) in self.0.iter().enumerate() {
+            if state & (1 << bit) != 0 {
+                modifiers |= *bit_modifiers;
+            }
         }
+
+        modifiers
     }
-    ret
 }
 
-pub(super) fn convert_key_press_event(key_press: &xcb::KeyPressEvent) -> KeyboardEvent {
-    let hw_keycode = key_press.detail();
-    let code = hardware_keycode_to_code(hw_keycode.into());
-    let modifiers = key_mods(key_press.state());
-    let key = code_to_key(code, modifiers);
-    let location = code_to_location(code);
-    let state = KeyState::Down;
+impl Default for ModifierMap {
+    /// The usual mapping, for when the X server can't be queried.
+    fn default() -> Self {
+        let mut map = [Modifiers::empty(); 8];
+        let default_mapping = [
+            (xproto::MOD_MASK_SHIFT, Modifiers::SHIFT),
+            (xproto::MOD_MASK_LOCK, Modifiers::CAPS_LOCK),
+            (xproto::MOD_MASK_CONTROL, Modifiers::CONTROL),
+            (xproto::MOD_MASK_1, Modifiers::ALT),
+            (xproto::MOD_MASK_2, Modifiers::NUM_LOCK),
+            (xproto::MOD_MASK_4, Modifiers::META),
+            (xproto::MOD_MASK_5, Modifiers::ALT_GRAPH),
+        ];
+        for (mask, modifiers) in default_mapping {
+            map[mask.trailing_zeros() as usize] = modifiers;
+        }
 
-    KeyboardEvent { code, key, modifiers, location, state, repeat: false, is_composing: false }
+        Self(map)
+    }
 }
 
-pub(super) fn convert_key_release_event(key_release: &xcb::KeyReleaseEvent) -> KeyboardEvent {
-    let hw_keycode = key_release.detail();
-    let code = hardware_keycode_to_code(hw_keycode.into());
-    let modifiers = key_mods(key_release.state());
-    let key = code_to_key(code, modifiers);
-    let location = code_to_location(code);
-    let state = KeyState::Up;
+/// The modifiers [`keysym_to_modifier()`] can return, in the order they're picked in when keys
+/// for several of them are mapped to the same modifier bit.
+const MODIFIER_PRIORITY: [Modifiers; 9] = [
+    Modifiers::SHIFT,
+    Modifiers::CONTROL,
+    Modifiers::ALT,
+    Modifiers::META,
+    Modifiers::HYPER,
+    Modifiers::ALT_GRAPH,
+    Modifiers::CAPS_LOCK,
+    Modifiers::NUM_LOCK,
+    Modifiers::SCROLL_LOCK,
+];
 
-    KeyboardEvent { code, key, modifiers, location, state, repeat: false, is_composing: false }
+/// The modifier a key with this keysym activates when it's mapped to a modifier bit.
+#[allow(non_upper_case_globals)]
+fn keysym_to_modifier(keysym: u32) -> Modifiers {
+    match keysym {
+        XK_Shift_L | XK_Shift_R => Modifiers::SHIFT,
+        XK_Control_L | XK_Control_R => Modifiers::CONTROL,
+        XK_Caps_Lock | XK_Shift_Lock => Modifiers::CAPS_LOCK,
+        XK_Alt_L | XK_Alt_R => Modifiers::ALT,
+        // The Windows key usually produces `Super_L`
+        XK_Meta_L | XK_Meta_R | XK_Super_L | XK_Super_R => Modifiers::META,
+        XK_Hyper_L | XK_Hyper_R => Modifiers::HYPER,
+        XK_ISO_Level3_Shift | XK_Mode_switch => Modifiers::ALT_GRAPH,
+        XK_Num_Lock => Modifiers::NUM_LOCK,
+        XK_Scroll_Lock => Modifiers::SCROLL_LOCK,
+        _ => Modifiers::empty(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYSYMS_PER_KEYCODE: usize = 4;
+    const MIN_KEYCODE: u8 = 8;
+
+    /// The keys on the modifier bits with the default XKB configuration for a US layout, as
+    /// reported by `xmodmap -pm` and `xmodmap -pke`.
+    fn default_us_mapping() -> ModifierMap {
+        let keys: [(u8, [u32; KEYSYMS_PER_KEYCODE]); 15] = [
+            (50, [XK_Shift_L, 0, XK_Shift_L, 0]),
+            (62, [XK_Shift_R, 0, XK_Shift_R, 0]),
+            (66, [XK_Caps_Lock, 0, XK_Caps_Lock, 0]),
+            (37, [XK_Control_L, 0, XK_Control_L, 0]),
+            (105, [XK_Control_R, 0, XK_Control_R, 0]),
+            (64, [XK_Alt_L, XK_Meta_L, XK_Alt_L, XK_Meta_L]),
+            (108, [XK_Alt_R, XK_Meta_R, XK_Alt_R, XK_Meta_R]),
+            (205, [0, XK_Meta_L, 0, XK_Meta_L]),
+            (77, [XK_Num_Lock, 0, XK_Num_Lock, 0]),
+            (133, [XK_Super_L, 0, XK_Super_L, 0]),
+            (134, [XK_Super_R, 0, XK_Super_R, 0]),
+            (206, [0, XK_Super_L, 0, XK_Super_L]),
+            (207, [0, XK_Hyper_L, 0, XK_Hyper_L]),
+            (92, [XK_ISO_Level3_Shift, 0, XK_ISO_Level3_Shift, 0]),
+            (203, [XK_Mode_switch, 0, XK_Mode_switch, 0]),
+        ];
+        #[rustfmt::skip]
+        let modifier_keycodes = [
+            50, 62, 0, 0, // Shift
+            66, 0, 0, 0, // Lock
+            37, 105, 0, 0, // Control
+            64, 108, 205, 0, // Mod1
+            77, 0, 0, 0, // Mod2
+            0, 0, 0, 0, // Mod3
+            133, 134, 206, 207, // Mod4
+            92, 203, 0, 0, // Mod5
+        ];
+
+        let mut keysyms = vec![0; (256 - MIN_KEYCODE as usize) * KEYSYMS_PER_KEYCODE];
+        for (keycode, keycode_keysyms) in keys {
+            let start = (keycode - MIN_KEYCODE) as usize * KEYSYMS_PER_KEYCODE;
+            keysyms[start..start + KEYSYMS_PER_KEYCODE].copy_from_slice(&keycode_keysyms);
+        }
+
+        ModifierMap::from_mapping(&modifier_keycodes, 4, MIN_KEYCODE, &keysyms, KEYSYMS_PER_KEYCODE)
+            .unwrap()
+    }
+
+    #[test]
+    fn assigns_one_modifier_per_bit() {
+        let map = default_us_mapping();
+        assert_eq!(map.modifiers(xproto::MOD_MASK_SHIFT as u16), Modifiers::SHIFT);
+        assert_eq!(map.modifiers(xproto::MOD_MASK_LOCK as u16), Modifiers::CAPS_LOCK);
+        assert_eq!(map.modifiers(xproto::MOD_MASK_CONTROL as u16), Modifiers::CONTROL);
+        assert_eq!(map.modifiers(xproto::MOD_MASK_1 as u16), Modifiers::ALT);
+        assert_eq!(map.modifiers(xproto::MOD_MASK_2 as u16), Modifiers::NUM_LOCK);
+        assert_eq!(map.modifiers(xproto::MOD_MASK_3 as u16), Modifiers::empty());
+        assert_eq!(map.modifiers(xproto::MOD_MASK_4 as u16), Modifiers::META);
+        assert_eq!(map.modifiers(xproto::MOD_MASK_5 as u16), Modifiers::ALT_GRAPH);
+    }
+
+    #[test]
+    fn matches_the_fallback_mapping() {
+        let map = default_us_mapping();
+        assert_eq!(map.0, ModifierMap::default().0);
+
+        let state = (xproto::MOD_MASK_CONTROL | xproto::MOD_MASK_1 | xproto::MOD_MASK_2) as u16;
+        assert_eq!(map.modifiers(state), Modifiers::CONTROL | Modifiers::ALT | Modifiers::NUM_LOCK);
+    }
+
+    #[test]
+    fn uses_other_levels_if_needed() {
+        // A bit with only the `Meta_L` key on it
+        let modifier_keycodes = [0, 0, 0, 0, 0, 0, 0, 205];
+        let mut keysyms = vec![0; (256 - MIN_KEYCODE as usize) * KEYSYMS_PER_KEYCODE];
+        let start = (205 - MIN_KEYCODE) as usize * KEYSYMS_PER_KEYCODE;
+        keysyms[start + 1] = XK_Meta_L;
+
+        let map = ModifierMap::from_mapping(
+            &modifier_keycodes,
+            1,
+            MIN_KEYCODE,
+            &keysyms,
+            KEYSYMS_PER_KEYCODE,
+        )
+        .unwrap();
+        assert_eq!(map.modifiers(xproto::MOD_MASK_5 as u16), Modifiers::META);
+    }
 }
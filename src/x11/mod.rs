@@ -4,5 +4,12 @@ use xcb_connection::XcbConnection;
 mod window;
 pub use window::*;
 
+mod clipboard;
 mod cursor;
+mod drag_drop;
+mod ime;
 mod keyboard;
+mod waker;
+mod xinput;
+mod xkb;
+mod xsettings;
@@ -0,0 +1,41 @@
+use std::os::unix::io::RawFd;
+
+use nix::sys::eventfd::{eventfd, EfdFlags};
+
+/// Wakes up the window's event loop from other threads. This wraps an eventfd that's polled
+/// together with the X11 connection's file descriptor.
+pub(super) struct Waker {
+    fd: RawFd,
+}
+
+impl Waker {
+    pub fn new() -> nix::Result<Self> {
+        let fd = eventfd(0, EfdFlags::EFD_NONBLOCK | EfdFlags::EFD_CLOEXEC)?;
+
+        Ok(Self { fd })
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// Make the event loop's `poll()` call return. This doesn't allocate, so it's safe to call
+    /// from realtime threads.
+    pub fn wake(&self) {
+        // This can only fail if the counter would overflow, in which case the event loop is
+        // already going to wake up
+        let _ = nix::unistd::write(self.fd, &1u64.to_ne_bytes());
+    }
+
+    /// Reset the eventfd after it has woken up the event loop.
+    pub fn reset(&self) {
+        let mut buf = [0u8; 8];
+        let _ = nix::unistd::read(self.fd, &mut buf);
+    }
+}
+
+impl Drop for Waker {
+    fn drop(&mut self) {
+        let _ = nix::unistd::close(self.fd);
+    }
+}
@@ -1,23 +1,37 @@
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
 use std::marker::PhantomData;
 use std::os::raw::{c_ulong, c_void};
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc;
 use std::sync::Arc;
-use std::thread;
+use std::thread::{self, JoinHandle};
 use std::time::*;
 
+use keyboard_types::Modifiers;
 use raw_window_handle::{HasRawWindowHandle, RawWindowHandle, XlibHandle};
 use x11::xlib::{Display, XResizeWindow, XSetInputFocus};
 use xcb::ffi::{xcb_connection_t, xcb_screen_t};
 use xcb::StructPtr;
 
+use super::clipboard::Clipboard;
+use super::drag_drop::{
+    drag_contents, find_xdnd_target, remove_xdnd_proxy, send_xdnd_message, set_xdnd_proxy,
+    DragSource, DropTarget, XdndAtoms, XDND_VERSION,
+};
+use super::ime::{InputMethod, InputMethodEvent};
+use super::waker::Waker;
+use super::xinput::{XInput2, XInput2Event};
 use super::XcbConnection;
 use crate::{
-    Event, MouseButton, MouseCursor, MouseEvent, PhyPoint, PhySize, ScrollDelta, Size, WindowEvent,
-    WindowHandler, WindowInfo, WindowOpenOptions,
+    ClipboardKind, DragData, DragDropEvent, DropAction, Error, Event, EventStatus, FrameRate,
+    MouseButton, MouseCursor, MouseEvent, PhyPoint, PhyRect, PhySize, Point, ScrollDelta,
+    ScrollPhase, Size, WindowEvent, WindowHandler, WindowInfo, WindowOpenOptions, WindowPosition,
+    WindowScalePolicy,
 };
 
-use super::keyboard::{convert_key_press_event, convert_key_release_event, key_mods};
+use super::keyboard::KeyboardState;
 
 #[cfg(feature = "opengl")]
 use crate::{
@@ -25,10 +39,19 @@ use crate::{
     window::RawWindowHandleWrapper,
 };
 
+/// How long we wait for the drop target to finish after dropping data onto it.
+const DROP_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long after the last smooth scrolling movement a scroll gesture is considered to have ended.
+/// X11 doesn't report the end of a gesture, and touchpads send scroll movements much more often
+/// than this.
+const SCROLL_GESTURE_TIMEOUT: Duration = Duration::from_millis(100);
+
 pub struct WindowHandle {
     raw_window_handle: Option<RawWindowHandle>,
     close_requested: Arc<AtomicBool>,
     is_open: Arc<AtomicBool>,
+    proxy: WindowProxy,
+    thread: Option<JoinHandle<()>>,
 
     // Ensure handle is !Send
     _phantom: PhantomData<*mut ()>,
@@ -104,19 +127,81 @@ impl WindowHandle {
     }
 
 
+    /// Close the window and block until the window thread has finished, so the host can safely
+    /// unload the plugin afterwards.
     pub fn close(&mut self) {
         if self.raw_window_handle.take().is_some() {
-            // FIXME: This will need to be changed from just setting an atomic to somehow
-            // synchronizing with the window being closed (using a synchronous channel, or
-            // by joining on the event loop thread).
-
             self.close_requested.store(true, Ordering::Relaxed);
+            self.proxy.waker.wake();
+
+            // When this is called from the window's own thread, e.g. because the handler owned
+            // this handle, the window can only close after we return
+            let window_thread_id = self.thread.as_ref().map(|thread| thread.thread().id());
+            if window_thread_id == Some(thread::current().id()) {
+                return;
+            }
+
+            // A panic on the window thread has already been reported by the panic hook, and
+            // there's nothing left to clean up after it
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
         }
     }
 
     pub fn is_open(&self) -> bool {
         self.is_open.load(Ordering::Relaxed)
     }
+
+    pub fn proxy(&self) -> WindowProxy {
+        self.proxy.clone()
+    }
+}
+
+/// A user message posted through `WindowProxy::post()`.
+type Message = Box<dyn Any + Send>;
+
+#[derive(Clone)]
+pub struct WindowProxy {
+    waker: Arc<Waker>,
+    /// Set by `wake()` so the event loop can tell these wakeups apart from other wakeups, and so
+    /// multiple calls before the handler runs result in a single `on_wake()` call.
+    woken: Arc<AtomicBool>,
+    message_tx: mpsc::Sender<Message>,
+}
+
+impl WindowProxy {
+    fn new() -> Result<(Self, mpsc::Receiver<Message>), Error> {
+        let waker = Waker::new().map_err(|err| {
+            Error::WindowCreationFailed(format!("could not create an eventfd: {}", err))
+        })?;
+        let (message_tx, message_rx) = mpsc::channel();
+
+        let proxy =
+            Self { waker: Arc::new(waker), woken: Arc::new(AtomicBool::new(false)), message_tx };
+
+        Ok((proxy, message_rx))
+    }
+
+    pub fn post(&self, message: Message) {
+        // This only fails once the window has been closed, in which case there's nobody left to
+        // receive the message
+        if self.message_tx.send(message).is_ok() {
+            self.waker.wake();
+        }
+    }
+
+    pub fn wake(&self) {
+        if !self.woken.swap(true, Ordering::AcqRel) {
+            self.waker.wake();
+        }
+    }
+}
+
+impl Drop for WindowHandle {
+    fn drop(&mut self) {
+        self.close();
+    }
 }
 
 unsafe impl HasRawWindowHandle for WindowHandle {
@@ -137,7 +222,7 @@ struct ParentHandle {
 }
 
 impl ParentHandle {
-    pub fn new() -> (Self, WindowHandle) {
+    pub fn new(proxy: WindowProxy) -> (Self, WindowHandle) {
         let close_requested = Arc::new(AtomicBool::new(false));
         let is_open = Arc::new(AtomicBool::new(true));
 
@@ -145,7 +230,9 @@ impl ParentHandle {
             raw_window_handle: None,
             close_requested: Arc::clone(&close_requested),
             is_open: Arc::clone(&is_open),
-            _phantom: PhantomData::default(),
+            proxy,
+            thread: None,
+            _phantom: PhantomData,
         };
 
         (Self { close_requested, is_open }, handle)
@@ -166,14 +253,80 @@ pub struct Window {
     xcb_connection: XcbConnection,
     window_id: u32,
     window_info: WindowInfo,
+    /// Whether this is a top-level window, i.e. not parented to another application's window.
+    /// The window manager may reparent these windows into its own frame windows.
+    is_top_level: bool,
+    /// The window's position relative to its parent window, or relative to the root window for
+    /// top-level windows.
+    position: PhyPoint,
+    scale_policy: WindowScalePolicy,
     // FIXME: There's all this mouse cursor logic but it's never actually used, is this correct?
     mouse_cursor: MouseCursor,
 
+    frame_rate: FrameRate,
+    /// The interval between two `on_frame` calls, derived from `frame_rate`
     frame_interval: Duration,
     event_loop_running: bool,
     close_requested: bool,
 
+    size_constraints: SizeConstraints,
     new_physical_size: Option<PhySize>,
+    new_position: Option<PhyPoint>,
+    /// Set when a configure event didn't contain the window's position on the root window. The
+    /// position is then queried once all pending events have been handled.
+    query_position: bool,
+    /// The exposed areas since the last `WindowEvent::Damaged` event
+    damaged: Vec<PhyRect>,
+    redraw_requested: bool,
+
+    clipboard: Clipboard,
+    /// The timestamp of the last input event, which is used when taking ownership of a selection
+    last_event_time: u32,
+    /// Events that were received while blocking on a clipboard transfer. These are handled before
+    /// any new events.
+    pending_events: VecDeque<xcb::GenericEvent>,
+
+    xdnd_atoms: XdndAtoms,
+    /// The host's top level window if it forwards XDND messages to this window through
+    /// `XdndProxy`
+    xdnd_proxy_for: Option<u32>,
+    /// A drag from another window that's currently over this window
+    drop_target: Option<DropTarget>,
+    /// A drag started from this window
+    drag_source: Option<DragSource>,
+
+    keyboard: KeyboardState,
+    /// Used for smooth scrolling if the X server supports XInput2
+    xinput: Option<XInput2>,
+    /// The time of the last scrolling movement from a touchpad and the modifiers at that time
+    /// while a scroll gesture is in progress
+    scroll_gesture: Option<(Instant, Modifiers)>,
+    /// The cursor's position in physical pixels when the pointer was locked through
+    /// `Window::lock_pointer()`. The cursor is moved back here whenever the pointer moves.
+    locked_cursor_position: Option<PhyPoint>,
+    /// The number of mouse buttons currently held down. The pointer is grabbed while any button
+    /// is held so drags keep receiving events outside of the window.
+    mouse_button_counter: usize,
+    /// Whether the pointer was grabbed through `Window::capture_mouse()`
+    mouse_captured: bool,
+    /// The connection to the user's input method, if there is one. This is only opened once IME
+    /// input is allowed for the first time.
+    input_method: Option<InputMethod>,
+    /// Whether key events should be passed to the input method, set through
+    /// `Window::set_ime_allowed()`
+    ime_allowed: bool,
+    /// Whether we already tried to connect to the input method
+    ime_opened: bool,
+    /// The position of the input method's candidate window, set through
+    /// `Window::set_ime_cursor_area()`
+    ime_spot_location: Option<(i16, i16)>,
+    /// Whether the window has the keyboard focus
+    has_focus: bool,
+    proxy: WindowProxy,
+    message_rx: mpsc::Receiver<Message>,
+    /// Set when the XSETTINGS or the X resources changed, so the scale factor and the cursor
+    /// settings can be read again once all pending events have been handled.
+    system_scale_changed: bool,
     parent_handle: Option<ParentHandle>,
 
     #[cfg(feature = "opengl")]
@@ -185,10 +338,28 @@ struct SendableRwh(RawWindowHandle);
 
 unsafe impl Send for SendableRwh {}
 
-type WindowOpenResult = Result<SendableRwh, ()>;
+type WindowOpenResult = Result<SendableRwh, Error>;
+
+/// Wait for the window thread to report whether the window could be opened. If it couldn't, the
+/// thread is joined before the error is returned so none of its code runs after `open_*()`
+/// returns.
+fn wait_for_window_thread(
+    rx: &mpsc::Receiver<WindowOpenResult>, thread: &mut Option<JoinHandle<()>>,
+) -> WindowOpenResult {
+    let result = rx.recv().map_err(|_| Error::BuildPanicked).and_then(|result| result);
+    if result.is_err() {
+        if let Some(thread) = thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    result
+}
 
 impl Window {
-    pub fn open_parented<P, H, B>(parent: &P, options: WindowOpenOptions, build: B) -> WindowHandle
+    pub fn open_parented<P, H, B>(
+        parent: &P, options: WindowOpenOptions, build: B,
+    ) -> Result<WindowHandle, Error>
     where
         P: HasRawWindowHandle,
         H: WindowHandler + 'static,
@@ -199,77 +370,163 @@ impl Window {
         let parent_id = match parent.raw_window_handle() {
             RawWindowHandle::Xlib(h) => h.window as u32,
             RawWindowHandle::Xcb(h) => h.window,
-            h => panic!("unsupported parent handle type {:?}", h),
+            h => {
+                return Err(Error::WindowCreationFailed(format!(
+                    "unsupported parent handle type {:?}",
+                    h
+                )))
+            }
         };
 
+        let (proxy, message_rx) = WindowProxy::new()?;
         let (tx, rx) = mpsc::sync_channel::<WindowOpenResult>(1);
 
-        let (parent_handle, mut window_handle) = ParentHandle::new();
+        let (parent_handle, mut window_handle) = ParentHandle::new(proxy.clone());
 
-        thread::spawn(move || {
-            Self::window_thread(Some(parent_id), options, build, tx.clone(), Some(parent_handle));
+        let thread = thread::spawn(move || {
+            let (parent, parent_handle) = (Some(parent_id), Some(parent_handle));
+            Self::window_thread(parent, options, build, tx, parent_handle, proxy, message_rx);
         });
+        window_handle.thread = Some(thread);
 
-        let raw_window_handle = rx.recv().unwrap().unwrap();
+        let raw_window_handle = wait_for_window_thread(&rx, &mut window_handle.thread)?;
         window_handle.raw_window_handle = Some(raw_window_handle.0);
 
-        window_handle
+        Ok(window_handle)
     }
 
-    pub fn open_as_if_parented<H, B>(options: WindowOpenOptions, build: B) -> WindowHandle
+    pub fn open_as_if_parented<H, B>(
+        options: WindowOpenOptions, build: B,
+    ) -> Result<WindowHandle, Error>
     where
         H: WindowHandler + 'static,
         B: FnOnce(&mut crate::Window) -> H,
         B: Send + 'static,
     {
+        let (proxy, message_rx) = WindowProxy::new()?;
         let (tx, rx) = mpsc::sync_channel::<WindowOpenResult>(1);
 
-        let (parent_handle, mut window_handle) = ParentHandle::new();
+        let (parent_handle, mut window_handle) = ParentHandle::new(proxy.clone());
 
-        thread::spawn(move || {
-            Self::window_thread(None, options, build, tx.clone(), Some(parent_handle));
+        let thread = thread::spawn(move || {
+            let parent_handle = Some(parent_handle);
+            Self::window_thread(None, options, build, tx, parent_handle, proxy, message_rx);
         });
+        window_handle.thread = Some(thread);
 
-        let raw_window_handle = rx.recv().unwrap().unwrap();
+        let raw_window_handle = wait_for_window_thread(&rx, &mut window_handle.thread)?;
         window_handle.raw_window_handle = Some(raw_window_handle.0);
 
-        window_handle
+        Ok(window_handle)
     }
 
-    pub fn open_blocking<H, B>(options: WindowOpenOptions, build: B)
+    pub fn open_blocking<H, B>(options: WindowOpenOptions, build: B) -> Result<(), Error>
     where
         H: WindowHandler + 'static,
         B: FnOnce(&mut crate::Window) -> H,
         B: Send + 'static,
     {
+        let (proxy, message_rx) = WindowProxy::new()?;
         let (tx, rx) = mpsc::sync_channel::<WindowOpenResult>(1);
 
-        let thread = thread::spawn(move || {
-            Self::window_thread(None, options, build, tx, None);
-        });
+        let mut thread = Some(thread::spawn(move || {
+            Self::window_thread(None, options, build, tx, None, proxy, message_rx);
+        }));
 
-        let _ = rx.recv().unwrap().unwrap();
+        wait_for_window_thread(&rx, &mut thread)?;
 
-        thread.join().unwrap_or_else(|err| {
-            eprintln!("Window thread panicked: {:#?}", err);
-        });
+        if let Some(thread) = thread {
+            thread.join().unwrap_or_else(|err| {
+                eprintln!("Window thread panicked: {:#?}", err);
+            });
+        }
+
+        Ok(())
     }
 
     fn window_thread<H, B>(
         parent: Option<u32>, options: WindowOpenOptions, build: B,
         tx: mpsc::SyncSender<WindowOpenResult>, parent_handle: Option<ParentHandle>,
+        proxy: WindowProxy, message_rx: mpsc::Receiver<Message>,
     ) where
         H: WindowHandler + 'static,
         B: FnOnce(&mut crate::Window) -> H,
         B: Send + 'static,
     {
+        let mut window = match Self::create(parent, options, parent_handle, proxy, message_rx) {
+            Ok(window) => window,
+            Err(err) => {
+                let _ = tx.send(Err(err));
+                return;
+            }
+        };
+
+        // The build closure is user code, so a panic in there should be reported back to the
+        // thread that tried to open the window instead of silently killing the window thread
+        let mut handler = match panic::catch_unwind(AssertUnwindSafe(|| {
+            build(&mut crate::Window::new(&mut window))
+        })) {
+            Ok(handler) => handler,
+            Err(_) => {
+                let _ = tx.send(Err(Error::BuildPanicked));
+                return;
+            }
+        };
+
+        // Send an initial window resized event so the user is alerted of
+        // the correct dpi scaling.
+        let window_info = window.window_info;
+        handler.on_event(
+            &mut crate::Window::new(&mut window),
+            Event::Window(WindowEvent::Resized(window_info)),
+        );
+
+        let _ = tx.send(Ok(SendableRwh(window.raw_window_handle())));
+
+        window.run_event_loop(&mut handler);
+
+        // The handler and the OpenGL context may still need the window, and the window needs the
+        // X11 connection. Dropping the parent handle signals a blocking `WindowHandle::close()`
+        // call that everything has been cleaned up, so that needs to happen last.
+        drop(handler);
+        #[cfg(feature = "opengl")]
+        drop(window.gl_context.take());
+        // The input context refers to the window, so it needs to be destroyed first
+        drop(window.input_method.take());
+        if let Some(top_level) = window.xdnd_proxy_for {
+            remove_xdnd_proxy(
+                &window.xcb_connection,
+                &window.xdnd_atoms,
+                window.window_id,
+                top_level,
+            );
+        }
+        xcb::destroy_window(&window.xcb_connection.conn, window.window_id);
+        window.xcb_connection.conn.flush();
+
+        let parent_handle = window.parent_handle.take();
+        drop(window);
+        drop(parent_handle);
+    }
+
+    /// Connect to the X server and create the window. This is done on the window thread, and any
+    /// errors are sent back to the thread that tried to open the window.
+    fn create(
+        parent: Option<u32>, options: WindowOpenOptions, parent_handle: Option<ParentHandle>,
+        proxy: WindowProxy, message_rx: mpsc::Receiver<Message>,
+    ) -> Result<Self, Error> {
         // Connect to the X server
-        // FIXME: baseview error type instead of unwrap()
-        let xcb_connection = XcbConnection::new().unwrap();
+        let xcb_connection =
+            XcbConnection::new().map_err(|err| Error::ConnectionFailed(err.to_string()))?;
 
         // Get screen information (?)
         let setup = xcb_connection.conn.get_setup();
-        let screen = setup.roots().nth(xcb_connection.xlib_display as usize).unwrap();
+        let screen = setup.roots().nth(xcb_connection.xlib_display as usize).ok_or_else(|| {
+            Error::ConnectionFailed(format!(
+                "the X server does not have a screen {}",
+                xcb_connection.xlib_display
+            ))
+        })?;
 
         let foreground = xcb_connection.conn.generate_id();
 
@@ -282,9 +539,45 @@ impl Window {
             &[(xcb::GC_FOREGROUND, screen.black_pixel()), (xcb::GC_GRAPHICS_EXPOSURES, 0)],
         );
 
-        let scaling = 1.0; // TODO
+        let scaling = match options.scale {
+            WindowScalePolicy::SystemScaleFactor => xcb_connection.get_scaling().unwrap_or(1.0),
+            WindowScalePolicy::ScaleFactor(scale) => scale,
+        };
+
+        let size_constraints = SizeConstraints {
+            min_size: options.min_size,
+            max_size: options.max_size,
+            resizable: options.resizable,
+            aspect_ratio: options.aspect_ratio,
+        };
+
+        let mut window_info = WindowInfo::from_logical_size(options.size, scaling);
+        let physical_size = window_info.physical_size();
+        let constrained_size = size_constraints.constrain(physical_size, scaling);
+        if constrained_size != physical_size {
+            window_info = WindowInfo::from_physical_size(constrained_size, scaling);
+        }
+
+        let position = Self::initial_position(
+            &xcb_connection.conn,
+            &screen,
+            parent,
+            options.position,
+            &window_info,
+        );
 
-        let window_info = WindowInfo::from_logical_size(options.size, scaling);
+        // To detect changes to the system's scale factor and cursor settings we need to listen for
+        // changes to the RESOURCE_MANAGER property and for XSETTINGS managers announcing
+        // themselves on the root window. The cursor settings are used regardless of the scale
+        // policy. `XcbConnection` itself already listens for changes on the XSETTINGS owner.
+        xcb::change_window_attributes(
+            &xcb_connection.conn,
+            screen.root(),
+            &[(
+                xcb::CW_EVENT_MASK,
+                xcb::EVENT_MASK_PROPERTY_CHANGE | xcb::EVENT_MASK_STRUCTURE_NOTIFY,
+            )],
+        );
 
         // Now it starts becoming fun. If we're creating an OpenGL context, then we need to create
         // the window with a visual that matches the framebuffer used for the OpenGL context. So the
@@ -295,7 +588,7 @@ impl Window {
         let create_default_config = || {
             Self::find_visual_for_depth(&screen, 32)
                 .map(|visual| (32, visual))
-                .unwrap_or((xcb::COPY_FROM_PARENT as u8, xcb::COPY_FROM_PARENT as u32))
+                .unwrap_or((xcb::COPY_FROM_PARENT as u8, xcb::COPY_FROM_PARENT))
         };
         #[cfg(feature = "opengl")]
         let (fb_config, (depth, visual)) = match options.gl_config {
@@ -307,7 +600,7 @@ impl Window {
                 .map(|(fb_config, window_config)| {
                     (Some(fb_config), (window_config.depth, window_config.visual))
                 })
-                .expect("Could not fetch framebuffer config")
+                .map_err(|err| Error::GlConfigFailed(format!("{:?}", err)))?
             },
             None => (None, create_default_config()),
         };
@@ -331,8 +624,8 @@ impl Window {
             depth,
             window_id,
             parent_id,
-            0,                                         // x coordinate of the new window
-            0,                                         // y coordinate of the new window
+            position.x as i16,                         // x coordinate of the new window
+            position.y as i16,                         // y coordinate of the new window
             window_info.physical_size().width as u16,  // window width
             window_info.physical_size().height as u16, // window height
             0,                                         // window border
@@ -345,9 +638,13 @@ impl Window {
                         | xcb::EVENT_MASK_POINTER_MOTION
                         | xcb::EVENT_MASK_BUTTON_PRESS
                         | xcb::EVENT_MASK_BUTTON_RELEASE
+                        | xcb::EVENT_MASK_ENTER_WINDOW
+                        | xcb::EVENT_MASK_LEAVE_WINDOW
                         | xcb::EVENT_MASK_KEY_PRESS
                         | xcb::EVENT_MASK_KEY_RELEASE
-                        | xcb::EVENT_MASK_STRUCTURE_NOTIFY,
+                        | xcb::EVENT_MASK_FOCUS_CHANGE
+                        | xcb::EVENT_MASK_STRUCTURE_NOTIFY
+                        | xcb::EVENT_MASK_PROPERTY_CHANGE,
                 ),
                 // As mentioend above, these two values are needed to be able to create a window
                 // with a dpeth of 32-bits when the parent window has a different depth
@@ -356,9 +653,9 @@ impl Window {
             ],
         )
         .request_check()
-        .unwrap();
-
-        xcb::map_window(&xcb_connection.conn, window_id);
+        .map_err(|err| {
+            Error::WindowCreationFailed(format!("X11 error code {}", err.error_code()))
+        })?;
 
         // Change window title
         let title = options.title;
@@ -372,6 +669,17 @@ impl Window {
             title.as_bytes(),
         );
 
+        // Window managers ignore the position the window was created at unless it's also set in
+        // the size hints. The window manager places the window when it's mapped, so these need to
+        // be set before that.
+        let size = window_info.physical_size();
+        let mut size_hints =
+            size_constraints.size_hints(&window_info).size(size.width as i32, size.height as i32);
+        if options.position != WindowPosition::Default {
+            size_hints = size_hints.position(position.x, position.y);
+        }
+        xcb_util::icccm::set_wm_normal_hints(&xcb_connection.conn, window_id, &size_hints.build());
+
         if let Some((wm_protocols, wm_delete_window)) =
             xcb_connection.atoms.wm_protocols.zip(xcb_connection.atoms.wm_delete_window)
         {
@@ -383,53 +691,100 @@ impl Window {
             );
         }
 
+        xcb::map_window(&xcb_connection.conn, window_id);
         xcb_connection.conn.flush();
 
         // TODO: These APIs could use a couple tweaks now that everything is internal and there is
         //       no error handling anymore at this point. Everything is more or less unchanged
         //       compared to when raw-gl-context was a separate crate.
         #[cfg(feature = "opengl")]
-        let gl_context = fb_config.map(|fb_config| {
-            let mut handle = XlibHandle::empty();
-            handle.window = window_id as c_ulong;
-            handle.display = xcb_connection.conn.get_raw_dpy() as *mut c_void;
-            let handle = RawWindowHandleWrapper { handle: RawWindowHandle::Xlib(handle) };
-
-            // Because of the visual negotation we had to take some extra steps to create this context
-            let context = unsafe { platform::GlContext::create(&handle, fb_config) }
-                .expect("Could not create OpenGL context");
-            GlContext::new(context)
-        });
+        let gl_context = fb_config
+            .map(|fb_config| {
+                let mut handle = XlibHandle::empty();
+                handle.window = window_id as c_ulong;
+                handle.display = xcb_connection.conn.get_raw_dpy() as *mut c_void;
+                let handle = RawWindowHandleWrapper { handle: RawWindowHandle::Xlib(handle) };
+
+                // Because of the visual negotation we had to take some extra steps to create this context
+                unsafe { platform::GlContext::create(&handle, fb_config) }.map(GlContext::new)
+            })
+            .transpose()
+            .map_err(|err| Error::GlConfigFailed(format!("{:?}", err)))?;
+
+        let clipboard = Clipboard::new(&xcb_connection);
+        let keyboard = KeyboardState::new(&xcb_connection);
+        let xinput = XInput2::new(&xcb_connection, window_id);
+
+        // Advertise that we accept drops. Drag sources look for this property on the window under
+        // the pointer.
+        let xdnd_atoms = XdndAtoms::new(&xcb_connection);
+        xcb::change_property(
+            &xcb_connection.conn,
+            xcb::PROP_MODE_REPLACE as u8,
+            window_id,
+            xdnd_atoms.aware,
+            xcb::ATOM_ATOM,
+            32,
+            &[XDND_VERSION],
+        );
+        let xdnd_proxy_for = parent
+            .and_then(|parent| set_xdnd_proxy(&xcb_connection, &xdnd_atoms, window_id, parent));
 
         let mut window = Self {
             xcb_connection,
             window_id,
             window_info,
+            is_top_level: parent.is_none(),
+            position,
+            scale_policy: options.scale,
             mouse_cursor: MouseCursor::default(),
 
-            frame_interval: Duration::from_millis(15),
+            frame_rate: options.frame_rate,
+            frame_interval: Duration::from_secs_f64(1.0 / FrameRate::FALLBACK_HZ),
             event_loop_running: false,
             close_requested: false,
 
+            size_constraints,
             new_physical_size: None,
+            new_position: None,
+            query_position: false,
+            damaged: Vec::new(),
+            redraw_requested: false,
+
+            clipboard,
+            last_event_time: xcb::CURRENT_TIME,
+            pending_events: VecDeque::new(),
+
+            xdnd_atoms,
+            xdnd_proxy_for,
+            drop_target: None,
+            drag_source: None,
+
+            keyboard,
+            xinput,
+            scroll_gesture: None,
+            locked_cursor_position: None,
+            mouse_button_counter: 0,
+            mouse_captured: false,
+            input_method: None,
+            ime_allowed: false,
+            ime_opened: false,
+            ime_spot_location: None,
+            has_focus: false,
+            proxy,
+            message_rx,
+            system_scale_changed: false,
             parent_handle,
 
             #[cfg(feature = "opengl")]
             gl_context,
         };
 
-        let mut handler = build(&mut crate::Window::new(&mut window));
-
-        // Send an initial window resized event so the user is alerted of
-        // the correct dpi scaling.
-        handler.on_event(
-            &mut crate::Window::new(&mut window),
-            Event::Window(WindowEvent::Resized(window_info)),
-        );
-
-        let _ = tx.send(Ok(SendableRwh(window.raw_window_handle())));
+        // Querying the monitor's refresh rate requires the window to exist
+        window.update_frame_interval();
+        window.xcb_connection.update_cursor_settings(window.window_info.scale());
 
-        window.run_event_loop(&mut handler);
+        Ok(window)
     }
 
     pub fn set_mouse_cursor(&mut self, mouse_cursor: MouseCursor) {
@@ -437,7 +792,14 @@ impl Window {
             return;
         }
 
-        let xid = self.xcb_connection.get_cursor_xid(mouse_cursor);
+        self.mouse_cursor = mouse_cursor;
+        self.update_mouse_cursor();
+    }
+
+    /// Set the window's cursor to `self.mouse_cursor`. This needs to be called again when the
+    /// scale factor or the cursor settings change, since the cursor's image depends on them.
+    fn update_mouse_cursor(&mut self) {
+        let xid = self.xcb_connection.get_cursor_xid(&self.mouse_cursor, self.window_info.scale());
 
         if xid != 0 {
             xcb::change_window_attributes(
@@ -448,8 +810,99 @@ impl Window {
 
             self.xcb_connection.conn.flush();
         }
+    }
 
-        self.mouse_cursor = mouse_cursor;
+    pub fn set_cursor_position(&mut self, position: Point) {
+        self.warp_cursor(position.to_physical(&self.window_info));
+    }
+
+    pub fn lock_pointer(&mut self) {
+        if self.locked_cursor_position.is_some() {
+            return;
+        }
+
+        let reply = xcb::query_pointer(&self.xcb_connection.conn, self.window_id).get_reply();
+        if let Ok(reply) = reply {
+            self.locked_cursor_position =
+                Some(PhyPoint::new(reply.win_x() as i32, reply.win_y() as i32));
+            if let Some(xinput) = &self.xinput {
+                xinput.select_raw_motion(&self.xcb_connection, true);
+            }
+            self.update_pointer_grab();
+        }
+    }
+
+    pub fn unlock_pointer(&mut self) {
+        if let Some(position) = self.locked_cursor_position.take() {
+            if let Some(xinput) = &self.xinput {
+                xinput.select_raw_motion(&self.xcb_connection, false);
+            }
+            self.update_pointer_grab();
+
+            self.warp_cursor(position);
+        }
+    }
+
+    /// Move the cursor to a physical position relative to the window.
+    fn warp_cursor(&mut self, position: PhyPoint) {
+        xcb::warp_pointer(
+            &self.xcb_connection.conn,
+            xcb::NONE,
+            self.window_id,
+            0,
+            0,
+            0,
+            0,
+            position.x as i16,
+            position.y as i16,
+        );
+        self.xcb_connection.conn.flush();
+    }
+
+    pub fn capture_mouse(&mut self) {
+        self.mouse_captured = true;
+        self.update_pointer_grab();
+    }
+
+    pub fn release_mouse(&mut self) {
+        self.mouse_captured = false;
+        self.update_pointer_grab();
+    }
+
+    /// Grab the pointer while a button is held, the mouse is captured, or the pointer is locked,
+    /// and release the grab otherwise.
+    fn update_pointer_grab(&mut self) {
+        // Drags started from this window use their own grab
+        if self.is_dragging() {
+            return;
+        }
+
+        let conn = &self.xcb_connection.conn;
+        let locked = self.locked_cursor_position.is_some();
+        if self.mouse_button_counter > 0 || self.mouse_captured || locked {
+            // While the pointer is locked, the cursor is confined to the window so it can't leave
+            // the window before it has been moved back
+            let confine_to = if locked { self.window_id } else { xcb::NONE };
+
+            // With `owner_events` set, events inside of the window are reported as usual, and
+            // events outside of it are reported to the window as core events
+            xcb::grab_pointer(
+                conn,
+                true,
+                self.window_id,
+                (xcb::EVENT_MASK_BUTTON_PRESS
+                    | xcb::EVENT_MASK_BUTTON_RELEASE
+                    | xcb::EVENT_MASK_POINTER_MOTION) as u16,
+                xcb::GRAB_MODE_ASYNC as u8,
+                xcb::GRAB_MODE_ASYNC as u8,
+                confine_to,
+                xcb::NONE,
+                xcb::CURRENT_TIME,
+            );
+        } else {
+            xcb::ungrab_pointer(conn, xcb::CURRENT_TIME);
+        }
+        conn.flush();
     }
 
     pub fn close(&mut self) {
@@ -460,14 +913,17 @@ impl Window {
         let scaling = self.window_info.scale();
         self.window_info = WindowInfo::from_logical_size(size, scaling);
 
-        xcb::configure_window(
-            &self.xcb_connection.conn,
-            self.window_id,
-            &[
-                (xcb::CONFIG_WINDOW_WIDTH as u16, self.window_info.physical_size().width),
-                (xcb::CONFIG_WINDOW_HEIGHT as u16, self.window_info.physical_size().height),
-            ],
-        );
+        let physical_size = self.window_info.physical_size();
+        let constrained_size = self.size_constraints.constrain(physical_size, scaling);
+        if constrained_size != physical_size {
+            self.window_info = WindowInfo::from_physical_size(constrained_size, scaling);
+        }
+
+        // A non-resizable window's size hints pin it to its current size
+        if !self.size_constraints.resizable {
+            self.update_size_hints();
+        }
+        self.configure_size(self.window_info.physical_size());
         self.xcb_connection.conn.flush();
     }
 
@@ -476,130 +932,1202 @@ impl Window {
         self.gl_context.as_ref()
     }
 
-    fn find_visual_for_depth(screen: &StructPtr<xcb_screen_t>, depth: u8) -> Option<u32> {
-        for candidate_depth in screen.allowed_depths() {
-            if candidate_depth.depth() != depth {
-                continue;
-            }
+    pub fn proxy(&self) -> WindowProxy {
+        self.proxy.clone()
+    }
 
-            for candidate_visual in candidate_depth.visuals() {
-                if candidate_visual.class() == xcb::VISUAL_CLASS_TRUE_COLOR as u8 {
-                    return Some(candidate_visual.visual_id());
-                }
-            }
-        }
+    pub fn clipboard_get_text(&mut self, kind: ClipboardKind) -> Option<String> {
+        self.clipboard.get_text(
+            &self.xcb_connection,
+            self.window_id,
+            &mut self.pending_events,
+            kind,
+        )
+    }
 
-        None
+    pub fn clipboard_set_text(&mut self, kind: ClipboardKind, text: &str) {
+        let time = self.last_event_time;
+        self.clipboard.set_text(&self.xcb_connection, self.window_id, kind, text, time);
     }
 
-    #[inline]
-    fn drain_xcb_events(&mut self, handler: &mut dyn WindowHandler) {
-        // the X server has a tendency to send spurious/extraneous configure notify events when a
-        // window is resized, and we need to batch those together and just send one resize event
-        // when they've all been coalesced.
-        self.new_physical_size = None;
+    pub fn clipboard_get(&mut self, kind: ClipboardKind, mime_type: &str) -> Option<Vec<u8>> {
+        let target = self.xcb_connection.intern_atom(mime_type);
 
-        while let Some(event) = self.xcb_connection.conn.poll_for_event() {
-            self.handle_xcb_event(handler, event);
-        }
+        self.clipboard.get(
+            &self.xcb_connection,
+            self.window_id,
+            &mut self.pending_events,
+            kind,
+            target,
+        )
+    }
 
-        if let Some(size) = self.new_physical_size.take() {
-            self.window_info = WindowInfo::from_physical_size(size, self.window_info.scale());
+    pub fn clipboard_set(&mut self, kind: ClipboardKind, mime_type: &str, data: Vec<u8>) {
+        let target = self.xcb_connection.intern_atom(mime_type);
+        let contents = HashMap::from([(target, data.into())]);
 
-            let window_info = self.window_info;
+        let time = self.last_event_time;
+        self.clipboard.set(&self.xcb_connection, self.window_id, kind, contents, time);
+    }
 
-            handler.on_event(
-                &mut crate::Window::new(self),
-                Event::Window(WindowEvent::Resized(window_info)),
-            );
+    pub fn start_drag(&mut self, data: DragData, allowed_actions: &[DropAction]) {
+        if self.drag_source.is_some() {
+            return;
         }
-    }
 
-    // Event loop
-    // FIXME: poll() acts fine on linux, sometimes funky on *BSD. XCB upstream uses a define to
-    // switch between poll() and select() (the latter of which is fine on *BSD), and we should do
-    // the same.
-    fn run_event_loop(&mut self, handler: &mut dyn WindowHandler) {
-        use nix::poll::*;
+        let conn = &self.xcb_connection.conn;
+        let atoms = &self.xdnd_atoms;
 
-        let xcb_fd = unsafe {
-            let raw_conn = self.xcb_connection.conn.get_raw_conn();
-            xcb::ffi::xcb_get_file_descriptor(raw_conn)
-        };
+        let contents = drag_contents(&self.xcb_connection, atoms, &data);
+        let types: Vec<u32> = contents.keys().copied().collect();
+        if !self.clipboard.set_selection(
+            &self.xcb_connection,
+            self.window_id,
+            atoms.selection,
+            contents,
+            self.last_event_time,
+        ) {
+            return;
+        }
 
-        let mut next_frame = Instant::now() + self.frame_interval;
-        self.event_loop_running = true;
+        // Targets read the offered types from this property if there are more than three
+        xcb::change_property(
+            conn,
+            xcb::PROP_MODE_REPLACE as u8,
+            self.window_id,
+            atoms.type_list,
+            xcb::ATOM_ATOM,
+            32,
+            &types,
+        );
 
-        while self.event_loop_running {
-            let now = Instant::now();
-            let until_next_frame = if now > next_frame {
-                handler.on_frame(&mut crate::Window::new(self));
+        let mut actions = Vec::new();
+        for &action in allowed_actions {
+            if !actions.contains(&atoms.action_atom(action)) {
+                actions.push(atoms.action_atom(action));
+            }
+        }
+        if actions.is_empty() {
+            actions.push(atoms.action_copy);
+        }
 
-                next_frame = Instant::now() + self.frame_interval;
-                self.frame_interval
-            } else {
-                next_frame - now
-            };
+        // Targets can let the user choose between the actions in this list, in which case we
+        // propose `XdndActionAsk`. Otherwise we propose the only allowed action.
+        xcb::change_property(
+            conn,
+            xcb::PROP_MODE_REPLACE as u8,
+            self.window_id,
+            atoms.action_list,
+            xcb::ATOM_ATOM,
+            32,
+            &actions,
+        );
+        let action = if actions.len() > 1 {
+            // Asking requires a description for every action in the list
+            let descriptions: String = actions
+                .iter()
+                .filter_map(|&action| atoms.atom_action(action))
+                .map(|action| format!("{:?}\0", action))
+                .collect();
+            xcb::change_property(
+                conn,
+                xcb::PROP_MODE_REPLACE as u8,
+                self.window_id,
+                atoms.action_description,
+                xcb::ATOM_STRING,
+                8,
+                descriptions.as_bytes(),
+            );
 
-            let mut fds = [PollFd::new(xcb_fd, PollFlags::POLLIN)];
+            actions.push(atoms.action_ask);
+            atoms.action_ask
+        } else {
+            actions[0]
+        };
 
-            // Check for any events in the internal buffers
-            // before going to sleep:
-            self.drain_xcb_events(handler);
+        // We need to receive all pointer events until the button is released, wherever the
+        // pointer is
+        let cursor = self
+            .xcb_connection
+            .get_cursor_xid(&MouseCursor::HandGrabbing, self.window_info.scale());
+        let conn = &self.xcb_connection.conn;
+        let grabbed = xcb::grab_pointer(
+            conn,
+            false,
+            self.window_id,
+            (xcb::EVENT_MASK_BUTTON_RELEASE | xcb::EVENT_MASK_POINTER_MOTION) as u16,
+            xcb::GRAB_MODE_ASYNC as u8,
+            xcb::GRAB_MODE_ASYNC as u8,
+            xcb::NONE,
+            cursor,
+            xcb::CURRENT_TIME,
+        )
+        .get_reply()
+        .map(|reply| reply.status() == xcb::GRAB_STATUS_SUCCESS as u8)
+        .unwrap_or(false);
 
-            // FIXME: handle errors
-            poll(&mut fds, until_next_frame.subsec_millis() as i32).unwrap();
+        if grabbed {
+            self.drag_source = Some(DragSource::new(types, action, actions));
+        }
+    }
 
-            if let Some(revents) = fds[0].revents() {
-                if revents.contains(PollFlags::POLLERR) {
-                    panic!("xcb connection poll error");
-                }
+    pub fn has_focus(&self) -> bool {
+        self.has_focus
+    }
 
-                if revents.contains(PollFlags::POLLIN) {
-                    self.drain_xcb_events(handler);
-                }
-            }
+    pub fn set_ime_allowed(&mut self, allowed: bool) {
+        if allowed == self.ime_allowed {
+            return;
+        }
 
-            // Check if the parents's handle was dropped (such as when the host
-            // requested the window to close)
-            //
-            // FIXME: This will need to be changed from just setting an atomic to somehow
-            // synchronizing with the window being closed (using a synchronous channel, or
-            // by joining on the event loop thread).
-            if let Some(parent_handle) = &self.parent_handle {
-                if parent_handle.parent_did_drop() {
-                    self.handle_must_close(handler);
-                    self.close_requested = false;
-                }
+        self.ime_allowed = allowed;
+        if allowed && !self.ime_opened {
+            self.ime_opened = true;
+            self.input_method = InputMethod::new(self.window_id);
+            if let (Some(input_method), Some((x, y))) =
+                (&mut self.input_method, self.ime_spot_location)
+            {
+                input_method.set_spot_location(x, y);
             }
+        }
 
-            // Check if the user has requested the window to close
-            if self.close_requested {
-                self.handle_must_close(handler);
-                self.close_requested = false;
+        if let Some(input_method) = &mut self.input_method {
+            if !allowed {
+                input_method.reset();
             }
+            input_method.set_focus(allowed && self.has_focus);
         }
     }
 
-    fn handle_close_requested(&mut self, handler: &mut dyn WindowHandler) {
-        handler.on_event(&mut crate::Window::new(self), Event::Window(WindowEvent::WillClose));
+    pub fn set_ime_cursor_area(&mut self, position: Point, size: Size) {
+        // The candidate window is placed below the spot location, so this shouldn't overlap the
+        // text being edited
+        let scale = self.window_info.scale();
+        let position = position.to_physical(&self.window_info);
+        let height = (size.height * scale).round() as i32;
+        let x = position.x.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        let y = (position.y + height).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+
+        self.ime_spot_location = Some((x, y));
+        if let Some(input_method) = &mut self.input_method {
+            input_method.set_spot_location(x, y);
+        }
+    }
 
-        // FIXME: handler should decide whether window stays open or not
-        self.event_loop_running = false;
+    pub fn request_redraw(&mut self) {
+        self.redraw_requested = true;
     }
 
-    fn handle_must_close(&mut self, handler: &mut dyn WindowHandler) {
-        handler.on_event(&mut crate::Window::new(self), Event::Window(WindowEvent::WillClose));
+    pub fn set_frame_rate(&mut self, frame_rate: FrameRate) {
+        self.frame_rate = frame_rate;
+        self.update_frame_interval();
+    }
 
-        self.event_loop_running = false;
+    fn update_frame_interval(&mut self) {
+        self.frame_interval = self.frame_rate.fixed_interval().unwrap_or_else(|| {
+            let refresh_rate = self
+                .monitor_refresh_rate()
+                .filter(|refresh_rate| *refresh_rate > 0.0)
+                .unwrap_or(FrameRate::FALLBACK_HZ);
+
+            Duration::from_secs_f64(1.0 / refresh_rate)
+        });
     }
 
-    fn handle_xcb_event(&mut self, handler: &mut dyn WindowHandler, event: xcb::GenericEvent) {
-        let event_type = event.response_type() & !0x80;
+    /// The refresh rate of the monitor the center of the window is on.
+    fn monitor_refresh_rate(&self) -> Option<f64> {
+        let size = self.window_info.physical_size();
+        let root = self.xcb_connection.root_window();
+        let center = xcb::translate_coordinates(
+            &self.xcb_connection.conn,
+            self.window_id,
+            root,
+            (size.width / 2) as i16,
+            (size.height / 2) as i16,
+        )
+        .get_reply()
+        .ok()?;
 
-        // For all of the keyboard and mouse events, you can fetch
-        // `x`, `y`, `detail`, and `state`.
-        // - `x` and `y` are the position inside the window where the cursor currently is
+        self.xcb_connection.get_monitor_refresh_rate(center.dst_x() as i32, center.dst_y() as i32)
+    }
+
+    pub fn position(&self) -> Point {
+        self.position.to_logical(&self.window_info)
+    }
+
+    pub fn set_position(&mut self, position: Point) {
+        self.position = position.to_physical(&self.window_info);
+
+        xcb::configure_window(
+            &self.xcb_connection.conn,
+            self.window_id,
+            &[
+                (xcb::CONFIG_WINDOW_X as u16, self.position.x as u32),
+                (xcb::CONFIG_WINDOW_Y as u16, self.position.y as u32),
+            ],
+        );
+        self.xcb_connection.conn.flush();
+    }
+
+    pub fn set_min_size(&mut self, min_size: Option<Size>) {
+        self.size_constraints.min_size = min_size;
+        self.apply_size_constraints();
+    }
+
+    pub fn set_max_size(&mut self, max_size: Option<Size>) {
+        self.size_constraints.max_size = max_size;
+        self.apply_size_constraints();
+    }
+
+    pub fn set_resizable(&mut self, resizable: bool) {
+        self.size_constraints.resizable = resizable;
+        self.apply_size_constraints();
+    }
+
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: Option<f64>) {
+        self.size_constraints.aspect_ratio = aspect_ratio;
+        self.apply_size_constraints();
+    }
+
+    /// Update the window's size hints and resize the window if its current size no longer
+    /// satisfies the constraints. The resize is picked up again by `drain_xcb_events()`.
+    fn apply_size_constraints(&mut self) {
+        self.update_size_hints();
+
+        let size = self.window_info.physical_size();
+        let constrained_size = self.size_constraints.constrain(size, self.window_info.scale());
+        if constrained_size != size {
+            self.configure_size(constrained_size);
+        }
+
+        self.xcb_connection.conn.flush();
+    }
+
+    /// Write the size constraints to the window's `WM_NORMAL_HINTS` so the window manager can
+    /// enforce them during interactive resizes.
+    fn update_size_hints(&self) {
+        let size_hints = self.size_constraints.size_hints(&self.window_info).build();
+        let conn = &self.xcb_connection.conn;
+        xcb_util::icccm::set_wm_normal_hints(conn, self.window_id, &size_hints);
+    }
+
+    fn configure_size(&self, size: PhySize) {
+        xcb::configure_window(
+            &self.xcb_connection.conn,
+            self.window_id,
+            &[
+                (xcb::CONFIG_WINDOW_WIDTH as u16, size.width),
+                (xcb::CONFIG_WINDOW_HEIGHT as u16, size.height),
+            ],
+        );
+    }
+
+    /// Compute the physical position the window should be created at, relative to the parent
+    /// window or to the root window if there's no parent.
+    fn initial_position(
+        conn: &xcb::Connection, screen: &xcb::Screen, parent: Option<u32>,
+        position: WindowPosition, window_info: &WindowInfo,
+    ) -> PhyPoint {
+        let size = window_info.physical_size();
+        let centered_in = |width: u16, height: u16| {
+            PhyPoint::new(
+                (width as i32 - size.width as i32) / 2,
+                (height as i32 - size.height as i32) / 2,
+            )
+        };
+        let centered_on_screen = || {
+            let position = centered_in(screen.width_in_pixels(), screen.height_in_pixels());
+            match parent {
+                // The window's position is relative to its parent
+                Some(parent) => xcb::translate_coordinates(
+                    conn,
+                    screen.root(),
+                    parent,
+                    position.x as i16,
+                    position.y as i16,
+                )
+                .get_reply()
+                .map(|reply| PhyPoint::new(reply.dst_x() as i32, reply.dst_y() as i32))
+                .unwrap_or(position),
+                None => position,
+            }
+        };
+
+        match position {
+            WindowPosition::Default => PhyPoint::new(0, 0),
+            WindowPosition::Absolute(position) => position.to_physical(window_info),
+            WindowPosition::CenteredOnScreen => centered_on_screen(),
+            WindowPosition::CenteredOnParent => match parent {
+                Some(parent) => xcb::get_geometry(conn, parent)
+                    .get_reply()
+                    .map(|geometry| centered_in(geometry.width(), geometry.height()))
+                    .unwrap_or_else(|_| centered_on_screen()),
+                None => centered_on_screen(),
+            },
+        }
+    }
+
+    fn find_visual_for_depth(screen: &StructPtr<xcb_screen_t>, depth: u8) -> Option<u32> {
+        for candidate_depth in screen.allowed_depths() {
+            if candidate_depth.depth() != depth {
+                continue;
+            }
+
+            for candidate_visual in candidate_depth.visuals() {
+                if candidate_visual.class() == xcb::VISUAL_CLASS_TRUE_COLOR as u8 {
+                    return Some(candidate_visual.visual_id());
+                }
+            }
+        }
+
+        None
+    }
+
+    #[inline]
+    fn drain_xcb_events(&mut self, handler: &mut dyn WindowHandler) {
+        // the X server has a tendency to send spurious/extraneous configure notify events when a
+        // window is resized, and we need to batch those together and just send one resize event
+        // when they've all been coalesced.
+        self.new_physical_size = None;
+        self.new_position = None;
+        self.query_position = false;
+
+        while let Some(event) =
+            self.pending_events.pop_front().or_else(|| self.xcb_connection.conn.poll_for_event())
+        {
+            self.handle_xcb_event(handler, event);
+        }
+
+        let previous_scale = self.window_info.scale();
+        let mut scale = previous_scale;
+        let settings_changed = std::mem::take(&mut self.system_scale_changed);
+        if settings_changed && self.scale_policy == WindowScalePolicy::SystemScaleFactor {
+            // Like on Windows, the physical size stays the same when the scale factor changes
+            let new_scale = self.xcb_connection.get_scaling().unwrap_or(1.0);
+            if new_scale != scale {
+                scale = new_scale;
+                self.new_physical_size.get_or_insert(self.window_info.physical_size());
+            }
+        }
+
+        if let Some(size) = self.new_physical_size.take() {
+            // The window manager should already respect the size hints, but parented windows and
+            // some window managers don't
+            let constrained_size = self.size_constraints.constrain(size, scale);
+            if constrained_size != size {
+                self.configure_size(constrained_size);
+            }
+
+            let scale_changed = scale != self.window_info.scale();
+            self.window_info = WindowInfo::from_physical_size(constrained_size, scale);
+            if scale_changed || !self.size_constraints.resizable {
+                self.update_size_hints();
+            }
+
+            let window_info = self.window_info;
+
+            handler.on_event(
+                &mut crate::Window::new(self),
+                Event::Window(WindowEvent::Resized(window_info)),
+            );
+        }
+
+        // System cursors depend on the cursor settings and the scale factor, and custom cursors
+        // only depend on the scale factor
+        let scale_changed = self.window_info.scale() != previous_scale;
+        if settings_changed || scale_changed {
+            let cursor_settings_changed =
+                self.xcb_connection.update_cursor_settings(self.window_info.scale());
+            if cursor_settings_changed
+                || (scale_changed && matches!(self.mouse_cursor, MouseCursor::Custom(_)))
+            {
+                self.update_mouse_cursor();
+            }
+        }
+
+        if std::mem::take(&mut self.query_position) {
+            let conn = &self.xcb_connection.conn;
+            let root = self.xcb_connection.root_window();
+            let reply = xcb::translate_coordinates(conn, self.window_id, root, 0, 0).get_reply();
+            if let Ok(reply) = reply {
+                let position = PhyPoint::new(reply.dst_x() as i32, reply.dst_y() as i32);
+                if self.new_position.is_some() || position != self.position {
+                    self.new_position = Some(position);
+                }
+            }
+        }
+
+        if let Some(position) = self.new_position.take() {
+            self.position = position;
+
+            // The window may have been moved to another monitor
+            if self.frame_rate == FrameRate::MonitorRefreshRate {
+                self.update_frame_interval();
+            }
+
+            let logical_position = position.to_logical(&self.window_info);
+
+            handler.on_event(
+                &mut crate::Window::new(self),
+                Event::Window(WindowEvent::Moved(logical_position)),
+            );
+        }
+
+        if !self.damaged.is_empty() {
+            let damaged = std::mem::take(&mut self.damaged);
+
+            handler.on_event(
+                &mut crate::Window::new(self),
+                Event::Window(WindowEvent::Damaged(damaged)),
+            );
+        }
+
+        // Don't wait forever if the drop target never finishes
+        if let Some(dropped_at) = self.drag_source.as_ref().and_then(|source| source.dropped_at) {
+            if dropped_at.elapsed() > DROP_TIMEOUT {
+                self.end_drag(handler, None);
+            }
+        }
+
+        if let Some((last_scroll, modifiers)) = self.scroll_gesture {
+            if last_scroll.elapsed() >= SCROLL_GESTURE_TIMEOUT {
+                self.scroll_gesture = None;
+
+                handler.on_event(
+                    &mut crate::Window::new(self),
+                    Event::Mouse(MouseEvent::WheelScrolled {
+                        delta: ScrollDelta::Pixels { x: 0.0, y: 0.0 },
+                        phase: ScrollPhase::Ended,
+                        modifiers,
+                    }),
+                );
+            }
+        }
+    }
+
+    fn handle_xinput2_event(&mut self, handler: &mut dyn WindowHandler, event: XInput2Event) {
+        match &event {
+            XInput2Event::Motion { pointer, .. }
+            | XInput2Event::ButtonPress { pointer, .. }
+            | XInput2Event::ButtonRelease { pointer, .. }
+            | XInput2Event::Enter { pointer } => self.last_event_time = pointer.time,
+            _ => {}
+        }
+
+        match event {
+            XInput2Event::Motion { pointer, moved, scroll, scroll_gestures } => {
+                if self.is_dragging() {
+                    self.handle_drag_motion(
+                        pointer.root_x as i16,
+                        pointer.root_y as i16,
+                        pointer.time,
+                    );
+                    return;
+                }
+
+                let modifiers = self.keyboard.modifiers(pointer.state);
+                if moved {
+                    self.handle_cursor_moved(handler, pointer.event_x, pointer.event_y, modifiers);
+                }
+
+                if let Some(delta) = scroll {
+                    // Mouse wheels scroll in separate steps
+                    let phase = if !scroll_gestures || self.scroll_gesture.is_some() {
+                        ScrollPhase::Moved
+                    } else {
+                        ScrollPhase::Started
+                    };
+                    if scroll_gestures {
+                        self.scroll_gesture = Some((Instant::now(), modifiers));
+                    }
+
+                    handler.on_event(
+                        &mut crate::Window::new(self),
+                        Event::Mouse(MouseEvent::WheelScrolled { delta, phase, modifiers }),
+                    );
+                }
+            }
+            XInput2Event::ButtonPress { pointer, button, emulated } => {
+                if !emulated {
+                    let modifiers = self.keyboard.modifiers(pointer.state);
+                    self.handle_button_press(handler, button, modifiers);
+                }
+            }
+            XInput2Event::ButtonRelease { pointer, button, emulated } => {
+                if self.drag_source.is_some() {
+                    self.handle_drag_release(handler, pointer.time);
+                }
+
+                if !emulated {
+                    self.handle_button_release(handler, button, pointer.state);
+                }
+            }
+            XInput2Event::Enter { pointer } => {
+                let modifiers = self.keyboard.modifiers(pointer.state);
+                self.handle_cursor_entered(handler, pointer.event_x, pointer.event_y, modifiers);
+            }
+            XInput2Event::Leave => {
+                handler
+                    .on_event(&mut crate::Window::new(self), Event::Mouse(MouseEvent::CursorLeft));
+            }
+            XInput2Event::RawMotion { dx, dy } => {
+                // Raw events are sent no matter where the pointer is
+                if self.locked_cursor_position.is_some() && (dx != 0.0 || dy != 0.0) {
+                    handler.on_event(
+                        &mut crate::Window::new(self),
+                        Event::Mouse(MouseEvent::RawMotion { dx, dy }),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Send a `CursorEntered` event followed by a `CursorMoved` event for the position where the
+    /// pointer entered the window.
+    fn handle_cursor_entered(
+        &mut self, handler: &mut dyn WindowHandler, x: f64, y: f64, modifiers: Modifiers,
+    ) {
+        handler.on_event(&mut crate::Window::new(self), Event::Mouse(MouseEvent::CursorEntered));
+        self.handle_cursor_moved(handler, x, y, modifiers);
+    }
+
+    /// Send a `CursorMoved` event for a pointer position in physical pixels.
+    fn handle_cursor_moved(
+        &mut self, handler: &mut dyn WindowHandler, x: f64, y: f64, modifiers: Modifiers,
+    ) {
+        // While the pointer is locked it's moved back after every movement. This also causes
+        // another motion event at the locked position, which is ignored.
+        if let Some(locked_position) = self.locked_cursor_position {
+            let dx = x - locked_position.x as f64;
+            let dy = y - locked_position.y as f64;
+            if dx != 0.0 || dy != 0.0 {
+                self.warp_cursor(locked_position);
+
+                // Without XInput2 there are no raw motion events, so the movement is taken from
+                // the regular motion events instead
+                if self.xinput.is_none() {
+                    handler.on_event(
+                        &mut crate::Window::new(self),
+                        Event::Mouse(MouseEvent::RawMotion { dx, dy }),
+                    );
+                }
+            }
+
+            return;
+        }
+
+        let scale_recip = self.window_info.scale_recip();
+        let position = Point::new(x * scale_recip, y * scale_recip);
+
+        handler.on_event(
+            &mut crate::Window::new(self),
+            Event::Mouse(MouseEvent::CursorMoved { position, modifiers }),
+        );
+    }
+
+    fn handle_button_press(
+        &mut self, handler: &mut dyn WindowHandler, button: u8, modifiers: Modifiers,
+    ) {
+        // Buttons 4 to 7 scroll up, down, left and right
+        let event = match button {
+            4..=7 => {
+                let (x, y) = match button {
+                    4 => (0.0, 1.0),
+                    5 => (0.0, -1.0),
+                    6 => (1.0, 0.0),
+                    _ => (-1.0, 0.0),
+                };
+
+                MouseEvent::WheelScrolled {
+                    delta: ScrollDelta::Lines { x, y },
+                    phase: ScrollPhase::Moved,
+                    modifiers,
+                }
+            }
+            button => {
+                self.mouse_button_counter = self.mouse_button_counter.saturating_add(1);
+                self.update_pointer_grab();
+
+                MouseEvent::ButtonPressed { button: mouse_id(button), modifiers }
+            }
+        };
+
+        handler.on_event(&mut crate::Window::new(self), Event::Mouse(event));
+    }
+
+    /// Handle a button release. `state` is the core modifier and button state from just before
+    /// the release.
+    fn handle_button_release(&mut self, handler: &mut dyn WindowHandler, button: u8, state: u16) {
+        if !(4..=7).contains(&button) {
+            // The counter gets out of sync when a press or release is missed, so the grab is
+            // released once the event's state says that no other buttons are held
+            let button_mask = match button {
+                1..=5 => xcb::BUTTON_MASK_1 << (button - 1),
+                _ => 0,
+            };
+            let button_masks = xcb::BUTTON_MASK_1
+                | xcb::BUTTON_MASK_2
+                | xcb::BUTTON_MASK_3
+                | xcb::BUTTON_MASK_4
+                | xcb::BUTTON_MASK_5;
+            if state as u32 & button_masks & !button_mask == 0 {
+                self.mouse_button_counter = 0;
+            } else {
+                self.mouse_button_counter = self.mouse_button_counter.saturating_sub(1).max(1);
+            }
+            if self.mouse_button_counter == 0 {
+                self.update_pointer_grab();
+            }
+
+            let modifiers = self.keyboard.modifiers(state);
+            handler.on_event(
+                &mut crate::Window::new(self),
+                Event::Mouse(MouseEvent::ButtonReleased { button: mouse_id(button), modifiers }),
+            );
+        }
+    }
+
+    /// Forget about all held buttons and release their grab. Their releases may never arrive
+    /// once the window loses the focus or is unmapped.
+    fn reset_mouse_buttons(&mut self) {
+        if self.mouse_button_counter > 0 {
+            self.mouse_button_counter = 0;
+            self.update_pointer_grab();
+        }
+    }
+
+    fn handle_focus_change(&mut self, handler: &mut dyn WindowHandler, has_focus: bool) {
+        if has_focus == self.has_focus {
+            return;
+        }
+
+        self.has_focus = has_focus;
+        if !has_focus {
+            self.keyboard.focus_lost();
+            self.reset_mouse_buttons();
+        }
+        if let Some(input_method) = &mut self.input_method {
+            input_method.set_focus(has_focus && self.ime_allowed);
+        }
+
+        let event = if has_focus { WindowEvent::Focused } else { WindowEvent::Unfocused };
+        handler.on_event(&mut crate::Window::new(self), Event::Window(event));
+    }
+
+    /// Without XKB's detectable auto-repeat, X11 sends a key release event followed by a key press
+    /// event with the same timestamp for every repeated key press. Returns `true` if `event` is
+    /// such a release event, in which case it should be ignored.
+    fn is_auto_repeat_release(&mut self, event: &xcb::KeyReleaseEvent) -> bool {
+        if self.keyboard.detectable_auto_repeat() {
+            return false;
+        }
+
+        let is_repeat = |next_event: &xcb::GenericEvent| {
+            next_event.response_type() & !0x80 == xcb::KEY_PRESS && {
+                let next_event = unsafe { xcb::cast_event::<xcb::KeyPressEvent>(next_event) };
+                next_event.detail() == event.detail() && next_event.time() == event.time()
+            }
+        };
+
+        // Our own queue is handled before XCB's, so if the release came from there the matching
+        // key press is the next event in it
+        if let Some(next_event) = self.pending_events.front() {
+            return is_repeat(next_event);
+        }
+
+        // Otherwise the key press is sent together with the release, so it should already be in
+        // XCB's queue. It's put in our own queue so it's handled next.
+        let next_event = match self.xcb_connection.conn.poll_for_queued_event() {
+            Some(next_event) => next_event,
+            None => return false,
+        };
+        let is_repeat = is_repeat(&next_event);
+        self.pending_events.push_back(next_event);
+
+        is_repeat
+    }
+
+    /// Pass a key event to the input method if it's enabled. Returns `true` if the input method
+    /// consumed the event.
+    fn filter_ime_key_event(
+        &mut self, handler: &mut dyn WindowHandler, event: &xcb::KeyPressEvent, pressed: bool,
+    ) -> bool {
+        let input_method = match &mut self.input_method {
+            Some(input_method) if self.ime_allowed => input_method,
+            _ => return false,
+        };
+
+        let filtered = input_method.filter_key_event(event, pressed);
+        self.drain_ime_events(handler);
+
+        filtered
+    }
+
+    /// Handle the messages from the input method and send the resulting events to the handler.
+    fn drain_ime_events(&mut self, handler: &mut dyn WindowHandler) {
+        let events = match &mut self.input_method {
+            Some(input_method) => {
+                input_method.process_events();
+                input_method.take_events()
+            }
+            None => return,
+        };
+
+        for event in events {
+            let event = match event {
+                InputMethodEvent::Ime(event) => Event::Ime(event),
+                InputMethodEvent::Key(event, true) => {
+                    Event::Keyboard(self.keyboard.convert_key_press_event(&event))
+                }
+                InputMethodEvent::Key(event, false) => {
+                    Event::Keyboard(self.keyboard.convert_key_release_event(&event))
+                }
+            };
+
+            handler.on_event(&mut crate::Window::new(self), event);
+        }
+    }
+
+    // Event loop
+    // FIXME: poll() acts fine on linux, sometimes funky on *BSD. XCB upstream uses a define to
+    // switch between poll() and select() (the latter of which is fine on *BSD), and we should do
+    // the same.
+    fn run_event_loop(&mut self, handler: &mut dyn WindowHandler) {
+        use nix::poll::*;
+
+        let xcb_fd = unsafe {
+            let raw_conn = self.xcb_connection.conn.get_raw_conn();
+            xcb::ffi::xcb_get_file_descriptor(raw_conn)
+        };
+
+        let mut next_frame = Instant::now() + self.frame_interval;
+        self.event_loop_running = true;
+
+        while self.event_loop_running {
+            if Instant::now() >= next_frame {
+                handler.on_frame(&mut crate::Window::new(self));
+
+                // The next frame is scheduled relative to the previous deadline instead of to the
+                // current time so the frame rate doesn't drift. If we've fallen more than a frame
+                // behind, the missed frames are skipped instead of being run back to back.
+                next_frame += self.frame_interval;
+                let now = Instant::now();
+                if next_frame <= now {
+                    next_frame = now + self.frame_interval;
+                }
+            }
+
+            // A scroll gesture may need to be ended before the next frame
+            let wake_at = match self.scroll_gesture {
+                Some((last_scroll, _)) => next_frame.min(last_scroll + SCROLL_GESTURE_TIMEOUT),
+                None => next_frame,
+            };
+
+            // Round up so we don't wake up just before the deadline and then spin until it's
+            // reached
+            let until_wake = wake_at.saturating_duration_since(Instant::now());
+            let timeout_ms = until_wake.as_nanos().div_ceil(1_000_000);
+            let timeout_ms = timeout_ms.min(i32::MAX as u128) as i32;
+
+            // poll() ignores negative file descriptors
+            let ime_fd = self.input_method.as_ref().map_or(-1, |input_method| input_method.fd());
+            let mut fds = [
+                PollFd::new(xcb_fd, PollFlags::POLLIN),
+                PollFd::new(self.proxy.waker.fd(), PollFlags::POLLIN),
+                PollFd::new(ime_fd, PollFlags::POLLIN),
+            ];
+
+            // Check for any events in the internal buffers
+            // before going to sleep:
+            self.drain_xcb_events(handler);
+            self.drain_ime_events(handler);
+
+            if std::mem::take(&mut self.redraw_requested) {
+                handler.on_redraw(&mut crate::Window::new(self));
+            }
+
+            // Don't go to sleep if the handler requested another redraw during the last one, or if
+            // it read from the clipboard and we still need to handle the events received meanwhile
+            let timeout_ms = if self.redraw_requested || !self.pending_events.is_empty() {
+                0
+            } else {
+                timeout_ms
+            };
+
+            // FIXME: handle errors
+            poll(&mut fds, timeout_ms).unwrap();
+
+            if let Some(revents) = fds[0].revents() {
+                if revents.contains(PollFlags::POLLERR) {
+                    panic!("xcb connection poll error");
+                }
+
+                if revents.contains(PollFlags::POLLIN) {
+                    self.drain_xcb_events(handler);
+                }
+            }
+
+            if let Some(revents) = fds[1].revents() {
+                if revents.contains(PollFlags::POLLIN) {
+                    self.proxy.waker.reset();
+                    self.handle_proxy_wakeup(handler);
+                }
+            }
+
+            if let Some(revents) = fds[2].revents() {
+                if revents.contains(PollFlags::POLLIN) {
+                    self.drain_ime_events(handler);
+                }
+            }
+
+            // Check if the parents's handle was closed or dropped (such as when the host
+            // requested the window to close)
+            if let Some(parent_handle) = &self.parent_handle {
+                if parent_handle.parent_did_drop() {
+                    self.handle_must_close(handler);
+                    self.close_requested = false;
+                }
+            }
+
+            // Check if the user has requested the window to close
+            if self.close_requested {
+                self.handle_must_close(handler);
+                self.close_requested = false;
+            }
+        }
+    }
+
+    /// Deliver the messages and wakeups from the window's `WindowProxy`s.
+    fn handle_proxy_wakeup(&mut self, handler: &mut dyn WindowHandler) {
+        if self.proxy.woken.swap(false, Ordering::AcqRel) {
+            handler.on_wake(&mut crate::Window::new(self));
+        }
+
+        while let Ok(message) = self.message_rx.try_recv() {
+            handler.on_message(&mut crate::Window::new(self), message);
+        }
+    }
+
+    /// Handle the client messages sent by a drag source while something is dragged over the
+    /// window.
+    fn handle_xdnd_message(
+        &mut self, handler: &mut dyn WindowHandler, window: u32, type_: u32, data: &[u32],
+    ) {
+        let XdndAtoms { enter, position, leave, drop, status, finished, .. } = self.xdnd_atoms;
+
+        // These are replies from the target of a drag started from this window
+        if type_ == status || type_ == finished {
+            self.handle_xdnd_target_reply(handler, type_, data);
+            return;
+        }
+
+        if type_ == enter {
+            // A new drag replaces any drag whose `XdndLeave` message we missed
+            self.handle_xdnd_leave(handler);
+
+            self.drop_target = Some(DropTarget::from_enter_message(
+                &self.xcb_connection,
+                &self.xdnd_atoms,
+                window,
+                data,
+            ));
+            return;
+        }
+
+        // All other messages need to be from the source of the current drag
+        let source = data[0];
+        match &self.drop_target {
+            Some(drop_target) if drop_target.source == source => (),
+            _ => return,
+        }
+
+        if type_ == position {
+            let root_x = (data[2] >> 16) as i16;
+            let root_y = (data[2] & 0xffff) as i16;
+            let time = data[3];
+            // Sources before version 2 leave this at `None`, which is treated as a copy
+            if let Some(drop_target) = &mut self.drop_target {
+                drop_target.action = data[4];
+            }
+            self.handle_xdnd_position(handler, root_x, root_y, time);
+        } else if type_ == leave {
+            self.handle_xdnd_leave(handler);
+        } else if type_ == drop {
+            self.handle_xdnd_drop(handler);
+        }
+    }
+
+    fn handle_xdnd_position(
+        &mut self, handler: &mut dyn WindowHandler, root_x: i16, root_y: i16, time: u32,
+    ) {
+        let mut drop_target = match self.drop_target.take() {
+            Some(drop_target) => drop_target,
+            None => return,
+        };
+
+        // The position is in root window coordinates, and we may be parented inside another window
+        let position = xcb::translate_coordinates(
+            &self.xcb_connection.conn,
+            self.xcb_connection.root_window(),
+            self.window_id,
+            root_x,
+            root_y,
+        )
+        .get_reply()
+        .map(|reply| PhyPoint::new(reply.dst_x() as i32, reply.dst_y() as i32))
+        .unwrap_or(PhyPoint::new(0, 0));
+        drop_target.position = position.to_logical(&self.window_info);
+
+        // When the messages are forwarded through `XdndProxy`, the pointer can also be over other
+        // parts of the host's window
+        let size = self.window_info.physical_size();
+        let inside = (0..size.width as i32).contains(&position.x)
+            && (0..size.height as i32).contains(&position.y);
+        if !inside {
+            if drop_target.entered {
+                drop_target.entered = false;
+                handler
+                    .on_event(&mut crate::Window::new(self), Event::DragDrop(DragDropEvent::Left));
+            }
+        } else if drop_target.data.is_none() {
+            drop_target.data = drop_target.fetch_data(
+                &self.xcb_connection,
+                &self.xdnd_atoms,
+                &mut self.clipboard,
+                self.window_id,
+                &mut self.pending_events,
+                time,
+            );
+        }
+
+        drop_target.accepted = match drop_target.data.clone() {
+            Some(data) if inside => {
+                let position = drop_target.position;
+                let action = drop_target.drop_action(&self.xdnd_atoms);
+                let event = if drop_target.entered {
+                    DragDropEvent::Moved { position, data, action }
+                } else {
+                    DragDropEvent::Entered { position, data, action }
+                };
+                drop_target.entered = true;
+
+                let status =
+                    handler.on_event(&mut crate::Window::new(self), Event::DragDrop(event));
+                status == EventStatus::Captured
+            }
+            _ => false,
+        };
+
+        send_xdnd_message(
+            &self.xcb_connection.conn,
+            drop_target.source,
+            self.xdnd_atoms.status,
+            drop_target.status_message(&self.xdnd_atoms),
+        );
+
+        self.drop_target = Some(drop_target);
+    }
+
+    fn handle_xdnd_leave(&mut self, handler: &mut dyn WindowHandler) {
+        if let Some(drop_target) = self.drop_target.take() {
+            if drop_target.entered {
+                handler
+                    .on_event(&mut crate::Window::new(self), Event::DragDrop(DragDropEvent::Left));
+            }
+        }
+    }
+
+    fn handle_xdnd_drop(&mut self, handler: &mut dyn WindowHandler) {
+        let mut drop_target = match self.drop_target.take() {
+            Some(drop_target) => drop_target,
+            None => return,
+        };
+
+        let accepted = match drop_target.data.take() {
+            Some(data) if drop_target.accepted => {
+                let position = drop_target.position;
+                let action = drop_target.drop_action(&self.xdnd_atoms);
+                let event = DragDropEvent::Dropped { position, data, action };
+                let status =
+                    handler.on_event(&mut crate::Window::new(self), Event::DragDrop(event));
+                status == EventStatus::Captured
+            }
+            _ => {
+                if drop_target.entered {
+                    handler.on_event(
+                        &mut crate::Window::new(self),
+                        Event::DragDrop(DragDropEvent::Left),
+                    );
+                }
+
+                false
+            }
+        };
+
+        send_xdnd_message(
+            &self.xcb_connection.conn,
+            drop_target.source,
+            self.xdnd_atoms.finished,
+            drop_target.finished_message(&self.xdnd_atoms, accepted),
+        );
+    }
+
+    /// Find the window under the pointer and tell it where the data is being dragged.
+    fn handle_drag_motion(&mut self, root_x: i16, root_y: i16, time: u32) {
+        let target = find_xdnd_target(&self.xcb_connection, &self.xdnd_atoms, root_x, root_y);
+
+        let conn = &self.xcb_connection.conn;
+        let atoms = &self.xdnd_atoms;
+        let source = match &mut self.drag_source {
+            Some(source) if source.dropped_at.is_none() => source,
+            _ => return,
+        };
+
+        if target.map(|target| target.window) != source.target.map(|target| target.window) {
+            if let Some(old_target) = source.target {
+                old_target.send_message(conn, atoms.leave, [self.window_id, 0, 0, 0, 0]);
+            }
+
+            source.target = target;
+            source.accepted = false;
+            source.waiting_for_status = false;
+            source.pending_position = None;
+
+            if let Some(new_target) = source.target {
+                new_target.send_message(conn, atoms.enter, source.enter_message(self.window_id));
+            }
+        }
+
+        let target = match source.target {
+            Some(target) => target,
+            None => return,
+        };
+        let position = ((root_x as u16 as u32) << 16) | root_y as u16 as u32;
+        if source.waiting_for_status {
+            source.pending_position = Some((position, time));
+        } else {
+            let data = source.position_message(self.window_id, position, time);
+            target.send_message(conn, atoms.position, data);
+            source.waiting_for_status = true;
+        }
+    }
+
+    /// Drop the data if the target accepted it, or cancel the drag otherwise.
+    fn handle_drag_release(&mut self, handler: &mut dyn WindowHandler, time: u32) {
+        let conn = &self.xcb_connection.conn;
+        xcb::ungrab_pointer(conn, xcb::CURRENT_TIME);
+        conn.flush();
+
+        let source = match &mut self.drag_source {
+            Some(source) if source.dropped_at.is_none() => source,
+            _ => return,
+        };
+
+        match source.target {
+            Some(target) if source.accepted => {
+                target.send_message(conn, self.xdnd_atoms.drop, [self.window_id, 0, time, 0, 0]);
+                source.dropped_at = Some(Instant::now());
+            }
+            target => {
+                if let Some(target) = target {
+                    target.send_message(conn, self.xdnd_atoms.leave, [self.window_id, 0, 0, 0, 0]);
+                }
+
+                self.end_drag(handler, None);
+            }
+        }
+    }
+
+    /// Handle the `XdndStatus` and `XdndFinished` messages sent by the target of a drag started
+    /// from this window.
+    fn handle_xdnd_target_reply(
+        &mut self, handler: &mut dyn WindowHandler, type_: u32, data: &[u32],
+    ) {
+        let conn = &self.xcb_connection.conn;
+        let atoms = &self.xdnd_atoms;
+        let (source, target) = match &mut self.drag_source {
+            Some(source) => match source.target {
+                Some(target) if target.window == data[0] => (source, target),
+                _ => return,
+            },
+            None => return,
+        };
+
+        if type_ == atoms.status {
+            source.handle_status(atoms, data);
+
+            // The pointer has moved since the last position we sent
+            if source.dropped_at.is_none() {
+                if let Some((position, time)) = source.pending_position.take() {
+                    let data = source.position_message(self.window_id, position, time);
+                    target.send_message(conn, atoms.position, data);
+                    source.waiting_for_status = true;
+                }
+            }
+        } else if type_ == atoms.finished && source.dropped_at.is_some() {
+            let action = source.finished_action(atoms, data);
+            self.end_drag(handler, action);
+        }
+    }
+
+    /// Whether the data of a drag started from this window hasn't been dropped yet. The pointer is
+    /// grabbed during that time.
+    fn is_dragging(&self) -> bool {
+        self.drag_source.as_ref().is_some_and(|source| source.dropped_at.is_none())
+    }
+
+    fn end_drag(&mut self, handler: &mut dyn WindowHandler, action: Option<DropAction>) {
+        self.drag_source = None;
+
+        handler.on_event(
+            &mut crate::Window::new(self),
+            Event::DragDrop(DragDropEvent::DragEnded { action }),
+        );
+    }
+
+    fn handle_close_requested(&mut self, handler: &mut dyn WindowHandler) {
+        let status = handler
+            .on_event(&mut crate::Window::new(self), Event::Window(WindowEvent::CloseRequested));
+
+        // The handler can veto the close request, e.g. to ask the user to save their changes
+        if status == EventStatus::Captured {
+            return;
+        }
+
+        self.handle_must_close(handler);
+    }
+
+    fn handle_must_close(&mut self, handler: &mut dyn WindowHandler) {
+        handler.on_event(&mut crate::Window::new(self), Event::Window(WindowEvent::WillClose));
+
+        self.event_loop_running = false;
+    }
+
+    fn handle_xcb_event(&mut self, handler: &mut dyn WindowHandler, event: xcb::GenericEvent) {
+        if self.keyboard.handle_xkb_event(&self.xcb_connection, &event) {
+            return;
+        }
+
+        let xinput_event = match &mut self.xinput {
+            Some(xinput) => xinput.handle_event(&self.xcb_connection, &event),
+            None => None,
+        };
+        if let Some(xinput_event) = xinput_event {
+            self.handle_xinput2_event(handler, xinput_event);
+            return;
+        }
+
+        let event_type = event.response_type() & !0x80;
+
+        // All input events start with the same fields as key press events
+        if (xcb::KEY_PRESS..=xcb::LEAVE_NOTIFY).contains(&event_type) {
+            let event = unsafe { xcb::cast_event::<xcb::KeyPressEvent>(&event) };
+            self.last_event_time = event.time();
+        }
+
+        // For all of the keyboard and mouse events, you can fetch
+        // `x`, `y`, `detail`, and `state`.
+        // - `x` and `y` are the position inside the window where the cursor currently is
         //   when the event happened.
         // - `detail` will tell you which keycode was pressed/released (for keyboard events)
         //   or which mouse button was pressed/released (for mouse events).
@@ -628,17 +2156,88 @@ impl Window {
                 let data = event.data().data;
                 let (_, data32, _) = unsafe { data.align_to::<u32>() };
 
-                let wm_delete_window =
-                    self.xcb_connection.atoms.wm_delete_window.unwrap_or(xcb::NONE);
+                let atoms = &self.xcb_connection.atoms;
+                let wm_protocols = atoms.wm_protocols.unwrap_or(xcb::NONE);
+                let wm_delete_window = atoms.wm_delete_window.unwrap_or(xcb::NONE);
+                let manager = atoms.manager.unwrap_or(xcb::NONE);
+                let xsettings_selection = atoms.xsettings_selection.unwrap_or(xcb::NONE);
 
-                if wm_delete_window == data32[0] {
+                if event.type_() == wm_protocols && wm_delete_window == data32[0] {
                     self.handle_close_requested(handler);
+                } else if event.type_() == manager && xsettings_selection == data32[1] {
+                    // A new XSETTINGS manager has taken over
+                    self.xcb_connection.update_xsettings_owner();
+                    self.system_scale_changed = true;
+                } else if event.window() == self.window_id
+                    || Some(event.window()) == self.xdnd_proxy_for
+                {
+                    self.handle_xdnd_message(handler, event.window(), event.type_(), data32);
                 }
             }
 
+            xcb::PROPERTY_NOTIFY => {
+                let event = unsafe { xcb::cast_event::<xcb::PropertyNotifyEvent>(&event) };
+
+                let is_resource_manager = event.atom() == xcb::ATOM_RESOURCE_MANAGER
+                    && event.window() == self.xcb_connection.root_window();
+                let is_xsettings = event.window() == self.xcb_connection.xsettings_owner
+                    && Some(event.atom()) == self.xcb_connection.atoms.xsettings_settings;
+
+                if is_resource_manager || is_xsettings {
+                    self.system_scale_changed = true;
+                }
+
+                self.clipboard.handle_property_notify(&self.xcb_connection, event);
+            }
+
+            xcb::SELECTION_REQUEST => {
+                let event = unsafe { xcb::cast_event::<xcb::SelectionRequestEvent>(&event) };
+
+                self.clipboard.handle_selection_request(&self.xcb_connection, event);
+            }
+
+            xcb::SELECTION_CLEAR => {
+                let event = unsafe { xcb::cast_event::<xcb::SelectionClearEvent>(&event) };
+
+                self.clipboard.handle_selection_clear(event);
+            }
+
+            xcb::DESTROY_NOTIFY => {
+                let event = unsafe { xcb::cast_event::<xcb::DestroyNotifyEvent>(&event) };
+
+                let xsettings_owner = self.xcb_connection.xsettings_owner;
+                if xsettings_owner != xcb::NONE && event.window() == xsettings_owner {
+                    // The XSETTINGS manager went away, there may be another one already
+                    self.xcb_connection.update_xsettings_owner();
+                    self.system_scale_changed = true;
+                }
+            }
+
+            xcb::EXPOSE => {
+                let event = unsafe { xcb::cast_event::<xcb::ExposeEvent>(&event) };
+
+                if event.window() == self.window_id {
+                    self.damaged.push(PhyRect::new(
+                        event.x() as i32,
+                        event.y() as i32,
+                        event.width() as u32,
+                        event.height() as u32,
+                    ));
+                }
+            }
+
+            xcb::UNMAP_NOTIFY => {
+                self.reset_mouse_buttons();
+            }
+
             xcb::CONFIGURE_NOTIFY => {
                 let event = unsafe { xcb::cast_event::<xcb::ConfigureNotifyEvent>(&event) };
 
+                // We also receive these for the root window and the XSETTINGS owner
+                if event.window() != self.window_id {
+                    return;
+                }
+
                 let new_physical_size = PhySize::new(event.width() as u32, event.height() as u32);
 
                 if self.new_physical_size.is_some()
@@ -646,6 +2245,21 @@ impl Window {
                 {
                     self.new_physical_size = Some(new_physical_size);
                 }
+
+                // When the window manager has reparented a top-level window, the position in
+                // regular configure events is relative to the window manager's frame. The
+                // synthetic events sent by the window manager contain the position on the root
+                // window instead. Querying the position is a round trip, so that's done only once
+                // in `drain_xcb_events()`.
+                let is_synthetic = event.response_type() & 0x80 != 0;
+                if self.is_top_level && !is_synthetic {
+                    self.query_position = true;
+                } else {
+                    let new_position = PhyPoint::new(event.x() as i32, event.y() as i32);
+                    if self.new_position.is_some() || new_position != self.position {
+                        self.new_position = Some(new_position);
+                    }
+                }
             }
 
             ////
@@ -655,17 +2269,47 @@ impl Window {
                 let event = unsafe { xcb::cast_event::<xcb::MotionNotifyEvent>(&event) };
                 let detail = event.detail();
 
+                // The pointer is grabbed while dragging, so these events aren't meant for the
+                // handler
+                if self.is_dragging() {
+                    self.handle_drag_motion(event.root_x(), event.root_y(), event.time());
+                    return;
+                }
+
                 if detail != 4 && detail != 5 {
-                    let physical_pos =
-                        PhyPoint::new(event.event_x() as i32, event.event_y() as i32);
-                    let logical_pos = physical_pos.to_logical(&self.window_info);
+                    let modifiers = self.keyboard.modifiers(event.state());
+                    self.handle_cursor_moved(
+                        handler,
+                        event.event_x() as f64,
+                        event.event_y() as f64,
+                        modifiers,
+                    );
+                }
+            }
+
+            xcb::ENTER_NOTIFY | xcb::LEAVE_NOTIFY => {
+                let event = unsafe { xcb::cast_event::<xcb::EnterNotifyEvent>(&event) };
+
+                // Ignore the crossings caused by grabs, and by moving between the window and its
+                // children
+                if event.mode() != xcb::NOTIFY_MODE_NORMAL as u8
+                    || event.detail() == xcb::NOTIFY_DETAIL_INFERIOR as u8
+                {
+                    return;
+                }
 
+                if event_type == xcb::ENTER_NOTIFY {
+                    let modifiers = self.keyboard.modifiers(event.state());
+                    self.handle_cursor_entered(
+                        handler,
+                        event.event_x() as f64,
+                        event.event_y() as f64,
+                        modifiers,
+                    );
+                } else {
                     handler.on_event(
                         &mut crate::Window::new(self),
-                        Event::Mouse(MouseEvent::CursorMoved {
-                            position: logical_pos,
-                            modifiers: key_mods(event.state()),
-                        }),
+                        Event::Mouse(MouseEvent::CursorLeft),
                     );
                 }
             }
@@ -673,74 +2317,66 @@ impl Window {
             xcb::BUTTON_PRESS => {
                 let event = unsafe { xcb::cast_event::<xcb::ButtonPressEvent>(&event) };
                 let detail = event.detail();
+                let modifiers = self.keyboard.modifiers(event.state());
 
-                match detail {
-                    4 => {
-                        handler.on_event(
-                            &mut crate::Window::new(self),
-                            Event::Mouse(MouseEvent::WheelScrolled {
-                                delta: ScrollDelta::Lines { x: 0.0, y: 1.0 },
-                                modifiers: key_mods(event.state()),
-                            }),
-                        );
-                    }
-                    5 => {
-                        handler.on_event(
-                            &mut crate::Window::new(self),
-                            Event::Mouse(MouseEvent::WheelScrolled {
-                                delta: ScrollDelta::Lines { x: 0.0, y: -1.0 },
-                                modifiers: key_mods(event.state()),
-                            }),
-                        );
-                    }
-                    detail => {
-                        let button_id = mouse_id(detail);
-                        handler.on_event(
-                            &mut crate::Window::new(self),
-                            Event::Mouse(MouseEvent::ButtonPressed {
-                                button: button_id,
-                                modifiers: key_mods(event.state()),
-                            }),
-                        );
-                    }
-                }
+                self.handle_button_press(handler, detail, modifiers);
             }
 
             xcb::BUTTON_RELEASE => {
                 let event = unsafe { xcb::cast_event::<xcb::ButtonPressEvent>(&event) };
                 let detail = event.detail();
 
-                if detail != 4 && detail != 5 {
-                    let button_id = mouse_id(detail);
-                    handler.on_event(
-                        &mut crate::Window::new(self),
-                        Event::Mouse(MouseEvent::ButtonReleased {
-                            button: button_id,
-                            modifiers: key_mods(event.state()),
-                        }),
-                    );
+                if self.drag_source.is_some() {
+                    self.handle_drag_release(handler, event.time());
                 }
+
+                self.handle_button_release(handler, detail, event.state());
             }
 
             ////
             // keys
             ////
+            xcb::MAPPING_NOTIFY => {
+                self.keyboard.reload_keymap(&self.xcb_connection);
+            }
+
+            xcb::FOCUS_IN | xcb::FOCUS_OUT => {
+                let event = unsafe { xcb::cast_event::<xcb::FocusInEvent>(&event) };
+
+                // Focus moving to or from a child window doesn't change whether the keyboard
+                // input ends up in this window, and `NotifyPointer` events are only sent to the
+                // window under the pointer when the focus is on the root window
+                let detail = event.detail();
+                if detail == xcb::NOTIFY_DETAIL_INFERIOR as u8
+                    || detail == xcb::NOTIFY_DETAIL_POINTER as u8
+                {
+                    return;
+                }
+
+                self.handle_focus_change(handler, event_type == xcb::FOCUS_IN);
+            }
+
             xcb::KEY_PRESS => {
                 let event = unsafe { xcb::cast_event::<xcb::KeyPressEvent>(&event) };
+                if self.filter_ime_key_event(handler, event, true) {
+                    return;
+                }
 
-                handler.on_event(
-                    &mut crate::Window::new(self),
-                    Event::Keyboard(convert_key_press_event(event)),
-                );
+                let event = self.keyboard.convert_key_press_event(event);
+                handler.on_event(&mut crate::Window::new(self), Event::Keyboard(event));
             }
 
             xcb::KEY_RELEASE => {
                 let event = unsafe { xcb::cast_event::<xcb::KeyReleaseEvent>(&event) };
+                if self.is_auto_repeat_release(event) {
+                    return;
+                }
+                if self.filter_ime_key_event(handler, event, false) {
+                    return;
+                }
 
-                handler.on_event(
-                    &mut crate::Window::new(self),
-                    Event::Keyboard(convert_key_release_event(event)),
-                );
+                let event = self.keyboard.convert_key_release_event(event);
+                handler.on_event(&mut crate::Window::new(self), Event::Keyboard(event));
             }
 
             _ => {}
@@ -748,6 +2384,71 @@ impl Window {
     }
 }
 
+/// The size constraints from `WindowOpenOptions`, in logical sizes.
+#[derive(Debug, Clone, Copy)]
+struct SizeConstraints {
+    min_size: Option<Size>,
+    max_size: Option<Size>,
+    resizable: bool,
+    aspect_ratio: Option<f64>,
+}
+
+impl SizeConstraints {
+    /// Constrain a physical size to the aspect ratio, and the minimum and maximum sizes. When
+    /// these conflict, the minimum and maximum sizes take precedence.
+    fn constrain(&self, size: PhySize, scale: f64) -> PhySize {
+        let mut width = size.width as f64;
+        let mut height = size.height as f64;
+
+        if let Some(aspect_ratio) = self.aspect_ratio.filter(|ratio| *ratio > 0.0) {
+            height = width / aspect_ratio;
+        }
+        if let Some(min_size) = self.min_size {
+            width = width.max(min_size.width * scale);
+            height = height.max(min_size.height * scale);
+        }
+        if let Some(max_size) = self.max_size {
+            width = width.min(max_size.width * scale);
+            height = height.min(max_size.height * scale);
+        }
+
+        PhySize::new(width.round().max(1.0) as u32, height.round().max(1.0) as u32)
+    }
+
+    /// The ICCCM size hints for a window with these constraints. A non-resizable window gets
+    /// pinned to its current size.
+    fn size_hints(&self, window_info: &WindowInfo) -> xcb_util::icccm::SizeHintsBuilder {
+        let scale = window_info.scale();
+        let to_physical = |size: Size| {
+            ((size.width * scale).round() as i32, (size.height * scale).round() as i32)
+        };
+
+        // With static gravity the window's position refers to our own window instead of to the
+        // window manager's frame, which matches the positions we report in `WindowEvent::Moved`
+        let mut size_hints = xcb_util::icccm::SizeHints::empty().gravity(xcb::GRAVITY_STATIC);
+        if self.resizable {
+            if let Some((width, height)) = self.min_size.map(to_physical) {
+                size_hints = size_hints.min_size(width, height);
+            }
+            if let Some((width, height)) = self.max_size.map(to_physical) {
+                size_hints = size_hints.max_size(width, height);
+            }
+            if let Some(aspect_ratio) = self.aspect_ratio.filter(|ratio| *ratio > 0.0) {
+                // The aspect ratio is stored as a fraction
+                let aspect = ((aspect_ratio * 1000.0).round() as i32, 1000);
+                size_hints = size_hints.aspect(aspect, aspect);
+            }
+        } else {
+            let size = window_info.physical_size();
+            size_hints = size_hints
+                .min_size(size.width as i32, size.height as i32)
+                .max_size(size.width as i32, size.height as i32);
+        }
+
+        size_hints
+    }
+}
+
 unsafe impl HasRawWindowHandle for Window {
     fn raw_window_handle(&self) -> RawWindowHandle {
         let mut handle = XlibHandle::empty();
@@ -763,8 +2464,8 @@ fn mouse_id(id: u8) -> MouseButton {
         1 => MouseButton::Left,
         2 => MouseButton::Middle,
         3 => MouseButton::Right,
-        6 => MouseButton::Back,
-        7 => MouseButton::Forward,
+        8 => MouseButton::Back,
+        9 => MouseButton::Forward,
         id => MouseButton::Other(id),
     }
 }
@@ -3,14 +3,27 @@ use std::collections::HashMap;
 ///
 /// Keeps track of the xcb connection itself and the xlib display ID that was used to connect.
 use std::ffi::{CStr, CString};
+use std::sync::Weak;
 
-use crate::MouseCursor;
+use crate::{CursorImage, MouseCursor};
 
 use super::cursor;
+use super::xsettings::{parse_xsettings, XSetting};
+
+/// The size system cursors are loaded at when the user didn't configure a size, before scaling
+const DEFAULT_CURSOR_SIZE: i32 = 24;
+
+/// A cursor created for `MouseCursor::Custom`, and a weak reference to the images it was created
+/// from
+type CustomCursorEntry = (Weak<[CursorImage]>, u32);
 
 pub(crate) struct Atoms {
     pub wm_protocols: Option<u32>,
     pub wm_delete_window: Option<u32>,
+    pub manager: Option<u32>,
+    pub xsettings_settings: Option<u32>,
+    /// The `_XSETTINGS_S<screen>` selection for our screen
+    pub xsettings_selection: Option<u32>,
 }
 
 pub struct XcbConnection {
@@ -19,8 +32,21 @@ pub struct XcbConnection {
 
     pub(crate) atoms: Atoms,
 
+    /// The window owning the XSETTINGS selection, or `xcb::NONE` if there's no XSETTINGS manager
+    /// running. Updated through `update_xsettings_owner()`.
+    pub(crate) xsettings_owner: u32,
+
     // FIXME: Same here, there's a ton of unused cursor machinery in here
     pub(super) cursor_cache: HashMap<MouseCursor, u32>,
+    /// The cursor theme the system cursors are loaded from, or `None` for the default theme. Set
+    /// through `update_cursor_settings()`.
+    cursor_theme: Option<CString>,
+    /// The size in physical pixels the system cursors are loaded at
+    cursor_size: i32,
+    /// The cursors created for `MouseCursor::Custom`, by the address of the cursor's images and
+    /// the index of the image they were created from. The weak references keep the addresses from
+    /// being reused for other cursors.
+    pub(super) custom_cursor_cache: HashMap<(usize, usize), CustomCursorEntry>,
 }
 
 macro_rules! intern_atoms {
@@ -42,71 +68,191 @@ macro_rules! intern_atoms {
 
 impl XcbConnection {
     pub fn new() -> Result<Self, xcb::base::ConnError> {
-        let (conn, xlib_display) = xcb::Connection::connect_with_xlib_display()?;
+        // `xcb::Connection::connect_with_xlib_display()` asserts that Xlib managed to open the
+        // display, so we need to open the display ourselves to be able to report the error
+        let (conn, xlib_display) = unsafe {
+            let dpy = x11::xlib::XOpenDisplay(std::ptr::null());
+            if dpy.is_null() {
+                return Err(xcb::base::ConnError::Connection);
+            }
+
+            let conn = xcb::Connection::new_from_xlib_display(dpy);
+            conn.has_error()?;
+
+            (conn, x11::xlib::XDefaultScreen(dpy))
+        };
 
         conn.set_event_queue_owner(xcb::base::EventQueueOwner::Xcb);
 
         let (wm_protocols, wm_delete_window) = intern_atoms!(&conn, WM_PROTOCOLS, WM_DELETE_WINDOW);
 
-        Ok(Self {
+        // These are created if they don't exist yet, since an XSETTINGS manager may still be
+        // started after the window has been opened
+        let manager = xcb::intern_atom(&conn, false, "MANAGER");
+        let xsettings_settings = xcb::intern_atom(&conn, false, "_XSETTINGS_SETTINGS");
+        let xsettings_selection =
+            xcb::intern_atom(&conn, false, &format!("_XSETTINGS_S{}", xlib_display));
+        let atoms = Atoms {
+            wm_protocols,
+            wm_delete_window,
+            manager: manager.get_reply().map(|r| r.atom()).ok(),
+            xsettings_settings: xsettings_settings.get_reply().map(|r| r.atom()).ok(),
+            xsettings_selection: xsettings_selection.get_reply().map(|r| r.atom()).ok(),
+        };
+
+        let mut xcb_connection = Self {
             conn,
             xlib_display,
 
-            atoms: Atoms { wm_protocols, wm_delete_window },
+            atoms,
+            xsettings_owner: xcb::NONE,
 
             cursor_cache: HashMap::new(),
-        })
+            custom_cursor_cache: HashMap::new(),
+            cursor_theme: None,
+            cursor_size: DEFAULT_CURSOR_SIZE,
+        };
+        xcb_connection.update_xsettings_owner();
+
+        Ok(xcb_connection)
     }
 
-    // Try to get the scaling with this function first.
-    // If this gives you `None`, fall back to `get_scaling_screen_dimensions`.
-    // If neither work, I guess just assume 96.0 and don't do any scaling.
-    fn get_scaling_xft(&self) -> Option<f64> {
-        use x11::xlib::{
-            XResourceManagerString, XrmDestroyDatabase, XrmGetResource, XrmGetStringDatabase,
-            XrmValue,
+    /// Get the atom with the given name, creating it if it doesn't exist yet. Returns
+    /// `xcb::NONE` if the request failed.
+    pub fn intern_atom(&self, name: &str) -> u32 {
+        xcb::intern_atom(&self.conn, false, name).get_reply().map(|r| r.atom()).unwrap_or(xcb::NONE)
+    }
+
+    pub fn root_window(&self) -> u32 {
+        let setup = self.conn.get_setup();
+        let screen = setup.roots().nth(self.xlib_display as usize).unwrap();
+
+        screen.root()
+    }
+
+    /// Look up the current owner of the XSETTINGS selection and listen for changes to its settings
+    /// and for its destruction. This should be called again whenever the XSETTINGS manager changes.
+    pub fn update_xsettings_owner(&mut self) {
+        self.xsettings_owner = xcb::NONE;
+
+        let xsettings_selection = match self.atoms.xsettings_selection {
+            Some(atom) => atom,
+            None => return,
         };
 
-        let display = self.conn.get_raw_dpy();
+        // The server is grabbed so the owner cannot be destroyed before we have selected events on
+        // it, as recommended by the XSETTINGS specification
+        xcb::grab_server(&self.conn);
+        let owner = xcb::get_selection_owner(&self.conn, xsettings_selection)
+            .get_reply()
+            .map(|r| r.owner())
+            .unwrap_or(xcb::NONE);
+        if owner != xcb::NONE {
+            xcb::change_window_attributes(
+                &self.conn,
+                owner,
+                &[(
+                    xcb::CW_EVENT_MASK,
+                    xcb::EVENT_MASK_PROPERTY_CHANGE | xcb::EVENT_MASK_STRUCTURE_NOTIFY,
+                )],
+            );
+        }
+        xcb::ungrab_server(&self.conn);
+        self.conn.flush();
+
+        self.xsettings_owner = owner;
+    }
+
+    /// Look up a setting from the XSETTINGS manager.
+    fn get_xsetting(&self, name: &str) -> Option<XSetting> {
+        if self.xsettings_owner == xcb::NONE {
+            return None;
+        }
+
+        let data = self.get_property(
+            self.xsettings_owner,
+            self.atoms.xsettings_settings?,
+            self.atoms.xsettings_settings?,
+        )?;
+        parse_xsettings(&data)?.remove(name)
+    }
+
+    /// Look up an X resource in the `RESOURCE_MANAGER` property on the root window.
+    fn get_resource(&self, name: &str, class: &str) -> Option<String> {
+        use x11::xlib::{XrmDestroyDatabase, XrmGetResource, XrmGetStringDatabase, XrmValue};
+
+        // `XResourceManagerString()` only returns the value from when the display was opened, so
+        // we need to read the property ourselves to pick up changes
+        let resource_manager =
+            self.get_property(self.root_window(), xcb::ATOM_RESOURCE_MANAGER, xcb::ATOM_STRING)?;
+        let rms = CString::new(resource_manager.split(|&b| b == 0).next()?).ok()?;
+
         unsafe {
-            let rms = XResourceManagerString(display);
-            if !rms.is_null() {
-                let db = XrmGetStringDatabase(rms);
-                if !db.is_null() {
-                    let mut value = XrmValue { size: 0, addr: std::ptr::null_mut() };
-
-                    let mut value_type: *mut std::os::raw::c_char = std::ptr::null_mut();
-                    let name_c_str = CString::new("Xft.dpi").unwrap();
-                    let c_str = CString::new("Xft.Dpi").unwrap();
-
-                    let dpi = if XrmGetResource(
-                        db,
-                        name_c_str.as_ptr(),
-                        c_str.as_ptr(),
-                        &mut value_type,
-                        &mut value,
-                    ) != 0
-                        && !value.addr.is_null()
-                    {
-                        let value_addr: &CStr = CStr::from_ptr(value.addr);
-                        value_addr.to_str().ok();
-                        let value_str = value_addr.to_str().ok()?;
-                        let value_f64: f64 = value_str.parse().ok()?;
-                        let dpi_to_scale = value_f64 / 96.0;
-                        Some(dpi_to_scale)
-                    } else {
-                        None
-                    };
-                    XrmDestroyDatabase(db);
-
-                    return dpi;
-                }
+            let db = XrmGetStringDatabase(rms.as_ptr());
+            if db.is_null() {
+                return None;
             }
+
+            let mut value = XrmValue { size: 0, addr: std::ptr::null_mut() };
+
+            let mut value_type: *mut std::os::raw::c_char = std::ptr::null_mut();
+            let name_c_str = CString::new(name).unwrap();
+            let c_str = CString::new(class).unwrap();
+
+            let resource = if XrmGetResource(
+                db,
+                name_c_str.as_ptr(),
+                c_str.as_ptr(),
+                &mut value_type,
+                &mut value,
+            ) != 0
+                && !value.addr.is_null()
+            {
+                let value_addr: &CStr = CStr::from_ptr(value.addr);
+                value_addr.to_str().ok().map(|value_str| value_str.trim().to_owned())
+            } else {
+                None
+            };
+            XrmDestroyDatabase(db);
+
+            resource
+        }
+    }
+
+    /// Read a property on `window` in its entirety. Returns `None` if the property doesn't exist or
+    /// if the window has been destroyed in the meantime.
+    fn get_property(&self, window: u32, property: u32, type_: u32) -> Option<Vec<u8>> {
+        let reply = xcb::get_property(&self.conn, false, window, property, type_, 0, u32::MAX / 4)
+            .get_reply()
+            .ok()?;
+        if reply.type_() == xcb::NONE || reply.format() != 8 {
+            return None;
         }
-        None
+
+        Some(reply.value::<u8>().to_vec())
+    }
+
+    // Try to get the scaling with this function first. This uses the `Xft/DPI` setting from the
+    // XSETTINGS manager, which is what GNOME and most other desktop environments use.
+    fn get_scaling_xsettings(&self) -> Option<f64> {
+        match self.get_xsetting("Xft/DPI")? {
+            // The value is stored in 1024ths of a dot per inch, and -1 means the default should
+            // be used
+            XSetting::Integer(dpi) if dpi > 0 => Some(dpi as f64 / 1024.0 / 96.0),
+            _ => None,
+        }
+    }
+
+    // If `get_scaling_xsettings` gives you `None`, try this function next.
+    // If this gives you `None`, fall back to `get_scaling_screen_dimensions`.
+    // If neither work, I guess just assume 96.0 and don't do any scaling.
+    fn get_scaling_xft(&self) -> Option<f64> {
+        let dpi = self.get_resource("Xft.dpi", "Xft.Dpi")?.parse::<f64>().ok()?;
+
+        Some(dpi / 96.0)
     }
 
-    // Try to get the scaling with `get_scaling_xft` first.
+    // Try to get the scaling with `get_scaling_xsettings` and `get_scaling_xft` first.
     // Only use this function as a fallback.
     // If neither work, I guess just assume 96.0 and don't do any scaling.
     fn get_scaling_screen_dimensions(&self) -> Option<f64> {
@@ -124,6 +270,10 @@ impl XcbConnection {
         let width_mm = screen.width_in_millimeters() as f64;
         let height_px = screen.height_in_pixels() as f64;
         let height_mm = screen.height_in_millimeters() as f64;
+        if width_mm <= 0.0 || height_mm <= 0.0 {
+            // Some (virtual) X servers don't report the physical screen size
+            return None;
+        }
         let _xres = width_px * 25.4 / width_mm;
         let yres = height_px * 25.4 / height_mm;
 
@@ -135,13 +285,141 @@ impl XcbConnection {
 
     #[inline]
     pub fn get_scaling(&self) -> Option<f64> {
-        self.get_scaling_xft().or_else(|| self.get_scaling_screen_dimensions())
+        self.get_scaling_xsettings()
+            .or_else(|| self.get_scaling_xft())
+            .or_else(|| self.get_scaling_screen_dimensions())
     }
 
-    #[inline]
-    pub fn get_cursor_xid(&mut self, cursor: MouseCursor) -> u32 {
+    /// Get the refresh rate in Hz of the monitor containing the given point on the root window,
+    /// or of the first active monitor if no monitor contains that point.
+    pub fn get_monitor_refresh_rate(&self, x: i32, y: i32) -> Option<f64> {
+        use xcb::randr;
+
+        let resources =
+            randr::get_screen_resources_current(&self.conn, self.root_window()).get_reply().ok()?;
+
+        // Send all requests before waiting for the replies
+        let config_timestamp = resources.config_timestamp();
+        let crtc_cookies: Vec<_> = resources
+            .crtcs()
+            .iter()
+            .map(|&crtc| randr::get_crtc_info(&self.conn, crtc, config_timestamp))
+            .collect();
+
+        let mut mode = None;
+        for crtc in crtc_cookies.into_iter().filter_map(|cookie| cookie.get_reply().ok()) {
+            // Disabled CRTCs don't have a mode
+            if crtc.mode() == xcb::NONE {
+                continue;
+            }
+
+            let (crtc_x, crtc_y) = (crtc.x() as i32, crtc.y() as i32);
+            if x >= crtc_x
+                && x < crtc_x + crtc.width() as i32
+                && y >= crtc_y
+                && y < crtc_y + crtc.height() as i32
+            {
+                mode = Some(crtc.mode());
+                break;
+            }
+
+            mode.get_or_insert(crtc.mode());
+        }
+
+        let mode = mode?;
+        let mode_info = resources.modes().find(|mode_info| mode_info.id() == mode)?;
+
+        let mut vtotal = mode_info.vtotal() as f64;
+        if mode_info.mode_flags() & randr::MODE_FLAG_DOUBLE_SCAN != 0 {
+            vtotal *= 2.0;
+        }
+        if mode_info.mode_flags() & randr::MODE_FLAG_INTERLACE != 0 {
+            vtotal /= 2.0;
+        }
+
+        let htotal = mode_info.htotal() as f64;
+        if htotal == 0.0 || vtotal == 0.0 {
+            return None;
+        }
+
+        Some(mode_info.dot_clock() as f64 / (htotal * vtotal))
+    }
+
+    /// Read the cursor theme and size from the XSETTINGS manager or the X resources, falling back
+    /// to the `XCURSOR_THEME` and `XCURSOR_SIZE` environment variables. The size is multiplied by
+    /// the window's scale factor. If either changed, the cached system cursors are freed and
+    /// `true` is returned, in which case the window's cursor needs to be set again.
+    pub fn update_cursor_settings(&mut self, scale: f64) -> bool {
+        let theme = match self.get_xsetting("Gtk/CursorThemeName") {
+            Some(XSetting::String(theme)) if !theme.is_empty() => Some(theme),
+            _ => self.get_resource("Xcursor.theme", "Xcursor.Theme"),
+        }
+        .or_else(|| std::env::var("XCURSOR_THEME").ok())
+        .filter(|theme| !theme.is_empty())
+        .and_then(|theme| CString::new(theme).ok());
+
+        let size = match self.get_xsetting("Gtk/CursorThemeSize") {
+            Some(XSetting::Integer(size)) if size > 0 => Some(size),
+            _ => {
+                self.get_resource("Xcursor.size", "Xcursor.Size").and_then(|size| size.parse().ok())
+            }
+        }
+        .or_else(|| std::env::var("XCURSOR_SIZE").ok().and_then(|size| size.parse().ok()))
+        .filter(|&size| size > 0)
+        .unwrap_or(DEFAULT_CURSOR_SIZE);
+        let size = (size as f64 * scale).round() as i32;
+
+        if theme == self.cursor_theme && size == self.cursor_size {
+            return false;
+        }
+
+        self.cursor_theme = theme;
+        self.cursor_size = size;
+        for (_, xid) in self.cursor_cache.drain() {
+            if xid != 0 {
+                xcb::free_cursor(&self.conn, xid);
+            }
+        }
+
+        true
+    }
+
+    /// Get the X11 cursor for a mouse cursor, loading or creating it if it's not cached yet. The
+    /// scale factor is used to pick the image for custom cursors.
+    pub fn get_cursor_xid(&mut self, cursor: &MouseCursor, scale: f64) -> u32 {
         let dpy = self.conn.get_raw_dpy();
 
-        *self.cursor_cache.entry(cursor).or_insert_with(|| cursor::get_xcursor(dpy, cursor))
+        if let MouseCursor::Custom(custom_cursor) = cursor {
+            let images = custom_cursor.downgrade();
+            let index = cursor::custom_cursor_image(custom_cursor, scale);
+            let key = (images.as_ptr() as *const CursorImage as usize, index);
+            if let Some(&(_, xid)) = self.custom_cursor_cache.get(&key) {
+                return xid;
+            }
+
+            // Cursors whose images have been dropped can't be used anymore. The server keeps
+            // cursors that are still set on a window until they're replaced.
+            let conn = &self.conn;
+            self.custom_cursor_cache.retain(|_, (images, xid)| {
+                let alive = images.strong_count() > 0;
+                if !alive && *xid != 0 {
+                    xcb::free_cursor(conn, *xid);
+                }
+
+                alive
+            });
+
+            let xid = cursor::create_custom_cursor(dpy, &custom_cursor.images()[index]);
+            self.custom_cursor_cache.insert(key, (images, xid));
+            return xid;
+        }
+
+        if let Some(&xid) = self.cursor_cache.get(cursor) {
+            return xid;
+        }
+
+        let xid = cursor::get_xcursor(dpy, cursor, self.cursor_theme.as_deref(), self.cursor_size);
+        self.cursor_cache.insert(cursor.clone(), xid);
+        xid
     }
 }
@@ -0,0 +1,428 @@
+//! Pointer input through XInput2. This is used for smooth and high resolution scrolling, which the
+//! core protocol can only report as clicks on buttons 4 to 7, and for the relative pointer
+//! movement while the pointer is locked.
+//!
+//! XInput2 events are read through XCB like all other events, but XCB's XInput bindings are
+//! unusable, so the events are parsed here by hand. Requests go through Xlib.
+
+use std::collections::HashMap;
+use std::os::raw::{c_int, c_uchar};
+
+use x11::xinput;
+use x11::xinput2 as xi;
+use x11::xlib;
+
+use super::XcbConnection;
+use crate::ScrollDelta;
+
+/// XInput 2.1 added smooth scrolling
+const XI_VERSION: (c_int, c_int) = (2, 1);
+
+/// How many pixels one scroll step corresponds to for devices that scroll in gestures. This is
+/// libinput's default scroll distance, which its X11 driver also uses as the valuator increment.
+const PIXELS_PER_SCROLL_STEP: f64 = 15.0;
+
+/// The offset of the data that follows the first 32 bytes of a generic event. XCB inserts the
+/// event's full sequence number after those 32 bytes.
+const GE_DATA_OFFSET: usize = 36;
+/// The offset of the button mask in an `XIDeviceEvent`, which is followed by the valuator mask
+const DEVICE_EVENT_MASKS_OFFSET: usize = GE_DATA_OFFSET + 48;
+
+pub(super) struct XInput2 {
+    /// The XInput extension's major opcode. XInput2 events are generic events with this extension
+    /// code.
+    opcode: u8,
+    /// The devices we've received scroll events from, by device ID. These are queried when a
+    /// device is first seen, and again after the pointer re-enters the window.
+    devices: HashMap<u16, ScrollDevice>,
+}
+
+struct ScrollDevice {
+    valuators: Vec<ScrollValuator>,
+    /// Whether the device scrolls in gestures, like a touchpad. Mouse wheels have a fixed
+    /// increment, and the server emulates button presses for every step.
+    gestures: bool,
+}
+
+struct ScrollValuator {
+    number: u16,
+    horizontal: bool,
+    /// How much the valuator changes for one scroll step
+    increment: f64,
+    /// The valuator's last value. Scroll valuators are absolute, so the deltas are computed from
+    /// this.
+    value: f64,
+}
+
+/// The parts of an XInput2 pointer event we use. Positions are in physical pixels.
+pub(super) struct PointerEvent {
+    pub event_x: f64,
+    pub event_y: f64,
+    pub root_x: f64,
+    pub root_y: f64,
+    /// The effective core modifier state. For motion and button events this also contains the
+    /// state of buttons 1 to 5 like in core events.
+    pub state: u16,
+    pub time: u32,
+}
+
+pub(super) enum XInput2Event {
+    Motion {
+        pointer: PointerEvent,
+        /// Whether the pointer actually moved. Scrolling produces motion events without any
+        /// movement.
+        moved: bool,
+        /// The scrolled amount, with positive values meaning left and up. This is in fractional
+        /// lines for mouse wheels and in pixels for devices that scroll in gestures.
+        scroll: Option<ScrollDelta>,
+        /// Whether the scrolling device scrolls in gestures, see `ScrollDevice::gestures`
+        scroll_gestures: bool,
+    },
+    ButtonPress {
+        pointer: PointerEvent,
+        button: u8,
+        /// Set for the button 4 to 7 presses the server emulates for smooth scrolling, which are
+        /// already covered by the motion events
+        emulated: bool,
+    },
+    ButtonRelease {
+        pointer: PointerEvent,
+        button: u8,
+        emulated: bool,
+    },
+    /// The pointer entered the window. Crossings caused by grabs and by moving between the window
+    /// and its children are not reported.
+    Enter {
+        pointer: PointerEvent,
+    },
+    /// The pointer left the window
+    Leave,
+    /// Relative pointer movement in physical pixels, only sent after `select_raw_motion()`
+    RawMotion {
+        dx: f64,
+        dy: f64,
+    },
+}
+
+impl XInput2 {
+    /// Select XInput2 pointer events on the window. Returns `None` if the X server doesn't
+    /// support XInput 2.1, in which case the core pointer events are used instead.
+    pub fn new(xcb_connection: &XcbConnection, window_id: u32) -> Option<Self> {
+        let opcode = xcb::query_extension(&xcb_connection.conn, "XInputExtension")
+            .get_reply()
+            .ok()
+            .filter(|reply| reply.present())?
+            .major_opcode();
+
+        let display = xcb_connection.conn.get_raw_dpy();
+        unsafe {
+            let (mut major, mut minor) = XI_VERSION;
+            if xi::XIQueryVersion(display, &mut major, &mut minor) != xlib::Success as c_int
+                || (major, minor) < XI_VERSION
+            {
+                return None;
+            }
+
+            // Selecting these stops the server from sending the core versions of these events
+            let mut mask = [0 as c_uchar; 4];
+            for event in [
+                xi::XI_Motion,
+                xi::XI_ButtonPress,
+                xi::XI_ButtonRelease,
+                xi::XI_Enter,
+                xi::XI_Leave,
+            ] {
+                xi::XISetMask(&mut mask, event);
+            }
+            let mut event_mask = xi::XIEventMask {
+                deviceid: xi::XIAllMasterDevices,
+                mask_len: mask.len() as c_int,
+                mask: mask.as_mut_ptr(),
+            };
+            xi::XISelectEvents(display, window_id as xlib::Window, &mut event_mask, 1);
+            xlib::XFlush(display);
+        }
+
+        Some(Self { opcode, devices: HashMap::new() })
+    }
+
+    /// Start or stop receiving `XInput2Event::RawMotion` events. Raw events are only sent to the
+    /// root window, and they're sent no matter where the pointer is.
+    pub fn select_raw_motion(&self, xcb_connection: &XcbConnection, enabled: bool) {
+        let display = xcb_connection.conn.get_raw_dpy();
+        unsafe {
+            let mut mask = [0 as c_uchar; 4];
+            if enabled {
+                xi::XISetMask(&mut mask, xi::XI_RawMotion);
+            }
+            let mut event_mask = xi::XIEventMask {
+                deviceid: xi::XIAllMasterDevices,
+                mask_len: mask.len() as c_int,
+                mask: mask.as_mut_ptr(),
+            };
+            xi::XISelectEvents(
+                display,
+                xcb_connection.root_window() as xlib::Window,
+                &mut event_mask,
+                1,
+            );
+            xlib::XFlush(display);
+        }
+    }
+
+    /// Parse the event if it's an XInput2 event. Returns `None` for all other events.
+    pub fn handle_event(
+        &mut self, xcb_connection: &XcbConnection, event: &xcb::GenericEvent,
+    ) -> Option<XInput2Event> {
+        if event.response_type() & !0x80 != xcb::GE_GENERIC {
+            return None;
+        }
+
+        let data = unsafe {
+            let header = std::slice::from_raw_parts(event.ptr as *const u8, GE_DATA_OFFSET);
+            if header[1] != self.opcode {
+                return None;
+            }
+
+            let length = read_u32(header, 4) as usize * 4;
+            std::slice::from_raw_parts(event.ptr as *const u8, GE_DATA_OFFSET + length)
+        };
+
+        let event_type = read_u16(data, 8) as c_int;
+        match event_type {
+            xi::XI_Motion | xi::XI_ButtonPress | xi::XI_ButtonRelease => {
+                // XInput2 reports the held buttons separately. Buttons 1 to 5 are added to the
+                // state like in core events.
+                let buttons_len = read_u16(data, 52) as usize * 4;
+                let buttons = data
+                    .get(DEVICE_EVENT_MASKS_OFFSET..DEVICE_EVENT_MASKS_OFFSET + buttons_len)
+                    .unwrap_or_default();
+                let button_state = (1..=5)
+                    .filter(|&button| buttons.first().is_some_and(|mask| mask & (1 << button) != 0))
+                    .fold(0, |state, button| state | (xcb::BUTTON_MASK_1 << (button - 1)));
+
+                let pointer = PointerEvent {
+                    root_x: read_fp1616(data, 36),
+                    root_y: read_fp1616(data, 40),
+                    event_x: read_fp1616(data, 44),
+                    event_y: read_fp1616(data, 48),
+                    state: read_u32(data, 76) as u16 | button_state as u16,
+                    time: read_u32(data, 12),
+                };
+                let detail = read_u32(data, 16);
+                let emulated = read_u32(data, 60) & xi::XIPointerEmulated as u32 != 0;
+
+                match event_type {
+                    xi::XI_Motion => {
+                        let source_id = read_u16(data, 56);
+                        let valuators_len = read_u16(data, 54) as usize * 4;
+                        let mask_offset =
+                            DEVICE_EVENT_MASKS_OFFSET + read_u16(data, 52) as usize * 4;
+                        let valuators = read_valuators(data, mask_offset, valuators_len);
+                        let moved = valuators.is_empty()
+                            || valuators.iter().any(|&(number, _)| number == 0 || number == 1);
+                        let (scroll, scroll_gestures) =
+                            self.scroll_delta(xcb_connection, source_id, &valuators);
+
+                        Some(XInput2Event::Motion { pointer, moved, scroll, scroll_gestures })
+                    }
+                    xi::XI_ButtonPress => {
+                        Some(XInput2Event::ButtonPress { pointer, button: detail as u8, emulated })
+                    }
+                    _ => Some(XInput2Event::ButtonRelease {
+                        pointer,
+                        button: detail as u8,
+                        emulated,
+                    }),
+                }
+            }
+            xi::XI_RawMotion => {
+                // The first two valuators are the pointer's X and Y axes. The accelerated values
+                // are used so the movement matches what the cursor would have done.
+                let valuators_len = read_u16(data, 22) as usize * 4;
+                let valuators = read_valuators(data, GE_DATA_OFFSET, valuators_len);
+                let axis = |axis| {
+                    valuators
+                        .iter()
+                        .find(|&&(number, _)| number == axis)
+                        .map_or(0.0, |&(_, value)| value)
+                };
+
+                Some(XInput2Event::RawMotion { dx: axis(0), dy: axis(1) })
+            }
+            xi::XI_Enter | xi::XI_Leave => {
+                let pointer = PointerEvent {
+                    root_x: read_fp1616(data, 36),
+                    root_y: read_fp1616(data, 40),
+                    event_x: read_fp1616(data, 44),
+                    event_y: read_fp1616(data, 48),
+                    state: read_u32(data, 68) as u16,
+                    time: read_u32(data, 12),
+                };
+                let mode = data[18] as c_int;
+                let detail = data[19] as c_int;
+
+                if event_type == xi::XI_Enter {
+                    // The valuators may have changed while the pointer was outside of the window
+                    self.devices.clear();
+                }
+
+                if mode != xi::XINotifyNormal || detail == xi::XINotifyInferior {
+                    None
+                } else if event_type == xi::XI_Enter {
+                    Some(XInput2Event::Enter { pointer })
+                } else {
+                    Some(XInput2Event::Leave)
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Compute the scrolled amount from the new valuator values, and update the stored values.
+    /// Also returns whether the device scrolls in gestures.
+    fn scroll_delta(
+        &mut self, xcb_connection: &XcbConnection, source_id: u16, valuators: &[(u16, f64)],
+    ) -> (Option<ScrollDelta>, bool) {
+        let device = self
+            .devices
+            .entry(source_id)
+            .or_insert_with(|| query_scroll_device(xcb_connection, source_id));
+
+        let mut delta = None;
+        for &(number, value) in valuators {
+            let valuator = match device.valuators.iter_mut().find(|v| v.number == number) {
+                Some(valuator) => valuator,
+                None => continue,
+            };
+
+            // Scrolling down or right increases the valuator's value
+            let steps = (valuator.value - value) / valuator.increment;
+            valuator.value = value;
+
+            let (x, y) = delta.get_or_insert((0.0, 0.0));
+            if valuator.horizontal {
+                *x += steps;
+            } else {
+                *y += steps;
+            }
+        }
+
+        let delta = delta.map(|(x, y)| {
+            if device.gestures {
+                ScrollDelta::Pixels {
+                    x: (x * PIXELS_PER_SCROLL_STEP) as f32,
+                    y: (y * PIXELS_PER_SCROLL_STEP) as f32,
+                }
+            } else {
+                ScrollDelta::Lines { x: x as f32, y: y as f32 }
+            }
+        });
+
+        (delta, device.gestures)
+    }
+}
+
+fn query_scroll_device(xcb_connection: &XcbConnection, device_id: u16) -> ScrollDevice {
+    let display = xcb_connection.conn.get_raw_dpy();
+
+    let mut device = ScrollDevice { valuators: Vec::new(), gestures: false };
+    unsafe {
+        // XInput 2 doesn't report the type of a device, but XInput 1 does
+        let touchpad = xcb_connection.intern_atom("TOUCHPAD");
+        let mut device_count = 0;
+        let devices = xinput::XListInputDevices(display, &mut device_count);
+        if !devices.is_null() {
+            let is_touchpad = |info: &xinput::XDeviceInfo| {
+                info.id == device_id as xlib::XID && info.type_ == touchpad as xlib::Atom
+            };
+            device.gestures =
+                std::slice::from_raw_parts(devices, device_count as usize).iter().any(is_touchpad);
+            xinput::XFreeDeviceList(devices);
+        }
+
+        let mut device_count = 0;
+        let info = xi::XIQueryDevice(display, device_id as c_int, &mut device_count);
+        if info.is_null() {
+            return device;
+        }
+
+        if device_count > 0 {
+            let classes = std::slice::from_raw_parts((*info).classes, (*info).num_classes as usize);
+
+            // The scroll classes describe the valuators, and the valuator classes hold their
+            // current values
+            for &class in classes {
+                if (*class)._type != xi::XIScrollClass {
+                    continue;
+                }
+
+                let scroll_class = &*(class as *const xi::XIScrollClassInfo);
+                let value = classes
+                    .iter()
+                    .filter(|&&class| (*class)._type == xi::XIValuatorClass)
+                    .map(|&class| &*(class as *const xi::XIValuatorClassInfo))
+                    .find(|valuator_class| valuator_class.number == scroll_class.number)
+                    .map_or(0.0, |valuator_class| valuator_class.value);
+
+                // The server doesn't emulate button presses for devices without discrete steps
+                if scroll_class.flags & xi::XIScrollFlagNoEmulation != 0 {
+                    device.gestures = true;
+                }
+                if scroll_class.increment != 0.0 {
+                    device.valuators.push(ScrollValuator {
+                        number: scroll_class.number as u16,
+                        horizontal: scroll_class.scroll_type == xi::XIScrollTypeHorizontal,
+                        increment: scroll_class.increment,
+                        value,
+                    });
+                }
+            }
+        }
+
+        xi::XIFreeDeviceInfo(info);
+    }
+
+    device
+}
+
+/// Read the valuator numbers and values from an event. The valuator mask with a length of
+/// `valuators_len` bytes starts at `mask_start`, and it's followed by the values.
+fn read_valuators(data: &[u8], mask_start: usize, valuators_len: usize) -> Vec<(u16, f64)> {
+    let mask = match data.get(mask_start..mask_start + valuators_len) {
+        Some(mask) => mask,
+        None => return Vec::new(),
+    };
+
+    // The values are stored as 32.32 fixed point numbers, in the order of the bits in the mask
+    let mut values_offset = mask_start + valuators_len;
+    let mut valuators = Vec::new();
+    for number in 0..mask.len() * 8 {
+        if mask[number / 8] & (1 << (number % 8)) == 0 {
+            continue;
+        }
+        if data.len() < values_offset + 8 {
+            break;
+        }
+
+        let integral = read_u32(data, values_offset) as i32 as f64;
+        let fraction = read_u32(data, values_offset + 4) as f64 / (1u64 << 32) as f64;
+        valuators.push((number as u16, integral + fraction));
+        values_offset += 8;
+    }
+
+    valuators
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_ne_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// Read a 16.16 fixed point number.
+fn read_fp1616(data: &[u8], offset: usize) -> f64 {
+    read_u32(data, offset) as i32 as f64 / 65536.0
+}
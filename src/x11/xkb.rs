@@ -0,0 +1,83 @@
+//! The parts of the libxkbcommon and libxkbcommon-x11 APIs we use.
+
+#![allow(non_camel_case_types)]
+
+use std::os::raw::{c_char, c_int};
+
+use xcb::ffi::xcb_connection_t;
+
+pub enum xkb_context {}
+pub enum xkb_keymap {}
+pub enum xkb_state {}
+pub enum xkb_compose_table {}
+pub enum xkb_compose_state {}
+
+pub type xkb_keycode_t = u32;
+pub type xkb_keysym_t = u32;
+pub type xkb_mod_mask_t = u32;
+pub type xkb_layout_index_t = u32;
+
+pub const XKB_CONTEXT_NO_FLAGS: c_int = 0;
+pub const XKB_KEYMAP_COMPILE_NO_FLAGS: c_int = 0;
+pub const XKB_X11_SETUP_XKB_EXTENSION_NO_FLAGS: c_int = 0;
+pub const XKB_COMPOSE_COMPILE_NO_FLAGS: c_int = 0;
+pub const XKB_COMPOSE_STATE_NO_FLAGS: c_int = 0;
+
+pub const XKB_COMPOSE_FEED_ACCEPTED: c_int = 1;
+
+pub const XKB_COMPOSE_COMPOSING: c_int = 1;
+pub const XKB_COMPOSE_COMPOSED: c_int = 2;
+pub const XKB_COMPOSE_CANCELLED: c_int = 3;
+
+pub const XKB_X11_MIN_MAJOR_XKB_VERSION: u16 = 1;
+pub const XKB_X11_MIN_MINOR_XKB_VERSION: u16 = 0;
+
+#[link(name = "xkbcommon")]
+extern "C" {
+    pub fn xkb_context_new(flags: c_int) -> *mut xkb_context;
+    pub fn xkb_context_unref(context: *mut xkb_context);
+
+    pub fn xkb_keymap_unref(keymap: *mut xkb_keymap);
+
+    pub fn xkb_state_unref(state: *mut xkb_state);
+    pub fn xkb_state_update_mask(
+        state: *mut xkb_state, depressed_mods: xkb_mod_mask_t, latched_mods: xkb_mod_mask_t,
+        locked_mods: xkb_mod_mask_t, depressed_layout: xkb_layout_index_t,
+        latched_layout: xkb_layout_index_t, locked_layout: xkb_layout_index_t,
+    ) -> c_int;
+    pub fn xkb_state_key_get_one_sym(state: *mut xkb_state, key: xkb_keycode_t) -> xkb_keysym_t;
+
+    pub fn xkb_keysym_to_utf8(keysym: xkb_keysym_t, buffer: *mut c_char, size: usize) -> c_int;
+
+    pub fn xkb_compose_table_new_from_locale(
+        context: *mut xkb_context, locale: *const c_char, flags: c_int,
+    ) -> *mut xkb_compose_table;
+    pub fn xkb_compose_table_unref(table: *mut xkb_compose_table);
+
+    pub fn xkb_compose_state_new(
+        table: *mut xkb_compose_table, flags: c_int,
+    ) -> *mut xkb_compose_state;
+    pub fn xkb_compose_state_unref(state: *mut xkb_compose_state);
+    pub fn xkb_compose_state_reset(state: *mut xkb_compose_state);
+    pub fn xkb_compose_state_feed(state: *mut xkb_compose_state, keysym: xkb_keysym_t) -> c_int;
+    pub fn xkb_compose_state_get_status(state: *mut xkb_compose_state) -> c_int;
+    pub fn xkb_compose_state_get_utf8(
+        state: *mut xkb_compose_state, buffer: *mut c_char, size: usize,
+    ) -> c_int;
+}
+
+#[link(name = "xkbcommon-x11")]
+extern "C" {
+    pub fn xkb_x11_setup_xkb_extension(
+        connection: *mut xcb_connection_t, major_xkb_version: u16, minor_xkb_version: u16,
+        flags: c_int, major_xkb_version_out: *mut u16, minor_xkb_version_out: *mut u16,
+        base_event_out: *mut u8, base_error_out: *mut u8,
+    ) -> c_int;
+    pub fn xkb_x11_get_core_keyboard_device_id(connection: *mut xcb_connection_t) -> i32;
+    pub fn xkb_x11_keymap_new_from_device(
+        context: *mut xkb_context, connection: *mut xcb_connection_t, device_id: i32, flags: c_int,
+    ) -> *mut xkb_keymap;
+    pub fn xkb_x11_state_new_from_device(
+        keymap: *mut xkb_keymap, connection: *mut xcb_connection_t, device_id: i32,
+    ) -> *mut xkb_state;
+}
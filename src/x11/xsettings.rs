@@ -0,0 +1,224 @@
+//! A parser for the XSETTINGS protocol
+//!
+//! The XSETTINGS manager (usually the desktop environment's settings daemon) owns the
+//! `_XSETTINGS_S<screen>` selection and publishes the settings in the `_XSETTINGS_SETTINGS`
+//! property on the selection owner's window. See
+//! <https://specifications.freedesktop.org/xsettings-spec/xsettings-latest.html> for the format.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum XSetting {
+    Integer(i32),
+    String(String),
+    /// Red, green, blue and alpha
+    Color(u16, u16, u16, u16),
+}
+
+/// Parse the contents of the `_XSETTINGS_SETTINGS` property. Returns `None` if the data is
+/// malformed.
+pub(super) fn parse_xsettings(data: &[u8]) -> Option<HashMap<String, XSetting>> {
+    let mut reader = Reader { data, pos: 0, big_endian: false };
+
+    reader.big_endian = match reader.u8()? {
+        0 => false,
+        1 => true,
+        _ => return None,
+    };
+    reader.skip(3)?;
+    let _serial = reader.u32()?;
+    let n_settings = reader.u32()?;
+
+    let mut settings = HashMap::new();
+    for _ in 0..n_settings {
+        let setting_type = reader.u8()?;
+        reader.skip(1)?;
+        let name_len = reader.u16()? as usize;
+        let name = String::from_utf8_lossy(reader.bytes(name_len)?).into_owned();
+        reader.skip(padding(name_len))?;
+        let _last_change_serial = reader.u32()?;
+
+        let value = match setting_type {
+            0 => XSetting::Integer(reader.u32()? as i32),
+            1 => {
+                let len = reader.u32()? as usize;
+                let value = String::from_utf8_lossy(reader.bytes(len)?).into_owned();
+                reader.skip(padding(len))?;
+
+                XSetting::String(value)
+            }
+            2 => {
+                // The channels are stored in red, blue, green, alpha order
+                let red = reader.u16()?;
+                let blue = reader.u16()?;
+                let green = reader.u16()?;
+                let alpha = reader.u16()?;
+
+                XSetting::Color(red, green, blue, alpha)
+            }
+            _ => return None,
+        };
+
+        settings.insert(name, value);
+    }
+
+    Some(settings)
+}
+
+/// The number of padding bytes needed to align `len` to four bytes.
+fn padding(len: usize) -> usize {
+    (4 - (len % 4)) % 4
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+
+        Some(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.bytes(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|bytes| bytes[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.bytes(2)?.try_into().ok()?;
+
+        Some(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let bytes = self.bytes(4)?.try_into().ok()?;
+
+        Some(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds an `_XSETTINGS_SETTINGS` property in the given byte order.
+    struct Builder {
+        data: Vec<u8>,
+        big_endian: bool,
+    }
+
+    impl Builder {
+        fn new(big_endian: bool, n_settings: u32) -> Self {
+            let mut builder = Builder { data: vec![big_endian as u8, 0, 0, 0], big_endian };
+            builder.u32(7);
+            builder.u32(n_settings);
+
+            builder
+        }
+
+        fn u16(&mut self, value: u16) {
+            let bytes = if self.big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+            self.data.extend_from_slice(&bytes);
+        }
+
+        fn u32(&mut self, value: u32) {
+            let bytes = if self.big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+            self.data.extend_from_slice(&bytes);
+        }
+
+        fn padded(&mut self, bytes: &[u8]) {
+            self.data.extend_from_slice(bytes);
+            self.data.resize(self.data.len() + padding(bytes.len()), 0);
+        }
+
+        fn header(&mut self, setting_type: u8, name: &str) {
+            self.data.extend_from_slice(&[setting_type, 0]);
+            self.u16(name.len() as u16);
+            self.padded(name.as_bytes());
+            self.u32(0);
+        }
+
+        fn integer(mut self, name: &str, value: i32) -> Self {
+            self.header(0, name);
+            self.u32(value as u32);
+
+            self
+        }
+
+        fn string(mut self, name: &str, value: &str) -> Self {
+            self.header(1, name);
+            self.u32(value.len() as u32);
+            self.padded(value.as_bytes());
+
+            self
+        }
+
+        fn color(mut self, name: &str, red: u16, green: u16, blue: u16, alpha: u16) -> Self {
+            self.header(2, name);
+            for channel in [red, blue, green, alpha] {
+                self.u16(channel);
+            }
+
+            self
+        }
+    }
+
+    fn sample(big_endian: bool) -> Vec<u8> {
+        Builder::new(big_endian, 3)
+            .integer("Xft/DPI", 147456)
+            .string("Gtk/CursorThemeName", "Adwaita")
+            .color("Net/Color", 1, 2, 3, 4)
+            .data
+    }
+
+    #[test]
+    fn parses_both_byte_orders() {
+        for big_endian in [false, true] {
+            let settings = parse_xsettings(&sample(big_endian)).unwrap();
+
+            assert_eq!(settings.len(), 3);
+            assert_eq!(settings["Xft/DPI"], XSetting::Integer(147456));
+            assert_eq!(settings["Gtk/CursorThemeName"], XSetting::String("Adwaita".into()));
+            assert_eq!(settings["Net/Color"], XSetting::Color(1, 2, 3, 4));
+        }
+    }
+
+    #[test]
+    fn skips_padding() {
+        // Names and values whose lengths are already a multiple of four have no padding
+        let data = Builder::new(false, 2).string("Abcd", "Wxyz").integer("A", -1).data;
+        let settings = parse_xsettings(&data).unwrap();
+
+        assert_eq!(settings["Abcd"], XSetting::String("Wxyz".into()));
+        assert_eq!(settings["A"], XSetting::Integer(-1));
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let data = sample(false);
+        for len in 0..data.len() {
+            assert_eq!(parse_xsettings(&data[..len]), None, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        let mut data = sample(false);
+        data[0] = 2;
+        assert_eq!(parse_xsettings(&data), None);
+
+        // An unknown setting type
+        let mut data = Builder::new(false, 1).integer("Xft/DPI", 1).data;
+        data[12] = 3;
+        assert_eq!(parse_xsettings(&data), None);
+    }
+}